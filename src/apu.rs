@@ -0,0 +1,807 @@
+use crate::bus::Device;
+use crate::cartridge::Timing;
+
+// 本体の音源 (2A03のAPU)
+// $4000-$4013の各チャンネル、$4015の有効フラグと状態、$4017のフレームカウンタ
+// 周期の表はすべてCPUサイクルで数える
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+const NOISE_PERIODS_NTSC: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const NOISE_PERIODS_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+const DMC_RATES_NTSC: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const DMC_RATES_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+// フレームカウンタが1/4フレームと1/2フレームの処理をするサイクル 最後の値で0に戻る
+const FRAME_STEPS_NTSC: [[u32; 5]; 2] = [
+    [7457, 14913, 22371, 29829, 29830],
+    [7457, 14913, 22371, 29829, 37282],
+];
+const FRAME_STEPS_PAL: [[u32; 5]; 2] = [
+    [8313, 16627, 24939, 33252, 33253],
+    [8313, 16627, 24939, 33252, 41566],
+];
+
+// 音量のエンベロープ 矩形波とノイズが使う
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // 一定音量の時の音量、またはエンベロープの周期
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0F;
+    }
+
+    // 1/4フレームごと
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider > 0 {
+            self.divider -= 1;
+        } else {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+// 長さカウンタ 0になるとチャンネルが止まる
+#[derive(Default)]
+struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    count: u8,
+}
+
+impl LengthCounter {
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.count = LENGTH_TABLE[index as usize >> 3];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.count = 0;
+        }
+    }
+
+    // 1/2フレームごと
+    fn clock(&mut self) {
+        if !self.halt && self.count > 0 {
+            self.count -= 1;
+        }
+    }
+}
+
+// 矩形波 ($4000-$4003, $4004-$4007) MMC5の音源も同じものを使う
+#[derive(Default)]
+pub(crate) struct Pulse {
+    // 1チャンネル目はスイープで減らす量が1多い
+    first: bool,
+    // MMC5にはスイープが無く、周期による消音も無い
+    sweep: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub(crate) fn new(first: bool) -> Self {
+        Pulse {
+            first,
+            sweep: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn without_sweep() -> Self {
+        Pulse::default()
+    }
+
+    // registerは0-3
+    pub(crate) fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 if !self.sweep => {}
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0x07) as u16) << 8;
+                self.length.load(data);
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // タイマーはAPUサイクル(CPUの2サイクル)ごとに進む
+    pub(crate) fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = change + self.first as u16;
+            self.period.saturating_sub(change)
+        } else {
+            self.period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.sweep && (self.period < 8 || self.sweep_target() > 0x7FF)
+    }
+
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    // 長さカウンタが残っているか
+    pub(crate) fn active(&self) -> bool {
+        self.length.count > 0
+    }
+
+    // 1/4フレームごと
+    pub(crate) fn quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    // 1/2フレームごと
+    pub(crate) fn half_frame(&mut self) {
+        self.length.clock();
+        if self.sweep {
+            self.clock_sweep();
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub(crate) fn output(&self) -> u8 {
+        if self.length.count == 0
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+// 三角波 ($4008-$400B)
+#[derive(Default)]
+struct Triangle {
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: LengthCounter,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                // 長さカウンタを止めるフラグと線形カウンタの制御フラグは同じbit
+                self.length.halt = data & 0x80 != 0;
+                self.linear_reload_value = data & 0x7F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0x07) as u16) << 8;
+                self.length.load(data);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    // タイマーはCPUサイクルごとに進み、両方のカウンタが0でない時だけ波形が進む
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.count > 0 && self.linear_counter > 0 {
+                self.step = (self.step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // 1/4フレームごと
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.length.halt {
+            self.linear_reload = false;
+        }
+    }
+
+    // 止まっている間も最後の位置の値を出し続ける
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.step as usize]
+    }
+}
+
+// ノイズ ($400C-$400F)
+struct Noise {
+    periods: &'static [u16; 16],
+    // trueなら93stepの短い周期
+    short: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Noise {
+    fn new(periods: &'static [u16; 16]) -> Self {
+        Noise {
+            periods,
+            short: false,
+            period: periods[0],
+            timer: 0,
+            shift: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {}
+            2 => {
+                self.short = data & 0x80 != 0;
+                self.period = self.periods[(data & 0x0F) as usize];
+            }
+            _ => {
+                self.length.load(data);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length.count == 0 || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+// DPCM ($4010-$4013) サンプルはDMAでCPUのメモリから読む
+struct Dmc {
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    output: u8,
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Dmc {
+    fn new(rates: &'static [u16; 16]) -> Self {
+        Dmc {
+            rates,
+            irq_enabled: false,
+            looping: false,
+            period: rates[0],
+            timer: 0,
+            output: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = data & 0x40 != 0;
+                self.period = self.rates[(data & 0x0F) as usize];
+            }
+            1 => self.output = data & 0x7F,
+            // $C000 + A * 64、長さはL * 16 + 1
+            2 => self.sample_addr = 0xC000 | (data as u16) << 6,
+            _ => self.sample_length = (data as u16) << 4 | 1,
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn clock(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        // 1bitごとに出力を2ずつ上下させる
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.output <= 125 {
+                    self.output += 2;
+                }
+            } else if self.output >= 2 {
+                self.output -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    fn dma_request(&self) -> Option<u16> {
+        (self.buffer.is_none() && self.bytes_remaining > 0).then_some(self.current_addr)
+    }
+
+    fn dma_complete(&mut self, data: u8) {
+        self.buffer = Some(data);
+        // $FFFFの次は$8000に戻る
+        self.current_addr = self.current_addr.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+}
+
+pub struct Apu {
+    pulses: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_steps: &'static [[u32; 5]; 2],
+    // falseで4ステップ、trueで5ステップ
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    // 矩形波とノイズはAPUサイクル(CPUの2サイクル)で動く
+    odd_cycle: bool,
+}
+
+impl Apu {
+    pub fn new(timing: Timing) -> Self {
+        let pal = timing == Timing::Pal;
+        Apu {
+            pulses: [Pulse::new(true), Pulse::new(false)],
+            triangle: Triangle::default(),
+            noise: Noise::new(if pal {
+                &NOISE_PERIODS_PAL
+            } else {
+                &NOISE_PERIODS_NTSC
+            }),
+            dmc: Dmc::new(if pal { &DMC_RATES_PAL } else { &DMC_RATES_NTSC }),
+            frame_steps: if pal {
+                &FRAME_STEPS_PAL
+            } else {
+                &FRAME_STEPS_NTSC
+            },
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+        }
+    }
+
+    // フレームカウンタかDMCが割り込みを要求しているか
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        for (i, active) in [
+            self.pulses[0].active(),
+            self.pulses[1].active(),
+            self.triangle.length.count > 0,
+            self.noise.length.count > 0,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if active {
+                status |= 1 << i;
+            }
+        }
+        if self.dmc.bytes_remaining > 0 {
+            status |= 0x10;
+        }
+        if self.frame_irq {
+            status |= 0x40;
+        }
+        if self.dmc.irq {
+            status |= 0x80;
+        }
+        status
+    }
+
+    fn quarter_frame(&mut self) {
+        self.pulses[0].quarter_frame();
+        self.pulses[1].quarter_frame();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    fn half_frame(&mut self) {
+        self.pulses[0].half_frame();
+        self.pulses[1].half_frame();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let steps = self.frame_steps[self.five_step as usize];
+        let cycle = self.frame_cycle;
+        if cycle == steps[0] || cycle == steps[2] {
+            self.quarter_frame();
+        } else if cycle == steps[1] {
+            self.quarter_frame();
+            self.half_frame();
+        } else if !self.five_step && cycle == steps[3] {
+            self.quarter_frame();
+            self.half_frame();
+            if !self.irq_inhibit {
+                self.frame_irq = true;
+            }
+        } else if self.five_step && cycle == steps[4] - 1 {
+            self.quarter_frame();
+            self.half_frame();
+        }
+        if cycle >= steps[4] {
+            self.frame_cycle = 0;
+        }
+    }
+
+    // CPUの1サイクル分進める
+    fn clock(&mut self) {
+        self.triangle.clock();
+        self.noise.clock();
+        self.dmc.clock();
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            self.pulses[0].clock();
+            self.pulses[1].clock();
+        }
+        self.clock_frame_counter();
+    }
+
+    // 非線形のミキサー 0.0-1.0
+    pub fn output(&self) -> f32 {
+        let pulse = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse_out + tnd_out
+    }
+}
+
+// $4000-$4017に置く $4014はバスがOAM DMAに使い、$4016/$4017の読み込みはコントローラー
+impl Device for Apu {
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = self.peek(addr);
+        // 読むとフレームカウンタの割り込みが消える
+        if addr == 0x4015 {
+            self.frame_irq = false;
+        }
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulses[0].write(addr - 0x4000, data),
+            0x4004..=0x4007 => self.pulses[1].write(addr - 0x4004, data),
+            0x4008..=0x400B => self.triangle.write(addr - 0x4008, data),
+            0x400C..=0x400F => self.noise.write(addr - 0x400C, data),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, data),
+            0x4015 => {
+                self.pulses[0].set_enabled(data & 0x01 != 0);
+                self.pulses[1].set_enabled(data & 0x02 != 0);
+                self.triangle.length.set_enabled(data & 0x04 != 0);
+                self.noise.length.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+            }
+            0x4017 => {
+                self.five_step = data & 0x80 != 0;
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                // 5ステップにするとすぐに1/4と1/2フレームの処理が走る
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4015 => self.status(),
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.clock();
+        }
+    }
+
+    fn dma_request(&mut self) -> Option<u16> {
+        self.dmc.dma_request()
+    }
+
+    fn dma_complete(&mut self, data: u8) {
+        self.dmc.dma_complete(data);
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.output()
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_and_status() {
+        let mut apu = Apu::new(Timing::Ntsc);
+        // 無効なチャンネルには長さが入らない
+        apu.write(0x4003, 0x08);
+        assert_eq!(apu.peek(0x4015), 0x00);
+
+        apu.write(0x4015, 0x0F);
+        apu.write(0x4003, 0x08); // 長さ254
+        apu.write(0x400B, 0x18); // 長さ2
+        assert_eq!(apu.peek(0x4015), 0x05);
+        // 1/2フレーム2回で三角波が止まる
+        apu.tick(255);
+        for _ in 0..14913 * 2 / 255 {
+            apu.tick(255);
+        }
+        assert_eq!(apu.peek(0x4015) & 0x0F, 0x01);
+        // 無効にすると0になる
+        apu.write(0x4015, 0x00);
+        assert_eq!(apu.peek(0x4015) & 0x0F, 0x00);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = Apu::new(Timing::Ntsc);
+        for _ in 0..=29830 / 255 {
+            apu.tick(255);
+        }
+        assert!(apu.irq());
+        assert_eq!(apu.read(0x4015) & 0x40, 0x40);
+        // 読むと消える
+        assert!(!apu.irq());
+
+        // 5ステップでは割り込みは起きない
+        apu.write(0x4017, 0x80);
+        for _ in 0..=37282 / 255 {
+            apu.tick(255);
+        }
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_pulse_output() {
+        let mut apu = Apu::new(Timing::Ntsc);
+        // 三角波は止まっていても最初の位置の15を出す
+        let silent = apu.output();
+        apu.write(0x4015, 0x01);
+        // デューティ50%・一定音量15・周期$100
+        apu.write(0x4000, 0xBF);
+        apu.write(0x4002, 0x00);
+        apu.write(0x4003, 0x09);
+        let mut levels = Vec::new();
+        for _ in 0..16 {
+            for _ in 0..0x101 {
+                apu.tick(2);
+            }
+            levels.push(apu.pulses[0].output());
+        }
+        assert!(levels.contains(&15));
+        assert!(levels.contains(&0));
+        assert!(apu.output() - silent <= 95.88 / (8128.0 / 15.0 + 100.0));
+
+        // 周期が8未満なら鳴らない
+        apu.write(0x4002, 0x07);
+        apu.write(0x4003, 0x08);
+        for _ in 0..16 {
+            apu.tick(2);
+            assert_eq!(apu.pulses[0].output(), 0);
+        }
+    }
+
+    #[test]
+    fn test_dmc_reads_sample() {
+        let mut apu = Apu::new(Timing::Ntsc);
+        apu.write(0x4012, 0x01); // $C040
+        apu.write(0x4013, 0x01); // 17byte
+        apu.write(0x4015, 0x10);
+        assert_eq!(apu.peek(0x4015), 0x10);
+        assert_eq!(apu.dma_request(), Some(0xC040));
+        apu.dma_complete(0xFF);
+        // バッファが埋まっている間は要求しない
+        assert_eq!(apu.dma_request(), None);
+        // 8bit出し終えるとバッファが空いて次を読む
+        for _ in 0..8 * 428 / 255 + 1 {
+            apu.tick(255);
+        }
+        assert_eq!(apu.dma_request(), Some(0xC041));
+        // 全部読み終えて止まる
+        for _ in 0..16 {
+            apu.dma_complete(0x00);
+        }
+        assert_eq!(apu.peek(0x4015), 0x00);
+    }
+}
@@ -0,0 +1,396 @@
+use crate::hash;
+use crate::inflate::{self, InflateError};
+use std::fmt;
+
+// ZIPとgzipに入ったROMを取り出す
+// 中身はDEFLATEか無圧縮のものだけ 暗号化やZIP64には対応しない
+
+const ZIP_LOCAL: &[u8; 4] = b"PK\x03\x04";
+const ZIP_CENTRAL: &[u8; 4] = b"PK\x01\x02";
+const ZIP_END: &[u8; 4] = b"PK\x05\x06";
+const ZIP_END_SIZE: usize = 22;
+const GZIP_MAGIC: &[u8; 2] = b"\x1F\x8B";
+
+// gzipを展開した大きさの上限 ISIZEは4GBで折り返すので当てにしない
+// 大きなマルチカートでも収まる
+const MAX_OUTPUT_SIZE: usize = 64 * 1024 * 1024;
+
+// 名前を指定しない時に選ぶ拡張子
+pub const ROM_EXTENSIONS: &[&str] = &["nes", "unf", "unif", "fds", "qd", "nsf", "nsfe"];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArchiveError {
+    // ヘッダやディレクトリが途中で終わっている
+    Truncated,
+    // 対応していない圧縮方式など
+    Unsupported(&'static str),
+    Inflate(InflateError),
+    // 指定した名前のファイルが無い
+    EntryNotFound(String),
+    // ROMらしい拡張子のファイルが無い
+    NoRom,
+    // 展開した中身がディレクトリに書かれたCRC32と合わない
+    CrcMismatch {
+        name: String,
+        expected: u32,
+        actual: u32,
+    },
+    // 展開した大きさが書かれたものと合わない
+    SizeMismatch {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Truncated => write!(f, "archive is truncated"),
+            ArchiveError::Unsupported(feature) => write!(f, "{} is not supported", feature),
+            ArchiveError::Inflate(err) => write!(f, "{}", err),
+            ArchiveError::EntryNotFound(name) => write!(f, "{} is not in the archive", name),
+            ArchiveError::NoRom => write!(f, "archive contains no ROM"),
+            ArchiveError::CrcMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: CRC32 is {:08x}, archive says {:08x}",
+                name, actual, expected
+            ),
+            ArchiveError::SizeMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: size is {} bytes, archive says {}",
+                name, actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<InflateError> for ArchiveError {
+    fn from(err: InflateError) -> Self {
+        ArchiveError::Inflate(err)
+    }
+}
+
+pub fn is_zip(raw: &[u8]) -> bool {
+    raw.starts_with(ZIP_LOCAL) || raw.starts_with(ZIP_END)
+}
+
+pub fn is_gzip(raw: &[u8]) -> bool {
+    raw.starts_with(GZIP_MAGIC)
+}
+
+pub fn is_archive(raw: &[u8]) -> bool {
+    is_zip(raw) || is_gzip(raw)
+}
+
+fn u16_at(raw: &[u8], pos: usize) -> Result<u16, ArchiveError> {
+    raw.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(ArchiveError::Truncated)
+}
+
+fn u32_at(raw: &[u8], pos: usize) -> Result<u32, ArchiveError> {
+    raw.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(ArchiveError::Truncated)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(_, extension)| {
+        ROM_EXTENSIONS
+            .iter()
+            .any(|rom| rom.eq_ignore_ascii_case(extension))
+    })
+}
+
+// 中身をdata、期待する大きさとCRC32と照らし合わせる
+fn check(name: &str, data: Vec<u8>, size: usize, crc: u32) -> Result<Vec<u8>, ArchiveError> {
+    if data.len() != size {
+        return Err(ArchiveError::SizeMismatch {
+            name: name.to_string(),
+            expected: size,
+            actual: data.len(),
+        });
+    }
+    let actual = hash::crc32(&data);
+    if actual != crc {
+        return Err(ArchiveError::CrcMismatch {
+            name: name.to_string(),
+            expected: crc,
+            actual,
+        });
+    }
+    Ok(data)
+}
+
+// ZIPのセントラルディレクトリの1項目
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    pub name: String,
+    pub method: u16,
+    pub flags: u16,
+    pub crc32: u32,
+    pub compressed_size: usize,
+    pub size: usize,
+    local_offset: usize,
+}
+
+// 末尾のディレクトリ終端からセントラルディレクトリを読む
+pub fn zip_entries(raw: &[u8]) -> Result<Vec<ZipEntry>, ArchiveError> {
+    // 終端の後ろには最大65535byteのコメントが付く
+    let search_start = raw.len().saturating_sub(ZIP_END_SIZE + 0xFFFF);
+    let end = (search_start..=raw.len().saturating_sub(ZIP_END_SIZE))
+        .rev()
+        .find(|&pos| raw[pos..].starts_with(ZIP_END))
+        .ok_or(ArchiveError::Truncated)?;
+    let count = u16_at(raw, end + 10)? as usize;
+    let mut pos = u32_at(raw, end + 16)? as usize;
+    if count == 0xFFFF || pos == 0xFFFF_FFFF {
+        return Err(ArchiveError::Unsupported("ZIP64 archive"));
+    }
+
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if !raw[pos.min(raw.len())..].starts_with(ZIP_CENTRAL) {
+            return Err(ArchiveError::Truncated);
+        }
+        let name_len = u16_at(raw, pos + 28)? as usize;
+        let extra_len = u16_at(raw, pos + 30)? as usize;
+        let comment_len = u16_at(raw, pos + 32)? as usize;
+        let name = raw
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or(ArchiveError::Truncated)?;
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).to_string(),
+            flags: u16_at(raw, pos + 8)?,
+            method: u16_at(raw, pos + 10)?,
+            crc32: u32_at(raw, pos + 16)?,
+            compressed_size: u32_at(raw, pos + 20)? as usize,
+            size: u32_at(raw, pos + 24)? as usize,
+            local_offset: u32_at(raw, pos + 42)? as usize,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+// ZIPの1項目を展開する
+pub fn zip_extract(raw: &[u8], entry: &ZipEntry) -> Result<Vec<u8>, ArchiveError> {
+    if entry.flags & 0x01 != 0 {
+        return Err(ArchiveError::Unsupported("encrypted ZIP entry"));
+    }
+    let pos = entry.local_offset;
+    if !raw[pos.min(raw.len())..].starts_with(ZIP_LOCAL) {
+        return Err(ArchiveError::Truncated);
+    }
+    // 大きさなどはローカルヘッダでは省略されうるのでディレクトリの値を使う
+    let start = pos + 30 + u16_at(raw, pos + 26)? as usize + u16_at(raw, pos + 28)? as usize;
+    let data = raw
+        .get(start..start + entry.compressed_size)
+        .ok_or(ArchiveError::Truncated)?;
+    let data = match entry.method {
+        0 => data.to_vec(),
+        // ディレクトリに書かれた大きさを超えたら展開をやめる
+        8 => inflate::inflate(data, entry.size)?,
+        _ => return Err(ArchiveError::Unsupported("ZIP compression method")),
+    };
+    check(&entry.name, data, entry.size, entry.crc32)
+}
+
+// gzipを展開して、ヘッダにあれば元の名前も返す 複数のメンバーには対応しない
+pub fn gunzip(raw: &[u8]) -> Result<(Option<String>, Vec<u8>), ArchiveError> {
+    if !is_gzip(raw) {
+        return Err(ArchiveError::Unsupported("file that is not gzip"));
+    }
+    if raw.len() < 18 {
+        return Err(ArchiveError::Truncated);
+    }
+    if raw[2] != 8 {
+        return Err(ArchiveError::Unsupported("gzip compression method"));
+    }
+    let flags = raw[3];
+    let mut pos = 10;
+    // FEXTRA
+    if flags & 0x04 != 0 {
+        pos += 2 + u16_at(raw, pos)? as usize;
+    }
+    // FNAMEとFCOMMENTは0で終わる
+    let mut strings = [None, None];
+    for (i, flag) in [0x08, 0x10].into_iter().enumerate() {
+        if flags & flag != 0 {
+            let len = raw
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or(ArchiveError::Truncated)?;
+            strings[i] = Some(String::from_utf8_lossy(&raw[pos..pos + len]).to_string());
+            pos += len + 1;
+        }
+    }
+    // FHCRC
+    if flags & 0x02 != 0 {
+        pos += 2;
+    }
+    let trailer = raw.len() - 8;
+    if pos > trailer {
+        return Err(ArchiveError::Truncated);
+    }
+    let [name, _] = strings;
+    let data = inflate::inflate(&raw[pos..trailer], MAX_OUTPUT_SIZE)?;
+    let crc = u32_at(raw, trailer)?;
+    // ISIZEは4GBで折り返すので下位32bitだけ比べる
+    let size = u32_at(raw, trailer + 4)? as usize;
+    let label = name.as_deref().unwrap_or("gzip data");
+    let expected = (data.len() & !0xFFFF_FFFF) | size;
+    let data = check(label, data, expected, crc)?;
+    Ok((name, data))
+}
+
+// ZIPかgzipからROMを取り出す entryを指定しなければROMらしい最初のファイル
+pub fn extract(raw: &[u8], entry: Option<&str>) -> Result<(String, Vec<u8>), ArchiveError> {
+    if is_gzip(raw) {
+        let (name, data) = gunzip(raw)?;
+        return Ok((name.unwrap_or_default(), data));
+    }
+    let entries = zip_entries(raw)?;
+    let found = match entry {
+        Some(name) => entries
+            .iter()
+            .find(|e| e.name == name || e.name.rsplit('/').next() == Some(name))
+            .ok_or_else(|| ArchiveError::EntryNotFound(name.to_string()))?,
+        None => entries
+            .iter()
+            .find(|e| is_rom_name(&e.name))
+            .ok_or(ArchiveError::NoRom)?,
+    };
+    Ok((found.name.clone(), zip_extract(raw, found)?))
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::inflate::test::create_bomb;
+
+    // 無圧縮で格納したZIPを組み立てる
+    fn create_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        let mut central = Vec::new();
+        for (name, data) in files {
+            let crc = hash::crc32(data).to_le_bytes();
+            let size = (data.len() as u32).to_le_bytes();
+            let offset = (raw.len() as u32).to_le_bytes();
+            raw.extend(ZIP_LOCAL);
+            raw.extend([20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            raw.extend(crc);
+            raw.extend(size);
+            raw.extend(size);
+            raw.extend((name.len() as u16).to_le_bytes());
+            raw.extend([0, 0]);
+            raw.extend(name.as_bytes());
+            raw.extend(*data);
+
+            central.extend(ZIP_CENTRAL);
+            central.extend([20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            central.extend(crc);
+            central.extend(size);
+            central.extend(size);
+            central.extend((name.len() as u16).to_le_bytes());
+            central.extend([0; 12]);
+            central.extend(offset);
+            central.extend(name.as_bytes());
+        }
+        let offset = (raw.len() as u32).to_le_bytes();
+        let len = (central.len() as u32).to_le_bytes();
+        raw.extend(central);
+        raw.extend(ZIP_END);
+        raw.extend([0, 0, 0, 0]);
+        raw.extend((files.len() as u16).to_le_bytes());
+        raw.extend((files.len() as u16).to_le_bytes());
+        raw.extend(len);
+        raw.extend(offset);
+        raw.extend([0, 0]);
+        raw
+    }
+
+    #[test]
+    fn test_zip() {
+        let raw = create_zip(&[
+            ("readme.txt", b"hello"),
+            ("roms/game.NES", b"NES\x1Adata"),
+            ("other.nes", b"other"),
+        ]);
+        assert!(is_zip(&raw));
+        assert_eq!(zip_entries(&raw).unwrap().len(), 3);
+        // 名前を指定しなければROMらしい最初のファイル
+        let (name, data) = extract(&raw, None).unwrap();
+        assert_eq!(name, "roms/game.NES");
+        assert_eq!(data, b"NES\x1Adata");
+        // ディレクトリを省いた名前でも選べる
+        assert_eq!(extract(&raw, Some("other.nes")).unwrap().1, b"other");
+        assert_eq!(
+            extract(&raw, Some("missing.nes")),
+            Err(ArchiveError::EntryNotFound("missing.nes".to_string()))
+        );
+        let raw = create_zip(&[("readme.txt", b"hello")]);
+        assert_eq!(extract(&raw, None), Err(ArchiveError::NoRom));
+    }
+
+    #[test]
+    fn test_zip_crc_mismatch() {
+        let mut raw = create_zip(&[("game.nes", b"NES\x1Adata")]);
+        // 中身を壊す
+        raw[30 + 8] ^= 0xFF;
+        assert!(matches!(
+            extract(&raw, None),
+            Err(ArchiveError::CrcMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_zip_bomb() {
+        // 書かれた大きさは16byteだが、展開すると77KBを超える
+        let bomb = create_bomb(300);
+        let mut raw = create_zip(&[("game.nes", &bomb)]);
+        raw[8] = 8;
+        let central = raw.len() - ZIP_END_SIZE - (46 + 8);
+        raw[central + 10] = 8;
+        raw[central + 24..central + 28].copy_from_slice(&16u32.to_le_bytes());
+        assert_eq!(
+            extract(&raw, None),
+            Err(ArchiveError::Inflate(InflateError::Corrupt(
+                "output is larger than expected"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_gzip() {
+        // 名前付き、中身は固定ハフマンの"abcabcabcabc"
+        let mut raw = vec![0x1F, 0x8B, 8, 0x08, 0, 0, 0, 0, 0, 3];
+        raw.extend(b"abc.nes\0");
+        raw.extend(hash::from_hex("4b4c4a4e842100").unwrap());
+        raw.extend(hash::crc32(b"abcabcabcabc").to_le_bytes());
+        raw.extend(12u32.to_le_bytes());
+        assert!(is_archive(&raw));
+        let (name, data) = extract(&raw, None).unwrap();
+        assert_eq!(name, "abc.nes");
+        assert_eq!(data, b"abcabcabcabc");
+
+        let len = raw.len();
+        raw[len - 8] ^= 0x01;
+        assert!(matches!(
+            gunzip(&raw),
+            Err(ArchiveError::CrcMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,652 @@
+use crate::cartridge::Cartridge;
+use crate::hooks::{Access, HookId, Hooks};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+// アドレス範囲にマップされる周辺機器が実装するトレイト
+// アドレスはオフセットではなくCPUから見た絶対アドレスで渡される
+pub trait Device {
+    // CPUからの読み込み（レジスタのクリアなど副作用があってよい）
+    fn read(&mut self, addr: u16) -> u8;
+    // CPUからの書き込み
+    fn write(&mut self, addr: u16, data: u8);
+    // 副作用なしで読み込みの結果だけを返す（デバッガ用）
+    fn peek(&self, addr: u16) -> u8;
+    // CPUサイクルが進むたびに呼ばれる
+    fn tick(&mut self, _cycles: u8) {}
+    // DMCのようにCPUを止めてメモリを読ませたい時に読み出すアドレスを返す
+    fn dma_request(&mut self) -> Option<u16> {
+        None
+    }
+    // dma_requestで要求したアドレスの値を受け取る
+    fn dma_complete(&mut self, _data: u8) {}
+    // 音を出すデバイスの出力 (0.0-1.0)
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+}
+
+// デバイス登録時のエラー
+#[derive(Debug, PartialEq, Eq)]
+pub enum MapError {
+    // 開始アドレスが終了アドレスより大きい
+    EmptyRange(RangeInclusive<u16>),
+    // 既に登録済みの範囲と重なっている
+    Overlap {
+        range: RangeInclusive<u16>,
+        existing: RangeInclusive<u16>,
+    },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::EmptyRange(range) => {
+                write!(
+                    f,
+                    "empty range ${:04X}..=${:04X}",
+                    range.start(),
+                    range.end()
+                )
+            }
+            MapError::Overlap { range, existing } => write!(
+                f,
+                "range ${:04X}..=${:04X} overlaps ${:04X}..=${:04X}",
+                range.start(),
+                range.end(),
+                existing.start(),
+                existing.end()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+struct Mapping {
+    range: RangeInclusive<u16>,
+    device: Box<dyn Device>,
+}
+
+pub struct Bus {
+    // どのデバイスにもマップされていない番地はこのフラットなメモリに落ちる
+    memory: [u8; 0x10000],
+    devices: Vec<Mapping>,
+    pub cycles: u64,
+    // スプライトメモリ（$2003/$2004を担当するデバイスが無い場合に使う）
+    oam_addr: u8,
+    oam_data: [u8; 256],
+    // $4014に書き込まれたページ 命令の完了後にDMAを行う
+    oam_dma_page: Option<u8>,
+    oam_dma_active: bool,
+    // メモリアクセスを監視するフック 一つも無い時はNone
+    hooks: Option<Box<Hooks>>,
+    // 次に登録するフックの番号 フックが全部外れても戻さない
+    next_hook_id: u32,
+    // $4020-$FFFFに挿さるカートリッジ 同じ番地に登録したデバイスの方が優先される
+    cartridge: Option<Cartridge>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            memory: [0x00; 0x10000],
+            devices: Vec::new(),
+            cycles: 0,
+            oam_addr: 0,
+            oam_data: [0x00; 256],
+            oam_dma_page: None,
+            oam_dma_active: false,
+            hooks: None,
+            next_hook_id: 0,
+            cartridge: None,
+        }
+    }
+
+    // アドレス範囲にデバイスを登録する 既存の範囲と重なる場合はエラー
+    pub fn map(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Box<dyn Device>,
+    ) -> Result<(), MapError> {
+        if range.start() > range.end() {
+            return Err(MapError::EmptyRange(range));
+        }
+        if let Some(existing) = self
+            .devices
+            .iter()
+            .find(|m| m.range.start() <= range.end() && range.start() <= m.range.end())
+        {
+            return Err(MapError::Overlap {
+                range,
+                existing: existing.range.clone(),
+            });
+        }
+        self.devices.push(Mapping { range, device });
+        Ok(())
+    }
+
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    pub fn eject_cartridge(&mut self) -> Option<Cartridge> {
+        self.cartridge.take()
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    // addrを含む範囲のデバイスを取り外して返す
+    pub fn unmap(&mut self, addr: u16) -> Option<Box<dyn Device>> {
+        let index = self.devices.iter().position(|m| m.range.contains(&addr))?;
+        Some(self.devices.remove(index).device)
+    }
+
+    fn device_mut(&mut self, addr: u16) -> Option<&mut Box<dyn Device>> {
+        self.devices
+            .iter_mut()
+            .find(|m| m.range.contains(&addr))
+            .map(|m| &mut m.device)
+    }
+
+    fn device(&self, addr: u16) -> Option<&dyn Device> {
+        self.devices
+            .iter()
+            .find(|m| m.range.contains(&addr))
+            .map(|m| m.device.as_ref())
+    }
+
+    // range内のアクセスのたびにcallback(アドレス, 値, サイクル数)を呼ぶフックを登録する
+    pub fn add_hook(
+        &mut self,
+        access: Access,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(u16, u8, u64) + 'static,
+    ) -> HookId {
+        let id = HookId(self.next_hook_id);
+        self.next_hook_id += 1;
+        self.hooks
+            .get_or_insert_with(Box::default)
+            .add(id, access, range, Box::new(callback))
+    }
+
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let Some(hooks) = self.hooks.as_mut() else {
+            return false;
+        };
+        let removed = hooks.remove(id);
+        if hooks.is_empty() {
+            self.hooks = None;
+        }
+        removed
+    }
+
+    // CPUが命令を読み込んだことを実行フックに伝える
+    pub fn notify_execute(&mut self, addr: u16, opcode: u8) {
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.call(Access::Execute, addr, opcode, self.cycles);
+        }
+    }
+
+    pub fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = match self.device_mut(addr) {
+            Some(device) => device.read(addr),
+            None => self.read_internal(addr),
+        };
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.call(Access::Read, addr, data, self.cycles);
+        }
+        data
+    }
+
+    pub fn mem_write(&mut self, addr: u16, data: u8) {
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.call(Access::Write, addr, data, self.cycles);
+        }
+        // OAM DMAはバス自身が行うのでデバイスより優先する
+        if addr == 0x4014 {
+            self.oam_dma_page = Some(data);
+            return;
+        }
+        // MMC5のようにPPUレジスタへの書き込みを見ているマッパーがある
+        if let (0x2000..=0x3FFF, Some(cartridge)) = (addr, self.cartridge.as_mut()) {
+            cartridge.ppu_register_write(0x2000 | (addr & 7), data);
+        }
+        // Vs. Systemは$4016の上位bitでバンクを切り替える
+        if let (0x4016, Some(cartridge)) = (addr, self.cartridge.as_mut()) {
+            cartridge.controller_write(data);
+        }
+        match self.device_mut(addr) {
+            Some(device) => device.write(addr, data),
+            None => self.write_internal(addr, data),
+        }
+    }
+
+    // プログラムをaddrから裏のメモリへ直接置く フックやデバイス、カートリッジには書き込まない
+    // カートリッジが挿さっていれば$4020以降はカートリッジの方が見える
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) {
+        let start = addr as usize;
+        let end = (start + bytes.len()).min(self.memory.len());
+        self.memory[start..end].copy_from_slice(&bytes[..end - start]);
+    }
+
+    // 副作用なしの読み込み
+    pub fn peek(&self, addr: u16) -> u8 {
+        match self.device(addr) {
+            Some(device) => device.peek(addr),
+            None => self.peek_internal(addr),
+        }
+    }
+
+    // 2byteの副作用なしの読み込み
+    pub fn peek_u16(&self, pos: u16) -> u16 {
+        let lo = self.peek(pos) as u16;
+        let hi = self.peek(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    // デバイスの無い番地の読み込み
+    fn read_internal(&mut self, addr: u16) -> u8 {
+        match (addr, self.cartridge.as_mut()) {
+            (0x4020..=0xFFFF, Some(cartridge)) => cartridge.read(addr),
+            _ => self.peek_internal(addr),
+        }
+    }
+
+    // デバイスの無い番地の副作用なしの読み込み $2004はスプライトメモリ
+    fn peek_internal(&self, addr: u16) -> u8 {
+        match (addr, self.cartridge.as_ref()) {
+            (0x2000..=0x3FFF, _) if addr & 0x0007 == 0x0004 => {
+                self.oam_data[self.oam_addr as usize]
+            }
+            (0x4020..=0xFFFF, Some(cartridge)) => cartridge.peek(addr),
+            _ => self.memory[addr as usize],
+        }
+    }
+
+    // デバイスの無い番地の書き込み $2003/$2004はスプライトメモリ
+    fn write_internal(&mut self, addr: u16, data: u8) {
+        if let (0x4020..=0xFFFF, Some(cartridge)) = (addr, self.cartridge.as_mut()) {
+            cartridge.write(addr, data);
+            return;
+        }
+        match addr {
+            0x2000..=0x3FFF if addr & 0x0007 == 0x0003 => self.oam_addr = data,
+            0x2000..=0x3FFF if addr & 0x0007 == 0x0004 => {
+                self.oam_data[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            _ => self.memory[addr as usize] = data,
+        }
+    }
+
+    pub fn oam(&self) -> &[u8; 256] {
+        &self.oam_data
+    }
+
+    // CPUが消費したサイクルを数え、全デバイスに伝える
+    // 命令中に$4014が書かれていれば、ここでOAM DMAの分だけCPUを止める
+    pub fn tick(&mut self, cycles: u8) {
+        self.advance(cycles);
+        if let Some(page) = self.oam_dma_page.take() {
+            self.oam_dma(page);
+        }
+    }
+
+    fn advance(&mut self, cycles: u8) {
+        self.cycles += cycles as u64;
+        for mapping in self.devices.iter_mut() {
+            mapping.device.tick(cycles);
+        }
+        self.clock_cartridge(cycles);
+        self.dmc_dma();
+    }
+
+    // マッパーはCPUの1サイクルごとに動かす
+    fn clock_cartridge(&mut self, cycles: u8) {
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            for _ in 0..cycles {
+                cartridge.clock();
+            }
+        }
+    }
+
+    // デバイスの音声出力の合計 カートリッジの拡張音源は含まない
+    pub fn audio_output(&self) -> f32 {
+        self.devices.iter().map(|m| m.device.audio_output()).sum()
+    }
+
+    // カートリッジのIRQ線の状態
+    pub fn irq(&self) -> bool {
+        self.cartridge
+            .as_ref()
+            .is_some_and(|cartridge| cartridge.irq())
+    }
+
+    // $XX00-$XXFFの256byteを$2004へ転送する
+    // 書き込み直後が奇数サイクルなら整列待ちで1サイクル余計にかかり、合計513か514サイクル止まる
+    fn oam_dma(&mut self, page: u8) {
+        self.oam_dma_active = true;
+        let align = if self.cycles % 2 == 1 { 2 } else { 1 };
+        self.advance(align);
+        for i in 0..=0xFF {
+            let data = self.mem_read((page as u16) << 8 | i);
+            self.advance(1);
+            self.mem_write(0x2004, data);
+            self.advance(1);
+        }
+        self.oam_dma_active = false;
+    }
+
+    // デバイスからのDMA要求（DMCのサンプル読み込み）を処理する
+    // 通常はCPUを4サイクル止めるが、OAM DMA中は転送の隙間に入るので2サイクルで済む
+    fn dmc_dma(&mut self) {
+        loop {
+            let request = self
+                .devices
+                .iter_mut()
+                .enumerate()
+                .find_map(|(i, m)| m.device.dma_request().map(|addr| (i, addr)));
+            let Some((index, addr)) = request else {
+                return;
+            };
+            let data = self.mem_read(addr);
+            self.devices[index].device.dma_complete(data);
+
+            let stall = if self.oam_dma_active { 2 } else { 4 };
+            self.cycles += stall as u64;
+            for mapping in self.devices.iter_mut() {
+                mapping.device.tick(stall);
+            }
+            self.clock_cartridge(stall);
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // 読み込み回数を数えるテスト用RAM
+    struct TestRam {
+        base: u16,
+        data: Vec<u8>,
+        reads: Rc<Cell<u32>>,
+        ticks: Rc<Cell<u64>>,
+    }
+
+    impl TestRam {
+        fn new(range: &RangeInclusive<u16>) -> Self {
+            TestRam {
+                base: *range.start(),
+                data: vec![0; (range.end() - range.start()) as usize + 1],
+                reads: Rc::new(Cell::new(0)),
+                ticks: Rc::new(Cell::new(0)),
+            }
+        }
+    }
+
+    impl Device for TestRam {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.reads.set(self.reads.get() + 1);
+            self.data[(addr - self.base) as usize]
+        }
+        fn write(&mut self, addr: u16, data: u8) {
+            self.data[(addr - self.base) as usize] = data;
+        }
+        fn peek(&self, addr: u16) -> u8 {
+            self.data[(addr - self.base) as usize]
+        }
+        fn tick(&mut self, cycles: u8) {
+            self.ticks.set(self.ticks.get() + cycles as u64);
+        }
+    }
+
+    #[test]
+    fn test_mapped_device_receives_access() {
+        let mut bus = Bus::new();
+        let ram = TestRam::new(&(0x6000..=0x7FFF));
+        let reads = ram.reads.clone();
+        bus.map(0x6000..=0x7FFF, Box::new(ram)).unwrap();
+
+        bus.mem_write(0x6001, 0x42);
+        assert_eq!(bus.mem_read(0x6001), 0x42);
+        assert_eq!(reads.get(), 1);
+        // peekは読み込み回数に数えない
+        assert_eq!(bus.peek(0x6001), 0x42);
+        assert_eq!(reads.get(), 1);
+        // マップされていない番地はフラットなメモリ
+        bus.mem_write(0x5FFF, 0x11);
+        assert_eq!(bus.mem_read(0x5FFF), 0x11);
+    }
+
+    #[test]
+    fn test_map_overlap_is_rejected() {
+        let mut bus = Bus::new();
+        bus.map(0x6000..=0x7FFF, Box::new(TestRam::new(&(0x6000..=0x7FFF))))
+            .unwrap();
+        let result = bus.map(0x7000..=0x8FFF, Box::new(TestRam::new(&(0x7000..=0x8FFF))));
+        assert_eq!(
+            result,
+            Err(MapError::Overlap {
+                range: 0x7000..=0x8FFF,
+                existing: 0x6000..=0x7FFF,
+            })
+        );
+        // 隣接する範囲は登録できる
+        assert!(
+            bus.map(0x8000..=0x8FFF, Box::new(TestRam::new(&(0x8000..=0x8FFF))))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_map_empty_range_is_rejected() {
+        let mut bus = Bus::new();
+        #[allow(clippy::reversed_empty_ranges)]
+        let range = 0x7000..=0x6000;
+        assert!(matches!(
+            bus.map(range, Box::new(TestRam::new(&(0x6000..=0x6000)))),
+            Err(MapError::EmptyRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_tick_reaches_devices() {
+        let mut bus = Bus::new();
+        let ram = TestRam::new(&(0x6000..=0x60FF));
+        let ticks = ram.ticks.clone();
+        bus.map(0x6000..=0x60FF, Box::new(ram)).unwrap();
+
+        bus.tick(3);
+        bus.tick(4);
+        assert_eq!(bus.cycles, 7);
+        assert_eq!(ticks.get(), 7);
+    }
+
+    #[test]
+    fn test_unmap_restores_memory() {
+        let mut bus = Bus::new();
+        bus.map(0x6000..=0x60FF, Box::new(TestRam::new(&(0x6000..=0x60FF))))
+            .unwrap();
+        bus.mem_write(0x6000, 0x99);
+        assert!(bus.unmap(0x6080).is_some());
+        assert_eq!(bus.mem_read(0x6000), 0x00);
+    }
+
+    // 指定サイクル経過後に一度だけDMAを要求するテスト用DMC
+    struct TestDmc {
+        wait: u32,
+        addr: Option<u16>,
+        received: Rc<Cell<Option<u8>>>,
+    }
+
+    impl TestDmc {
+        fn new(wait: u32, addr: u16) -> Self {
+            TestDmc {
+                wait,
+                addr: Some(addr),
+                received: Rc::new(Cell::new(None)),
+            }
+        }
+    }
+
+    impl Device for TestDmc {
+        fn read(&mut self, _addr: u16) -> u8 {
+            0
+        }
+        fn write(&mut self, _addr: u16, _data: u8) {}
+        fn peek(&self, _addr: u16) -> u8 {
+            0
+        }
+        fn tick(&mut self, cycles: u8) {
+            self.wait = self.wait.saturating_sub(cycles as u32);
+        }
+        fn dma_request(&mut self) -> Option<u16> {
+            if self.wait == 0 {
+                self.addr.take()
+            } else {
+                None
+            }
+        }
+        fn dma_complete(&mut self, data: u8) {
+            self.received.set(Some(data));
+        }
+    }
+
+    #[test]
+    fn test_oam_dma_copies_page() {
+        let mut bus = Bus::new();
+        for i in 0..=0xFF {
+            bus.mem_write(0x0200 + i, i as u8);
+        }
+        bus.mem_write(0x2003, 0x00);
+        bus.mem_write(0x4014, 0x02);
+        bus.tick(4);
+
+        assert_eq!(bus.oam()[0x00], 0x00);
+        assert_eq!(bus.oam()[0x7F], 0x7F);
+        assert_eq!(bus.oam()[0xFF], 0xFF);
+    }
+
+    #[test]
+    fn test_oam_dma_stall_even_and_odd() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x4014, 0x02);
+        bus.tick(4);
+        assert_eq!(bus.cycles, 4 + 513);
+
+        let mut bus = Bus::new();
+        bus.mem_write(0x4014, 0x02);
+        bus.tick(5);
+        assert_eq!(bus.cycles, 5 + 514);
+    }
+
+    #[test]
+    fn test_dmc_dma_stall() {
+        // OAM DMAが無い時は4サイクル止まる
+        let mut bus = Bus::new();
+        bus.mem_write(0xC000, 0x77);
+        let dmc = TestDmc::new(2, 0xC000);
+        let received = dmc.received.clone();
+        bus.map(0x4010..=0x4013, Box::new(dmc)).unwrap();
+        bus.tick(2);
+        assert_eq!(bus.cycles, 2 + 4);
+        assert_eq!(received.get(), Some(0x77));
+
+        // OAM DMAの途中なら2サイクルで済む
+        let mut bus = Bus::new();
+        bus.map(0x4010..=0x4013, Box::new(TestDmc::new(100, 0xC000)))
+            .unwrap();
+        bus.mem_write(0x4014, 0x02);
+        bus.tick(4);
+        assert_eq!(bus.cycles, 4 + 513 + 2);
+    }
+
+    #[test]
+    fn test_read_and_write_hooks() {
+        let mut bus = Bus::new();
+        let log = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let reads = log.clone();
+        bus.add_hook(Access::Read, 0x0000..=0x00FF, move |addr, value, cycle| {
+            reads.borrow_mut().push(("read", addr, value, cycle));
+        });
+        let writes = log.clone();
+        let id = bus.add_hook(Access::Write, 0x0010..=0x0010, move |addr, value, cycle| {
+            writes.borrow_mut().push(("write", addr, value, cycle));
+        });
+
+        bus.mem_write(0x0010, 0x33);
+        bus.mem_write(0x0011, 0x44);
+        bus.tick(2);
+        bus.mem_read(0x0010);
+        bus.mem_read(0x0100);
+        // peekはフックを呼ばない
+        bus.peek(0x0010);
+        assert_eq!(
+            *log.borrow(),
+            vec![("write", 0x0010, 0x33, 0), ("read", 0x0010, 0x33, 2)]
+        );
+
+        assert!(bus.remove_hook(id));
+        assert!(!bus.remove_hook(id));
+        bus.mem_write(0x0010, 0x55);
+        assert_eq!(log.borrow().len(), 2);
+    }
+
+    // 全部外してから登録し直しても、古い識別子で新しいフックは外れない
+    #[test]
+    fn test_stale_hook_id() {
+        let mut bus = Bus::new();
+        let stale = bus.add_hook(Access::Write, 0x0000..=0x0000, |_, _, _| {});
+        assert!(bus.remove_hook(stale));
+
+        let count = Rc::new(std::cell::Cell::new(0));
+        let writes = count.clone();
+        let id = bus.add_hook(Access::Write, 0x0000..=0x0000, move |_, _, _| {
+            writes.set(writes.get() + 1);
+        });
+        assert_ne!(id, stale);
+        assert!(!bus.remove_hook(stale));
+        bus.mem_write(0x0000, 0x01);
+        assert_eq!(count.get(), 1);
+        assert!(bus.remove_hook(id));
+    }
+
+    #[test]
+    fn test_cartridge_is_mapped_above_4020() {
+        use crate::cartridge::Rom;
+        use crate::cartridge::test::create_rom;
+
+        let mut bus = Bus::new();
+        let rom = Rom::new(&create_rom(0, 0, 1, 1)).unwrap();
+        bus.insert_cartridge(Cartridge::new(rom).unwrap());
+
+        // ROMへの書き込みは無視される
+        bus.mem_write(0x8000, 0x12);
+        assert_eq!(bus.mem_read(0x8000), 0x00);
+        // PRG-RAM
+        bus.mem_write(0x6000, 0x34);
+        assert_eq!(bus.peek(0x6000), 0x34);
+        // 登録したデバイスの方が優先される
+        bus.map(0x6000..=0x60FF, Box::new(TestRam::new(&(0x6000..=0x60FF))))
+            .unwrap();
+        assert_eq!(bus.mem_read(0x6000), 0x00);
+        assert!(bus.eject_cartridge().is_some());
+    }
+}
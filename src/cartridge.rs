@@ -0,0 +1,645 @@
+use crate::mapper::{self, Mapper};
+use crate::save::SaveFile;
+use crate::unif;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A]; // "NES^Z"
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_PAGE_SIZE: usize = 0x4000; // 16KB
+const CHR_ROM_PAGE_SIZE: usize = 0x2000; // 8KB
+
+// ネームテーブルのミラーリング
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+    // 1画面（$2000側と$2400側）
+    SingleScreenLower,
+    SingleScreenUpper,
+    // $2000/$2400/$2800/$2C00がそれぞれ使うCIRAMのページ（MMC5）
+    Custom([u8; 4]),
+}
+
+// ROMファイル読み込み時のエラー
+#[derive(Debug, PartialEq, Eq)]
+pub enum RomError {
+    // 先頭が"NES^Z"ではない
+    BadMagic,
+    // ヘッダに書かれたサイズよりファイルが短い
+    Truncated { expected: usize, actual: usize },
+    // 対応していない形式・機能
+    Unsupported(&'static str),
+    // 対応していないマッパー番号
+    UnsupportedMapper(u16),
+    // 対応していないUNIFの基板名
+    UnsupportedBoard(String),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::BadMagic => write!(f, "file is not in iNES format"),
+            RomError::Truncated { expected, actual } => write!(
+                f,
+                "file is truncated: expected {} bytes, found {}",
+                expected, actual
+            ),
+            RomError::Unsupported(feature) => write!(f, "{} is not supported", feature),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+            RomError::UnsupportedBoard(board) => write!(f, "board {} is not supported", board),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+// CPU/PPUのタイミング（地域）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    // どちらの地域でも動く
+    MultiRegion,
+    Dendy,
+}
+
+// 本体の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    // Vs. Systemはヘッダに使うPPUの型とハードウェアの型を持つ
+    VsSystem { ppu: u8, hardware: u8 },
+    Playchoice10,
+    // NES 2.0の拡張本体種別（Famicloneなど）
+    Extended(u8),
+}
+
+// iNESファイルの中身
+#[derive(Debug)]
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    // $7000に置かれる512byteのトレーナー
+    pub trainer: Option<Vec<u8>>,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    // バッテリーバックアップ付きのPRG-RAMを持つ
+    pub battery: bool,
+    // NES 2.0ヘッダかどうか
+    pub nes2: bool,
+    // RAMの大きさ（byte） NVRAMはバッテリーバックアップされる分
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    // CHR-ROMの後ろに続くその他のROM
+    pub misc_rom: Vec<u8>,
+    // 標準の入力デバイス（NES 2.0のbyte 15）
+    pub expansion_device: u8,
+    // ディスクシステムのディスクの各面 カートリッジでは空
+    pub disk_sides: Vec<Vec<u8>>,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        // UNIF形式も同じRomとして読む
+        if raw.starts_with(unif::UNIF_TAG) {
+            return unif::parse(raw);
+        }
+        if raw.len() < NES_TAG.len() || raw[0..4] != NES_TAG {
+            return Err(RomError::BadMagic);
+        }
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::Truncated {
+                expected: HEADER_SIZE,
+                actual: raw.len(),
+            });
+        }
+
+        let nes2 = (raw[7] >> 2) & 0b11 == 0b10;
+        let header = if nes2 {
+            Header::nes2(raw)?
+        } else {
+            Header::ines(raw)
+        };
+        if header.console_type == ConsoleType::Playchoice10 {
+            return Err(RomError::Unsupported("PlayChoice-10"));
+        }
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+        let battery = raw[6] & 0b10 != 0;
+        let has_trainer = raw[6] & 0b100 != 0;
+
+        if header.prg_rom_size == 0 {
+            return Err(RomError::Unsupported("empty PRG ROM"));
+        }
+
+        let prg_rom_start = HEADER_SIZE + if has_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + header.prg_rom_size;
+        let expected = chr_rom_start + header.chr_rom_size;
+        if raw.len() < expected {
+            return Err(RomError::Truncated {
+                expected,
+                actual: raw.len(),
+            });
+        }
+        let misc_rom = if header.misc_roms > 0 {
+            raw[expected..].to_vec()
+        } else {
+            Vec::new()
+        };
+
+        Ok(Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..expected].to_vec(),
+            trainer: has_trainer.then(|| raw[HEADER_SIZE..prg_rom_start].to_vec()),
+            mapper: header.mapper,
+            submapper: header.submapper,
+            screen_mirroring,
+            battery,
+            nes2,
+            prg_ram_size: header.prg_ram_size,
+            prg_nvram_size: header.prg_nvram_size,
+            chr_ram_size: header.chr_ram_size,
+            chr_nvram_size: header.chr_nvram_size,
+            timing: header.timing,
+            console_type: header.console_type,
+            misc_rom,
+            expansion_device: header.expansion_device,
+            disk_sides: Vec::new(),
+        })
+    }
+}
+
+// バイト6以外のヘッダの内容
+struct Header {
+    mapper: u16,
+    submapper: u8,
+    prg_rom_size: usize,
+    chr_rom_size: usize,
+    prg_ram_size: usize,
+    prg_nvram_size: usize,
+    chr_ram_size: usize,
+    chr_nvram_size: usize,
+    timing: Timing,
+    console_type: ConsoleType,
+    misc_roms: u8,
+    expansion_device: u8,
+}
+
+impl Header {
+    // iNES 1.0ヘッダ
+    fn ines(raw: &[u8]) -> Header {
+        // バイト12-15はiNES 1.0では0のはずだが、古いツールが"DiskDude!"などの文字列を
+        // バイト7から書き込んでいることがある その場合はバイト7以降を信用しない
+        let archaic = (raw[7] >> 2) & 0b11 != 0;
+        let garbage = archaic || raw[12..16].iter().any(|&b| b != 0);
+        let flags7 = if garbage { 0 } else { raw[7] };
+
+        let console_type = match flags7 & 0b11 {
+            0b01 => ConsoleType::VsSystem {
+                ppu: 0,
+                hardware: 0,
+            },
+            0b10 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Nes,
+        };
+        let timing = if !garbage && raw[9] & 0b1 != 0 {
+            Timing::Pal
+        } else {
+            Timing::Ntsc
+        };
+        // バイト8はPRG-RAMの8KB単位の大きさ 0なら互換のため8KBとみなす
+        let prg_ram_size = if garbage || raw[8] == 0 {
+            0x2000
+        } else {
+            raw[8] as usize * 0x2000
+        };
+        let battery = raw[6] & 0b10 != 0;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+        Header {
+            mapper: ((flags7 & 0b1111_0000) | (raw[6] >> 4)) as u16,
+            submapper: 0,
+            prg_rom_size: raw[4] as usize * PRG_ROM_PAGE_SIZE,
+            chr_rom_size,
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            // CHR-ROMが無ければ8KBのCHR-RAMを持つ
+            chr_ram_size: if chr_rom_size == 0 { 0x2000 } else { 0 },
+            chr_nvram_size: 0,
+            timing,
+            console_type,
+            misc_roms: 0,
+            expansion_device: 0,
+        }
+    }
+
+    // NES 2.0ヘッダ
+    fn nes2(raw: &[u8]) -> Result<Header, RomError> {
+        let console_type = match raw[7] & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem {
+                ppu: raw[13] & 0x0F,
+                hardware: raw[13] >> 4,
+            },
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(raw[13] & 0x0F),
+        };
+        let timing = match raw[12] & 0b11 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultiRegion,
+            _ => Timing::Dendy,
+        };
+
+        Ok(Header {
+            mapper: (raw[8] as u16 & 0x0F) << 8 | (raw[7] & 0xF0) as u16 | (raw[6] >> 4) as u16,
+            submapper: raw[8] >> 4,
+            prg_rom_size: nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE)?,
+            chr_rom_size: nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE)?,
+            prg_ram_size: nes2_ram_size(raw[10] & 0x0F),
+            prg_nvram_size: nes2_ram_size(raw[10] >> 4),
+            chr_ram_size: nes2_ram_size(raw[11] & 0x0F),
+            chr_nvram_size: nes2_ram_size(raw[11] >> 4),
+            timing,
+            console_type,
+            misc_roms: raw[14] & 0b11,
+            expansion_device: raw[15] & 0b11_1111,
+        })
+    }
+}
+
+// NES 2.0のROMサイズ 上位ニブルが$Fの時は指数・乗数表記 (2^E * (MM*2+1))
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> Result<usize, RomError> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .filter(|&size| size <= u32::MAX as usize)
+            .ok_or(RomError::Unsupported("ROM size larger than 4GB"))
+    } else {
+        Ok(((msb as usize) << 8 | lsb as usize) * page_size)
+    }
+}
+
+// NES 2.0のRAMサイズ 0ならなし、それ以外は64 << shift byte
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+// CPUの$4020-$FFFFとPPUの$0000-$1FFFに挿さるカートリッジ
+// バンク切り替えなどはマッパー番号ごとの実装に任せる
+pub struct Cartridge {
+    pub rom: Rom,
+    mapper: Box<dyn Mapper>,
+    save_file: Option<SaveFile>,
+}
+
+impl Cartridge {
+    pub fn new(rom: Rom) -> Result<Cartridge, RomError> {
+        let mut mapper = mapper::create(&rom)?;
+        // トレーナーは$7000-$71FFのPRG-RAMに置かれる
+        // CPUから書くとRAMが無効だったりレジスタに当たったりするので、マッパーのRAMに直接写す
+        if let Some(trainer) = &rom.trainer
+            && let Some(ram) = mapper.prg_ram_mut()
+            && let Some(dest) = ram.get_mut(0x1000..0x1000 + trainer.len())
+        {
+            dest.copy_from_slice(trainer);
+        }
+        Ok(Cartridge {
+            rom,
+            mapper,
+            save_file: None,
+        })
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        self.mapper.cpu_read(addr)
+    }
+
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.mapper.cpu_peek(addr)
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        self.mapper.cpu_write(addr, data);
+    }
+
+    // パターンテーブル($0000-$1FFF)の読み込み
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.mapper.ppu_bus(addr);
+        self.mapper.ppu_read(addr)
+    }
+
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
+        self.mapper.ppu_peek(addr)
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.mapper.ppu_bus(addr);
+        self.mapper.ppu_write(addr, data);
+    }
+
+    // パターンテーブル以外のアクセスでもPPUのアドレスバスの変化を伝える
+    pub fn ppu_bus(&mut self, addr: u16) {
+        self.mapper.ppu_bus(addr);
+    }
+
+    // ネームテーブル($2000-$2FFF)の読み込み Noneの時はPPUのCIRAMから読む
+    pub fn nametable_read(&mut self, addr: u16) -> Option<u8> {
+        self.mapper.ppu_bus(addr);
+        self.mapper.nametable_read(addr)
+    }
+
+    pub fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        self.mapper.nametable_peek(addr)
+    }
+
+    // falseの時はPPUのCIRAMに書き込む
+    pub fn nametable_write(&mut self, addr: u16, data: u8) -> bool {
+        self.mapper.ppu_bus(addr);
+        self.mapper.nametable_write(addr, data)
+    }
+
+    // CPUからPPUレジスタへの書き込みをマッパーに見せる
+    pub fn ppu_register_write(&mut self, addr: u16, data: u8) {
+        self.mapper.ppu_register_write(addr, data);
+    }
+
+    pub fn leds(&self) -> u8 {
+        self.mapper.leds()
+    }
+
+    pub fn controller_write(&mut self, data: u8) {
+        self.mapper.controller_write(data);
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    pub fn clock(&mut self) {
+        self.mapper.clock();
+        // 失敗しても覚えている内容は変わらないので、次の周期か終了時にもう一度書く
+        if self.save_file.as_mut().is_some_and(|save| save.tick())
+            && let Err(err) = self.flush_save_file()
+        {
+            self.report_save_error(&err);
+        }
+    }
+
+    pub fn notify_scanline(&mut self) {
+        self.mapper.notify_scanline();
+    }
+
+    // 拡張音源の出力
+    pub fn audio_output(&self) -> f32 {
+        self.mapper.audio_output()
+    }
+
+    // ディスクシステムのディスクの面の数
+    pub fn disk_sides(&self) -> usize {
+        self.mapper.disk_sides()
+    }
+
+    pub fn inserted_disk(&self) -> Option<usize> {
+        self.mapper.inserted_disk()
+    }
+
+    // 面を入れ替える Noneで取り出す
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.mapper.insert_disk(side);
+    }
+
+    // 電池で保持されるメモリ 電池の無いカートリッジや保存先の無いマッパーはNone
+    pub fn save_data(&self) -> Option<&[u8]> {
+        if !self.rom.battery {
+            return None;
+        }
+        self.mapper.save_data().filter(|data| !data.is_empty())
+    }
+
+    // .savファイルを結びつけ、あれば読み込む 読み込んだらtrue
+    // 電池が無ければ何もしない
+    pub fn attach_save_file(&mut self, path: PathBuf) -> io::Result<bool> {
+        if self.save_data().is_none() {
+            return Ok(false);
+        }
+        let mut save = SaveFile::new(path);
+        let loaded = match self.mapper.save_data_mut() {
+            Some(data) => save.load(data)?,
+            None => false,
+        };
+        self.save_file = Some(save);
+        Ok(loaded)
+    }
+
+    // 変化があれば.savファイルに書き出す 書いたらtrue
+    pub fn flush_save_file(&mut self) -> io::Result<bool> {
+        match (self.save_file.as_mut(), self.mapper.save_data()) {
+            (Some(save), Some(data)) => save.flush(data),
+            _ => Ok(false),
+        }
+    }
+
+    // 実行中や終了時の書き出しは呼び出し元に返せないので表示だけする
+    fn report_save_error(&self, err: &io::Error) {
+        if let Some(save) = &self.save_file {
+            eprintln!("{}: {}", save.path().display(), err);
+        }
+    }
+}
+
+// パニックなどで途中で終わっても、捨てる時に最後の状態を書き出す
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_save_file() {
+            self.report_save_error(&err);
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // テスト用のiNESファイルを組み立てる
+    pub fn create_rom(flags6: u8, flags7: u8, prg_pages: u8, chr_pages: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, prg_pages, chr_pages, flags6, flags7];
+        raw.resize(HEADER_SIZE, 0);
+        if flags6 & 0b100 != 0 {
+            raw.extend(vec![0x77; TRAINER_SIZE]);
+        }
+        for page in 0..prg_pages {
+            raw.extend(vec![page; PRG_ROM_PAGE_SIZE]);
+        }
+        raw.extend(vec![0xCC; chr_pages as usize * CHR_ROM_PAGE_SIZE]);
+        raw
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let rom = Rom::new(&create_rom(0x31 | 0b10, 0x10, 2, 1)).unwrap();
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.mapper, 0x13);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert!(rom.trainer.is_none());
+    }
+
+    #[test]
+    fn test_trainer() {
+        let rom = Rom::new(&create_rom(0b100, 0, 1, 0)).unwrap();
+        assert_eq!(rom.trainer.as_ref().map(|t| t.len()), Some(TRAINER_SIZE));
+        assert_eq!(rom.prg_rom[0], 0x00);
+
+        let cartridge = Cartridge::new(rom).unwrap();
+        assert_eq!(cartridge.peek(0x7000), 0x77);
+
+        // VRC6は電源投入時にPRG-RAMが無効でも、有効にすればトレーナーが見える
+        let rom = Rom::new(&create_rom(0x80 | 0b100, 0x10, 2, 1)).unwrap();
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        assert_eq!(cartridge.peek(0x7000), 0x00);
+        cartridge.write(0xB003, 0x80);
+        assert_eq!(cartridge.peek(0x7000), 0x77);
+        assert_eq!(cartridge.peek(0x71FF), 0x77);
+        assert_eq!(cartridge.peek(0x7200), 0x00);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Rom::new(b"NEX\x1A").unwrap_err(), RomError::BadMagic);
+
+        let mut raw = create_rom(0, 0, 2, 1);
+        raw.truncate(raw.len() - 1);
+        assert_eq!(
+            Rom::new(&raw).unwrap_err(),
+            RomError::Truncated {
+                expected: raw.len() + 1,
+                actual: raw.len()
+            }
+        );
+
+        assert_eq!(
+            Rom::new(&create_rom(0, 0b10, 1, 1)).unwrap_err(),
+            RomError::Unsupported("PlayChoice-10")
+        );
+        let rom = Rom::new(&create_rom(0xF0, 0xF0, 1, 1)).unwrap();
+        assert!(matches!(
+            Cartridge::new(rom),
+            Err(RomError::UnsupportedMapper(255))
+        ));
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let mut raw = create_rom(0x40, 0x58, 2, 1);
+        raw[8] = 0x31; // サブマッパー3、マッパー上位1
+        raw[10] = 0x70; // PRG-NVRAM 8KB
+        raw[11] = 0x07; // CHR-RAM 8KB
+        raw[12] = 0x01; // PAL
+        raw[15] = 0x01; // 標準コントローラー
+        let rom = Rom::new(&raw).unwrap();
+        assert!(rom.nes2);
+        assert_eq!(rom.mapper, 0x154);
+        assert_eq!(rom.submapper, 3);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+        assert_eq!(rom.chr_ram_size, 0x2000);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert_eq!(rom.expansion_device, 1);
+
+        // Vs. System
+        let mut raw = create_rom(0, 0x09, 1, 1);
+        raw[13] = 0x21;
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(
+            rom.console_type,
+            ConsoleType::VsSystem {
+                ppu: 1,
+                hardware: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_nes2_exponent_size() {
+        assert_eq!(nes2_rom_size(0x02, 0x00, PRG_ROM_PAGE_SIZE), Ok(0x8000));
+        // 2^4 * 3 = 48byte
+        assert_eq!(nes2_rom_size(0b0001_0001, 0x0F, PRG_ROM_PAGE_SIZE), Ok(48));
+        assert!(nes2_rom_size(0xFF, 0x0F, PRG_ROM_PAGE_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_diskdude_header() {
+        let mut raw = create_rom(0x10, 0, 1, 1);
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        let rom = Rom::new(&raw).unwrap();
+        assert!(!rom.nes2);
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.console_type, ConsoleType::Nes);
+        assert_eq!(rom.timing, Timing::Ntsc);
+    }
+
+    #[test]
+    fn test_cartridge_uses_mapper() {
+        let rom = Rom::new(&create_rom(0, 0, 1, 1)).unwrap();
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        assert_eq!(cartridge.peek(0x8000), cartridge.peek(0xC000));
+        assert_eq!(cartridge.ppu_read(0x0000), 0xCC);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_battery_save_file() {
+        use std::{env, fs, process};
+        let path = env::temp_dir().join(format!("nes-cartridge-{}.sav", process::id()));
+        let _ = fs::remove_file(&path);
+
+        // 電池が無ければ.savは使わない
+        let rom = Rom::new(&create_rom(0, 0, 1, 1)).unwrap();
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        assert!(cartridge.save_data().is_none());
+        assert!(!cartridge.attach_save_file(path.clone()).unwrap());
+
+        let rom = Rom::new(&create_rom(0b10, 0, 1, 1)).unwrap();
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        assert!(!cartridge.attach_save_file(path.clone()).unwrap());
+        cartridge.write(0x6000, 0x99);
+        assert!(cartridge.flush_save_file().unwrap());
+        assert!(!cartridge.flush_save_file().unwrap());
+
+        let rom = Rom::new(&create_rom(0b10, 0, 1, 1)).unwrap();
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        assert!(cartridge.attach_save_file(path.clone()).unwrap());
+        assert_eq!(cartridge.peek(0x6000), 0x99);
+
+        // 捨てる時に書き出される
+        cartridge.write(0x6000, 0x42);
+        drop(cartridge);
+        assert_eq!(fs::read(&path).unwrap()[0], 0x42);
+        fs::remove_file(&path).unwrap();
+    }
+}
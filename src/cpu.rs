@@ -0,0 +1,1301 @@
+use crate::bus::Bus;
+use crate::loader::{self, Image, LoadError};
+use crate::opcodes;
+use std::fmt;
+
+#[derive(Debug)] //debugの書式指定を利用するために
+#[allow(non_camel_case_types)] //キャメルケース以外を利用する
+pub enum AddressingMode {
+    Immediate,
+    ZeroPage,
+    ZeroPage_X,
+    ZeroPage_Y,
+    Absolute,
+    Absolute_X,
+    Absolute_Y,
+    Indirect_X,
+    Indirect_Y,
+    // 分岐命令の符号付き1byteの相対アドレス
+    Relative,
+    // JMPだけが使う ページの境界をまたがないバグがある
+    Indirect,
+    NoneAddressing,
+}
+
+const STACK: u16 = 0x0100;
+const STACK_RESET: u8 = 0xFD;
+
+// ステータスレジスタのbit
+const CARRY: u8 = 0b0000_0001;
+const ZERO: u8 = 0b0000_0010;
+const INTERRUPT_DISABLE: u8 = 0b0000_0100;
+const DECIMAL_MODE: u8 = 0b0000_1000;
+// BREAKとBREAK2はスタックに積んだ時だけ意味がある
+const BREAK: u8 = 0b0001_0000;
+const BREAK2: u8 = 0b0010_0000;
+const OVERFLOW: u8 = 0b0100_0000;
+const NEGATIVE: u8 = 0b1000_0000;
+
+// 実行を続けられない命令に当たった
+#[derive(Debug)]
+pub enum CpuError {
+    // まだ実装していないopscode PCはその命令を指したまま止まる
+    UnknownOpcode { addr: u16, code: u8 },
+    // プログラムがメモリに収まらなかった
+    Load(LoadError),
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { addr, code } => {
+                write!(f, "unknown opcode ${:02X} at ${:04X}", code, addr)
+            }
+            CpuError::Load(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+impl From<LoadError> for CpuError {
+    fn from(err: LoadError) -> Self {
+        CpuError::Load(err)
+    }
+}
+
+// ASL/LSR/ROL/RORの種類
+#[derive(Clone, Copy)]
+enum Shift {
+    Asl,
+    Lsr,
+    Rol,
+    Ror,
+}
+
+// 2つの番地の上位byteが違うか
+fn page_differs(a: u16, b: u16) -> bool {
+    a & 0xFF00 != b & 0xFF00
+}
+
+pub struct CPU {
+    // CPUのレジスターを定義
+    pub register_a: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub status: u8,
+    pub program_counter: u16,
+    pub stack_pointer: u8,
+    pub bus: Bus,
+    // 直前のアドレス計算でページをまたいだか
+    page_crossed: bool,
+    // 分岐した時などの命令表にない追加サイクル
+    extra_cycles: u8,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//  rustでstructの関数を定義する際はimpl内に記述する
+impl CPU {
+    // コンストラクタを定義 各フィールドを初期化して構造体(self)を返すコンストラクタ
+    pub fn new() -> Self {
+        CPU {
+            register_a: 0,
+            register_x: 0,
+            register_y: 0,
+            status: 0,
+            program_counter: 0,
+            stack_pointer: STACK_RESET,
+            bus: Bus::new(),
+            page_crossed: false,
+            extra_cycles: 0,
+        }
+    }
+
+    // mutに変更した。
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
+        match mode {
+            AddressingMode::Immediate => self.program_counter,
+            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+            // 1byteアドレスにregister_xの値を足す
+            AddressingMode::ZeroPage_X => {
+                let pos = self.mem_read(self.program_counter);
+                pos.wrapping_add(self.register_x) as u16
+            }
+            // 1byteアドレスにregister_yの値を足す
+            AddressingMode::ZeroPage_Y => {
+                let pos = self.mem_read(self.program_counter);
+                pos.wrapping_add(self.register_y) as u16
+            }
+            // 2byteアドレスにregister_xの値を足す
+            AddressingMode::Absolute_X => {
+                let base = self.mem_read_u16(self.program_counter);
+                let addr = base.wrapping_add(self.register_x as u16);
+                self.page_crossed = page_differs(base, addr);
+                addr
+            }
+            // 2byteアドレスにregister_yの値を足す
+            AddressingMode::Absolute_Y => {
+                let base = self.mem_read_u16(self.program_counter);
+                let addr = base.wrapping_add(self.register_y as u16);
+                self.page_crossed = page_differs(base, addr);
+                addr
+            }
+            // 1byteアドレスにregister_xの値を足し、そのアドレスの値と次のアドレスの値2byteをアドレスとする
+            AddressingMode::Indirect_X => {
+                let base = self.mem_read(self.program_counter);
+
+                let ptr: u8 = base.wrapping_add(self.register_x);
+                let lo = self.mem_read(ptr as u16);
+                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+                (hi as u16) << 8 | (lo as u16)
+            }
+            // 1byteアドレスと次のアドレスの値をderef_baseとし、deref_baseにregister_yを足したのが最終的なアドレス
+            AddressingMode::Indirect_Y => {
+                let base = self.mem_read(self.program_counter);
+
+                let lo = self.mem_read(base as u16);
+                let hi = self.mem_read(base.wrapping_add(1) as u16);
+                let deref_base = (hi as u16) << 8 | (lo as u16);
+                let addr = deref_base.wrapping_add(self.register_y as u16);
+                self.page_crossed = page_differs(deref_base, addr);
+                addr
+            }
+            // 次の命令の番地からの相対位置
+            AddressingMode::Relative => {
+                let offset = self.mem_read(self.program_counter) as i8;
+                self.program_counter
+                    .wrapping_add(1)
+                    .wrapping_add(offset as u16)
+            }
+            // ($xxFF)は$xx00から上位byteを読む
+            AddressingMode::Indirect => {
+                let ptr = self.mem_read_u16(self.program_counter);
+                let lo = self.mem_read(ptr);
+                let hi = self.mem_read(ptr & 0xFF00 | (ptr as u8).wrapping_add(1) as u16);
+                (hi as u16) << 8 | (lo as u16)
+            }
+            AddressingMode::NoneAddressing => {
+                panic!("mode {:?} is not supported", mode);
+            }
+        }
+    }
+
+    // memory番地を受け取り格納値を返す バス経由でデバイスにも届く
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.bus.mem_read(addr)
+    }
+    // memory番地とdateを受け取り、番地に値を格納する
+    fn mem_write(&mut self, addr: u16, date: u8) {
+        self.bus.mem_write(addr, date);
+    }
+    // 2byteのデータを取る際のmem_read　リトルエンディアンアドレッシング
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+    // 読み込みと同じ値を副作用なしで返す（デバッガやメモリビューア用）
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+    pub fn peek_u16(&self, pos: u16) -> u16 {
+        self.bus.peek_u16(pos)
+    }
+    // 2byteの値を格納するmem_write 今はテストでしか使わない
+    #[cfg(test)]
+    fn mem_write_u16(&mut self, pos: u16, date: u16) {
+        let hi = (date >> 8) as u8;
+        let lo = (date & 0xff) as u8;
+        self.mem_write(pos, lo);
+        self.mem_write(pos.wrapping_add(1), hi);
+    }
+
+    // reset関数
+    pub fn reset(&mut self) {
+        self.register_a = 0;
+        self.register_x = 0;
+        self.register_y = 0;
+        self.status = 0;
+        self.stack_pointer = STACK_RESET;
+
+        self.program_counter = self.mem_read_u16(0xFFFC);
+    }
+
+    // programを受け取りメモリの 0x8000 番地からroadして実行
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<(), CpuError> {
+        self.load(program)?;
+        self.reset();
+        self.run()
+    }
+    // プログラムのバイト列を、メモリの 0x8000 番地から書き込んで、そこから実行開始するようにPCをセットする
+    // 32KBを超えるとアドレス空間からはみ出すのでエラーにする
+    pub fn load(&mut self, program: Vec<u8>) -> Result<(), LoadError> {
+        self.load_at(0x8000, &program)?;
+        self.set_reset_vector(0x8000);
+        Ok(())
+    }
+    // バイト列をaddr番地から書き込む リセットベクタは変更しない
+    // レジスタやマッパーに書き込んだことにはせず、裏のメモリへ直接置く
+    pub fn load_at(&mut self, addr: u16, bytes: &[u8]) -> Result<(), LoadError> {
+        loader::check_range(addr as u32, bytes.len())?;
+        self.bus.load(addr, bytes);
+        Ok(())
+    }
+    // ローダーで読んだイメージを書き込む 開始アドレスがあればリセットベクタに設定する
+    pub fn load_image(&mut self, image: &Image) -> Result<(), LoadError> {
+        for segment in &image.segments {
+            self.load_at(segment.addr, &segment.data)?;
+        }
+        if let Some(entry) = image.entry {
+            self.set_reset_vector(entry);
+        }
+        Ok(())
+    }
+    // reset時に読み込まれる 0xFFFC 番地の開始アドレスを設定する
+    pub fn set_reset_vector(&mut self, addr: u16) {
+        self.bus.load(0xFFFC, &addr.to_le_bytes());
+    }
+    // メモリの 0x8000 番地からopscode読み込んで実行
+    // BRKで止まる 実装していない命令ではその番地で止まってエラーを返す
+    pub fn run(&mut self) -> Result<(), CpuError> {
+        self.run_with_callback(|_| true)
+    }
+
+    // 命令の前ごとにcallbackを呼び、falseが返ったらその命令を実行せずに止まる
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> Result<(), CpuError>
+    where
+        F: FnMut(&mut CPU) -> bool,
+    {
+        loop {
+            if !callback(self) {
+                return Ok(());
+            }
+            // 命令の切れ目でIRQ線を見る Iフラグが立っていれば待たせる
+            if self.bus.irq() && self.status & INTERRUPT_DISABLE == 0 {
+                self.interrupt(0xFFFE);
+                continue;
+            }
+            let addr = self.program_counter;
+            let code = self.mem_read(addr);
+            self.bus.notify_execute(addr, code);
+
+            // 命令表から長さ・アドレッシングモード・サイクル数を引く
+            let opcode = opcodes::lookup(code).ok_or(CpuError::UnknownOpcode { addr, code })?;
+            self.program_counter = self.program_counter.wrapping_add(1);
+            let program_counter_state = self.program_counter;
+
+            self.page_crossed = false;
+            self.extra_cycles = 0;
+            match code {
+                /* ADC */
+                0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => self.adc(&opcode.mode),
+                /* SBC */
+                0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => self.sbc(&opcode.mode),
+                /* LDA */
+                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
+                    self.lda(&opcode.mode);
+                }
+                /* LDX */
+                0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => self.ldx(&opcode.mode),
+                /* LDY */
+                0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => self.ldy(&opcode.mode),
+                // BRK - Loop Break
+                0x00 => {
+                    return Ok(());
+                }
+                // NOP
+                0xEA => {}
+                // TAX - Transfer Accumulator to X
+                0xAA => self.tax(),
+                0xA8 => self.tay(),
+                0x8A => self.txa(),
+                0x98 => self.tya(),
+                0xBA => self.tsx(),
+                // TXS だけはフラグが変わらない
+                0x9A => self.stack_pointer = self.register_x,
+                0xE8 => self.inx(),
+                0xC8 => self.iny(),
+                0xCA => self.dex(),
+                0x88 => self.dey(),
+
+                /* STA */
+                0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
+                    self.sta(&opcode.mode);
+                }
+                /* STX */
+                0x86 | 0x96 | 0x8E => {
+                    let addr = self.get_operand_address(&opcode.mode);
+                    self.mem_write(addr, self.register_x);
+                }
+                /* STY */
+                0x84 | 0x94 | 0x8C => {
+                    let addr = self.get_operand_address(&opcode.mode);
+                    self.mem_write(addr, self.register_y);
+                }
+
+                /* AND, EOR, ORA */
+                0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
+                    self.and(&opcode.mode);
+                }
+                0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
+                    self.eor(&opcode.mode);
+                }
+                0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
+                    self.ora(&opcode.mode);
+                }
+                0x24 | 0x2C => self.bit(&opcode.mode),
+
+                /* CMP, CPX, CPY */
+                0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
+                    self.compare(&opcode.mode, self.register_a);
+                }
+                0xE0 | 0xE4 | 0xEC => self.compare(&opcode.mode, self.register_x),
+                0xC0 | 0xC4 | 0xCC => self.compare(&opcode.mode, self.register_y),
+
+                /* シフト・ローテート Aに対するものはアドレスを持たない */
+                0x0A => self.register_a = self.shift_value(self.register_a, Shift::Asl),
+                0x4A => self.register_a = self.shift_value(self.register_a, Shift::Lsr),
+                0x2A => self.register_a = self.shift_value(self.register_a, Shift::Rol),
+                0x6A => self.register_a = self.shift_value(self.register_a, Shift::Ror),
+                0x06 | 0x16 | 0x0E | 0x1E => self.shift(&opcode.mode, Shift::Asl),
+                0x46 | 0x56 | 0x4E | 0x5E => self.shift(&opcode.mode, Shift::Lsr),
+                0x26 | 0x36 | 0x2E | 0x3E => self.shift(&opcode.mode, Shift::Rol),
+                0x66 | 0x76 | 0x6E | 0x7E => self.shift(&opcode.mode, Shift::Ror),
+
+                /* INC, DEC */
+                0xE6 | 0xF6 | 0xEE | 0xFE => self.increment(&opcode.mode, 1),
+                0xC6 | 0xD6 | 0xCE | 0xDE => self.increment(&opcode.mode, 0xFF),
+
+                /* 分岐 */
+                0x90 => self.branch(self.status & CARRY == 0),
+                0xB0 => self.branch(self.status & CARRY != 0),
+                0xD0 => self.branch(self.status & ZERO == 0),
+                0xF0 => self.branch(self.status & ZERO != 0),
+                0x10 => self.branch(self.status & NEGATIVE == 0),
+                0x30 => self.branch(self.status & NEGATIVE != 0),
+                0x50 => self.branch(self.status & OVERFLOW == 0),
+                0x70 => self.branch(self.status & OVERFLOW != 0),
+
+                /* JMP, JSR, RTS, RTI */
+                0x4C | 0x6C => self.program_counter = self.get_operand_address(&opcode.mode),
+                0x20 => {
+                    let target = self.get_operand_address(&opcode.mode);
+                    // 戻り先-1 (JSRの最後のbyte) を積む
+                    self.stack_push_u16(self.program_counter.wrapping_add(1));
+                    self.program_counter = target;
+                }
+                0x60 => self.program_counter = self.stack_pop_u16().wrapping_add(1),
+                0x40 => {
+                    self.plp();
+                    self.program_counter = self.stack_pop_u16();
+                }
+
+                /* スタック */
+                0x48 => self.stack_push(self.register_a),
+                0x08 => self.stack_push(self.status | BREAK | BREAK2),
+                0x68 => {
+                    self.register_a = self.stack_pop();
+                    self.update_zero_and_negative_flags(self.register_a);
+                }
+                0x28 => self.plp(),
+
+                /* フラグ */
+                0x18 => self.status &= !CARRY,
+                0x38 => self.status |= CARRY,
+                0x58 => self.status &= !INTERRUPT_DISABLE,
+                0x78 => self.status |= INTERRUPT_DISABLE,
+                0xB8 => self.status &= !OVERFLOW,
+                0xD8 => self.status &= !DECIMAL_MODE,
+                0xF8 => self.status |= DECIMAL_MODE,
+
+                _ => {
+                    self.program_counter = addr;
+                    return Err(CpuError::UnknownOpcode { addr, code });
+                }
+            }
+
+            // 経過したサイクルをバスに伝える 読み込み命令はページをまたぐと1サイクル増える
+            let mut cycles = opcode.cycles + self.extra_cycles;
+            if self.page_crossed && opcodes::has_page_cross_penalty(opcode.mnemonic) {
+                cycles += 1;
+            }
+            self.bus.tick(cycles);
+
+            // ジャンプしていなければオペランド分PCを進める
+            if program_counter_state == self.program_counter {
+                self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
+            }
+        }
+    }
+
+    // アキュムレータにaddしcもaddする。
+    fn adc(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+
+        let carry = self.status & 0x01;
+        let (rhs, carry_flag1) = value.overflowing_add(carry);
+        let (n, carry_flag2) = self.register_a.overflowing_add(rhs);
+
+        // 加算する前に最上位ビットが同じだったのに、演算後変化した際。符号overflow
+        let overflow = (self.register_a & 0x80) == (value & 0x80) && (value & 0x80) != (n & 0x80);
+
+        self.register_a = n;
+
+        // bit_overflow
+        self.status = if carry_flag1 || carry_flag2 {
+            self.status | 0x01
+        } else {
+            self.status & !0x01
+        };
+        // 符号overflow
+        self.status = if overflow {
+            self.status | 0x40
+        } else {
+            self.status & !0x40
+        };
+
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    // アキュムレータから減算しcも減算する。
+    fn sbc(&mut self, mode: &AddressingMode) {
+        // A-M-(1-C)
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+
+        let carry = self.status & 0x01;
+        let (v1, carry_flag1) = self.register_a.overflowing_sub(value);
+        let (n, carry_flag2) = v1.overflowing_sub(1 - carry);
+
+        // 加算する前に最上位ビットが違った際に、演算後変化した際。符号overflow
+        let overflow =
+            (self.register_a & 0x80) != (value & 0x80) && (self.register_a & 0x80) != (n & 0x80);
+
+        self.register_a = n;
+
+        // bit_overflow
+        self.status = if !carry_flag1 && !carry_flag2 {
+            self.status | 0x01
+        } else {
+            self.status & !0x01
+        };
+        // 符号overflow
+        self.status = if overflow {
+            self.status | 0x40
+        } else {
+            self.status & !0x40
+        };
+
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    // 引数で取った値をアキュムレータに格納
+    fn lda(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.register_a = value;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    // アキュムレータの値をregister_xにコピー
+    fn tax(&mut self) {
+        self.register_x = self.register_a;
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    // register_xをインクリメント
+    fn inx(&mut self) {
+        self.register_x = self.register_x.wrapping_add(1);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    // register_aの値をmemoryに書き込み
+    fn sta(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_a);
+    }
+
+    // 引数で取った値をregister_x/register_yに格納
+    fn ldx(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.register_x = self.mem_read(addr);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    fn ldy(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.register_y = self.mem_read(addr);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+    // レジスタ間のコピー
+    fn tay(&mut self) {
+        self.register_y = self.register_a;
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+    fn txa(&mut self) {
+        self.register_a = self.register_x;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn tya(&mut self) {
+        self.register_a = self.register_y;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn tsx(&mut self) {
+        self.register_x = self.stack_pointer;
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    fn iny(&mut self) {
+        self.register_y = self.register_y.wrapping_add(1);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+    fn dex(&mut self) {
+        self.register_x = self.register_x.wrapping_sub(1);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    fn dey(&mut self) {
+        self.register_y = self.register_y.wrapping_sub(1);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    // アキュムレータとメモリの論理演算
+    fn and(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.register_a &= self.mem_read(addr);
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn eor(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.register_a ^= self.mem_read(addr);
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn ora(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.register_a |= self.mem_read(addr);
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    // A AND Mが0ならzero、Mのbit7/bit6をそのままnegative/overflowへ
+    fn bit(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.set_flag(ZERO, self.register_a & value == 0);
+        self.set_flag(NEGATIVE, value & 0x80 != 0);
+        self.set_flag(OVERFLOW, value & 0x40 != 0);
+    }
+    // register - M の結果でフラグだけを変える 引けたらcarry
+    fn compare(&mut self, mode: &AddressingMode, register: u8) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.set_flag(CARRY, register >= value);
+        self.update_zero_and_negative_flags(register.wrapping_sub(value));
+    }
+
+    // 1bitずらした値を返し、押し出されたbitをcarryに入れる
+    fn shift_value(&mut self, value: u8, shift: Shift) -> u8 {
+        let carry = self.status & CARRY;
+        let (result, out) = match shift {
+            Shift::Asl => (value << 1, value & 0x80),
+            Shift::Lsr => (value >> 1, value & 0x01),
+            Shift::Rol => (value << 1 | carry, value & 0x80),
+            Shift::Ror => (value >> 1 | carry << 7, value & 0x01),
+        };
+        self.set_flag(CARRY, out != 0);
+        self.update_zero_and_negative_flags(result);
+        result
+    }
+    fn shift(&mut self, mode: &AddressingMode, shift: Shift) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        let result = self.shift_value(value, shift);
+        self.mem_write(addr, result);
+    }
+    // メモリの値にdeltaを足す (0xFFで1減らす)
+    fn increment(&mut self, mode: &AddressingMode, delta: u8) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr).wrapping_add(delta);
+        self.mem_write(addr, value);
+        self.update_zero_and_negative_flags(value);
+    }
+
+    // 条件が成り立てば分岐する 分岐すると1サイクル、ページをまたぐとさらに1サイクルかかる
+    fn branch(&mut self, condition: bool) {
+        if condition {
+            let target = self.get_operand_address(&AddressingMode::Relative);
+            let next = self.program_counter.wrapping_add(1);
+            self.extra_cycles += if page_differs(next, target) { 2 } else { 1 };
+            self.program_counter = target;
+        }
+    }
+
+    // スタックは$0100-$01FFで、下に向かって積む
+    fn stack_push(&mut self, data: u8) {
+        self.mem_write(STACK + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+    }
+    fn stack_pop(&mut self) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(STACK + self.stack_pointer as u16)
+    }
+    fn stack_push_u16(&mut self, data: u16) {
+        self.stack_push((data >> 8) as u8);
+        self.stack_push((data & 0xff) as u8);
+    }
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+        hi << 8 | lo
+    }
+    // IRQ・NMIの割り込み PCとBREAKを落としたステータスを積み、ベクタへ飛ぶ 7サイクルかかる
+    fn interrupt(&mut self, vector: u16) {
+        self.stack_push_u16(self.program_counter);
+        self.stack_push(self.status & !BREAK | BREAK2);
+        self.status |= INTERRUPT_DISABLE;
+        self.program_counter = self.mem_read_u16(vector);
+        self.bus.tick(7);
+    }
+
+    // スタックから戻したステータスではBREAKは無視され、BREAK2は常に立つ
+    fn plp(&mut self) {
+        self.status = self.stack_pop() & !BREAK | BREAK2;
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.status |= flag;
+        } else {
+            self.status &= !flag;
+        }
+    }
+
+    // ゼロフラグとネガティブフラグ変更
+    fn update_zero_and_negative_flags(&mut self, result: u8) {
+        // もしresultが0ならzeroフラグを立てる
+        if result == 0 {
+            self.status |= 0b0000_0010;
+        } else {
+            self.status &= 0b1111_1101;
+        }
+        // もしresultの7番目が立っているのならNegativeフラグを立てる
+        if result & 0b1000_0000 != 0 {
+            self.status |= 0b1000_0000;
+        } else {
+            self.status &= 0b0111_1111;
+        }
+    }
+}
+
+// テスト
+#[cfg(test)] //条件付きコンパイルを利用する
+mod test {
+    use super::*;
+    use crate::hooks::Access;
+
+    #[test]
+    // フラグが立たないLDAテスト
+    fn test_lda_immediate_load_data() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x05, 0x00]).unwrap();
+        assert_eq!(cpu.register_a, 0x05);
+        assert!(cpu.status & 0b0000_0010 == 0b00);
+        assert!(cpu.status & 0b1000_0000 == 0);
+    }
+
+    #[test]
+    // zeroフラグが立つLADテスト
+    fn test_lda_zero_flag() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x00, 0x00]).unwrap();
+        assert!(cpu.status & 0b0000_0010 == 0b10);
+    }
+
+    #[test]
+    // negativeフラグが立つLADテスト
+    fn test_lda_negative_flag() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x80, 0x00]).unwrap();
+        assert!(cpu.status & 0b1000_0000 != 0);
+    }
+    // lda_zero_page
+    #[test]
+    fn test_lda_from_memory_zero_page() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x55);
+        cpu.load_and_run(vec![0xa5, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+    // lda_zero_page_x
+    #[test]
+    fn test_lda_from_memory_zero_page_x() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xb5, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.mem_write(0x11, 0x55);
+        cpu.register_x = 0x01;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+    // lda_absolute
+    #[test]
+    fn test_lda_from_memory_absolute() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xad, 0x10, 0xaa, 0x00]).unwrap();
+        cpu.reset();
+        cpu.mem_write(0xaa10, 0x57);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x57);
+    }
+    // lda_absolute_x
+    #[test]
+    fn test_lda_from_memory_absolute_x() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xbd, 0x10, 0xaa, 0x00]).unwrap();
+        cpu.reset();
+        cpu.mem_write(0xaa15, 0x58);
+        cpu.register_x = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x58);
+    }
+    // lda_absolute_y
+    #[test]
+    fn test_lda_from_memory_absolute_y() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xb9, 0x10, 0xaa, 0x00]).unwrap();
+        cpu.reset();
+        cpu.mem_write(0xaa16, 0x59);
+        cpu.register_y = 0x06;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x59);
+    }
+    // lda_indirect_x
+    #[test]
+    fn test_lda_from_memory_indirect_x() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xa1, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.mem_write_u16(0x16, 0xFF05);
+        cpu.mem_write(0xFF05, 0x5A);
+        cpu.register_x = 0x06;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x5A);
+    }
+    // lda_indirect_y
+    #[test]
+    fn test_lda_from_memory_indirect_y() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xb1, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.mem_write_u16(0x10, 0xFF06);
+        cpu.mem_write(0xFF09, 0x5B);
+        cpu.register_y = 0x03;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x5B);
+    }
+
+    #[test]
+    // register_xからregister_aにcopyのTAXテスト
+    fn test_tax_move_a_to_x() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x0A, 0xAA, 0x00]).unwrap();
+        assert_eq!(cpu.register_x, 0x0A);
+    }
+
+    // 結合テスト
+    #[test]
+    fn test_5_ops_working_together() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00])
+            .unwrap();
+        assert_eq!(cpu.register_x, 0xc1)
+    }
+
+    // register_xのオーバーフローテスト
+    #[test]
+    fn test_inx_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0xff, 0xaa, 0xe8, 0x00])
+            .unwrap(); // LDA #$FF, TAX, INX, BRK
+        assert_eq!(cpu.register_x, 0x00); // wrap around
+        assert!(cpu.status & 0b0000_0010 != 0); // Zero flag should be set
+    }
+
+    // staテスト
+    #[test]
+    fn test_sta_from_memory() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x85, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0xBA;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0xBA);
+    }
+
+    // OAM DMAでCPUが止まった分もサイクル数に数えられる
+    // LDX #$05, DEX, BNE -3 で5回まわる
+    #[test]
+    fn test_branch_loop() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa2, 0x05, 0xca, 0xd0, 0xfd, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.register_x, 0);
+        assert_eq!(cpu.status & ZERO, ZERO);
+        // 分岐した4回は1サイクルずつ多い
+        assert_eq!(cpu.bus.cycles, 2 + 5 * (2 + 2) + 4);
+    }
+
+    #[test]
+    fn test_jsr_rts() {
+        let mut cpu = CPU::new();
+        // JSR $8006, INX, BRK, (サブルーチン) LDA #$42, RTS
+        cpu.load_and_run(vec![0x20, 0x06, 0x80, 0xe8, 0x00, 0x00, 0xa9, 0x42, 0x60])
+            .unwrap();
+
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.register_x, 1);
+        assert_eq!(cpu.program_counter, 0x8005);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+        // 戻り先-1が積まれる
+        assert_eq!(cpu.mem_read(0x01FD), 0x80);
+        assert_eq!(cpu.mem_read(0x01FC), 0x02);
+    }
+
+    #[test]
+    fn test_stack() {
+        let mut cpu = CPU::new();
+        // LDA #$80, PHA, PHP, LDA #$00, PLP, PLA
+        cpu.load_and_run(vec![0xa9, 0x80, 0x48, 0x08, 0xa9, 0x00, 0x28, 0x68, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.register_a, 0x80);
+        assert_eq!(cpu.status, NEGATIVE | BREAK2);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+        // PHPはBREAKを立てて積む
+        assert_eq!(cpu.mem_read(0x01FC), NEGATIVE | BREAK | BREAK2);
+    }
+
+    #[test]
+    fn test_compare_and_shift() {
+        let mut cpu = CPU::new();
+        // LDA #$81, CMP #$81, ROL A, ROR $10 ($10は0x02)
+        cpu.load(vec![0xa9, 0x81, 0xc9, 0x81, 0x2a, 0x66, 0x10, 0x00])
+            .unwrap();
+        cpu.reset();
+        cpu.mem_write(0x10, 0x02);
+        cpu.run().unwrap();
+
+        // CMPで立ったcarryがbit0に入り、bit7がcarryへ
+        assert_eq!(cpu.register_a, 0x03);
+        // carryがbit7に入り、bit0の0がcarryへ
+        assert_eq!(cpu.mem_read(0x10), 0x81);
+        assert_eq!(cpu.status & (CARRY | NEGATIVE), NEGATIVE);
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_bug() {
+        let mut cpu = CPU::new();
+        // JMP ($02FF) は$02FFと$0200から飛び先を読む
+        cpu.load(vec![0x6c, 0xff, 0x02]).unwrap();
+        cpu.reset();
+        cpu.mem_write(0x02FF, 0x10);
+        cpu.mem_write(0x0200, 0x80);
+        cpu.mem_write(0x0300, 0x90);
+        cpu.mem_write(0x8010, 0xe8);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    // 読み込みはページをまたぐと1サイクル、書き込みはいつも同じ
+    #[test]
+    fn test_page_cross_cycles() {
+        let mut cpu = CPU::new();
+        // LDX #$01, LDA $02FF,X, STA $02FF,X
+        cpu.load_and_run(vec![0xa2, 0x01, 0xbd, 0xff, 0x02, 0x9d, 0xff, 0x02, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.bus.cycles, 2 + 5 + 5);
+    }
+
+    #[test]
+    fn test_oam_dma_stalls_cpu() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xa9, 0x02, 0x8d, 0x14, 0x40, 0x00]).unwrap(); // LDA #$02, STA $4014, BRK
+        cpu.reset();
+        cpu.mem_write(0x0205, 0x3C);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.bus.oam()[0x05], 0x3C);
+        assert_eq!(cpu.bus.cycles, 2 + 4 + 513);
+    }
+
+    // callbackがfalseを返すと、その命令の前で止まる
+    #[test]
+    fn test_run_with_callback_stops() {
+        let mut cpu = CPU::new();
+        // INXを10個並べる
+        cpu.load(vec![0xe8; 10]).unwrap();
+        cpu.reset();
+        cpu.run_with_callback(|cpu| cpu.register_x < 3).unwrap();
+
+        assert_eq!(cpu.register_x, 3);
+        assert_eq!(cpu.program_counter, 0x8003);
+    }
+
+    // 実装していない命令では止まってエラーを返す
+    #[test]
+    fn test_unknown_opcode_halts() {
+        let mut cpu = CPU::new();
+        // LDA #$01 の後の$02は公式の命令ではない
+        let result = cpu.load_and_run(vec![0xa9, 0x01, 0x02, 0x00]);
+
+        assert!(matches!(
+            result,
+            Err(CpuError::UnknownOpcode {
+                addr: 0x8002,
+                code: 0x02
+            })
+        ));
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(cpu.program_counter, 0x8002);
+        assert_eq!(cpu.bus.cycles, 2);
+    }
+
+    // 任意の番地へのロード
+    #[test]
+    fn test_load_at_keeps_reset_vector() {
+        let mut cpu = CPU::new();
+        cpu.set_reset_vector(0x0600);
+        cpu.load_at(0x0600, &[0xa9, 0x42, 0x00]).unwrap();
+        cpu.reset();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x42);
+        assert!(cpu.load_at(0xFFFF, &[0x00, 0x00]).is_err());
+    }
+
+    // MMC3のカウンタが0になるとIRQが入り、Iフラグが立っている間は待たされる
+    #[test]
+    fn test_mmc3_irq() {
+        use crate::cartridge::{Cartridge, Rom, test::create_rom};
+
+        let program = [
+            0xA9, 0x02, // LDA #$02
+            0x8D, 0x00, 0xC0, // STA $C000 ラッチ
+            0x8D, 0x01, 0xC0, // STA $C001 再読み込み
+            0x8D, 0x01, 0xE0, // STA $E001 IRQ有効
+            0x58, // CLI
+            0x4C, 0x0C, 0xE0, // JMP $E00C
+            0xA2, 0x42, // $E00F: LDX #$42
+            0x8D, 0x00, 0xE0, // STA $E000 IRQを下げる
+            0x00, // BRK
+        ];
+        let run = |cli: bool| {
+            // MMC3、PRG-ROM 32KB 最後の8KBは$E000に固定される
+            let mut raw = create_rom(0x40, 0, 2, 1);
+            let prg = 16;
+            raw[prg + 0x6000..prg + 0x6000 + program.len()].copy_from_slice(&program);
+            if !cli {
+                raw[prg + 0x600B] = 0x78;
+            }
+            raw[prg + 0x7FFC..prg + 0x8000].copy_from_slice(&[0x00, 0xE0, 0x0F, 0xE0]);
+            let mut cpu = CPU::new();
+            cpu.bus
+                .insert_cartridge(Cartridge::new(Rom::new(&raw).unwrap()).unwrap());
+            cpu.reset();
+            // PPUの代わりに4命令ごとにA12を上げる
+            let mut steps = 0;
+            cpu.run_with_callback(|cpu| {
+                steps += 1;
+                let addr = if steps % 4 == 0 { 0x1000 } else { 0x0000 };
+                cpu.bus.cartridge_mut().unwrap().ppu_bus(addr);
+                steps < 100
+            })
+            .unwrap();
+            cpu
+        };
+
+        let cpu = run(true);
+        assert_eq!(cpu.register_x, 0x42);
+        assert_ne!(cpu.status & INTERRUPT_DISABLE, 0);
+        // 戻り先はJMPの番地、ステータスはBREAKを落として積まれる
+        assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+        assert_eq!(cpu.peek(0x01FB), BREAK2);
+        assert_eq!(cpu.peek_u16(0x01FC), 0xE00C);
+
+        let cpu = run(false);
+        assert_eq!(cpu.register_x, 0);
+        assert!(cpu.bus.irq());
+    }
+
+    // 32KBを超えるプログラムはパニックせずにエラーになる
+    #[test]
+    fn test_load_too_large() {
+        let mut cpu = CPU::new();
+        assert!(matches!(
+            cpu.load(vec![0xEA; 0x8001]),
+            Err(LoadError::OutOfRange { addr: 0x8000, .. })
+        ));
+        assert!(matches!(
+            cpu.load_and_run(vec![0xEA; 0x8001]),
+            Err(CpuError::Load(_))
+        ));
+    }
+
+    // 読み込みはレジスタに書き込んだことにならない
+    #[test]
+    fn test_load_at_has_no_side_effects() {
+        let mut cpu = CPU::new();
+        // $4014に書けばOAM DMAが、$2004に書けばスプライトメモリへの書き込みが起きる
+        cpu.load_at(0x2000, &[0x11; 8]).unwrap();
+        cpu.load_at(0x4014, &[0x02]).unwrap();
+        cpu.bus.tick(1);
+        assert_eq!(cpu.bus.cycles, 1);
+        assert_eq!(cpu.bus.oam()[0], 0);
+    }
+
+    // Intel HEXの開始アドレスがリセットベクタになる
+    #[test]
+    fn test_load_image_sets_entry() {
+        let mut cpu = CPU::new();
+        let image =
+            loader::parse_intel_hex(":03C00000A905008F\n:040000050000C00037\n:00000001FF\n")
+                .unwrap();
+        cpu.load_image(&image).unwrap();
+        cpu.reset();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.program_counter, 0xC003);
+        assert_eq!(cpu.register_a, 0x05);
+    }
+
+    // 実行フックは命令ごとにアドレスとopscodeを受け取る
+    #[test]
+    fn test_execute_hook() {
+        let mut cpu = CPU::new();
+        let trace = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let log = trace.clone();
+        cpu.bus
+            .add_hook(Access::Execute, 0x8000..=0xFFFF, move |addr, opcode, _| {
+                log.borrow_mut().push((addr, opcode));
+            });
+        cpu.load_and_run(vec![0xa9, 0x0A, 0xAA, 0x00]).unwrap();
+
+        assert_eq!(
+            *trace.borrow(),
+            vec![(0x8000, 0xa9), (0x8002, 0xAA), (0x8003, 0x00)]
+        );
+    }
+
+    // peekはデバイスやスプライトメモリの状態を変えない
+    #[test]
+    fn test_peek_has_no_side_effects() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x2003, 0x10);
+        cpu.mem_write(0x2004, 0xAB);
+        cpu.mem_write(0x2003, 0x10);
+        cpu.mem_write_u16(0x0000, 0x1234);
+
+        assert_eq!(cpu.peek(0x2004), 0xAB);
+        assert_eq!(cpu.peek(0x200C), 0xAB);
+        assert_eq!(cpu.peek_u16(0x0000), 0x1234);
+        assert_eq!(cpu.mem_read(0x2004), 0xAB);
+    }
+
+    /* ADC */
+    #[test]
+    // 単純な足し算A+C(0)+M
+    fn test_adc_no_carry() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x69, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x20;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x30);
+        assert_eq!(cpu.status, 0x00); //変化しない
+    }
+    #[test]
+    // 単純な足し算A+C(1)+M
+    fn test_adc_has_carry() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x69, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x20;
+        cpu.status = 0x01; //carryフラグが立った状態でテスト
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x31);
+        assert_eq!(cpu.status, 0x00); //carryフラグが消滅する
+    }
+
+    #[test]
+    // overflow&zero+carryフラグが立つ
+    fn test_adc_occur_carry() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x69, 0x01, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0xFF;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x00);
+        assert_eq!(cpu.status, 0x03); //zero + carryフラグが立つ
+    }
+
+    #[test]
+    // overflow&zero+carryフラグが立つ
+    fn test_adc_occur_overflow_plus() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x69, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x7F;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x8F);
+        assert_eq!(cpu.status, 0xC0); //overflow+negativeフラグが立つ
+    }
+
+    #[test]
+    // carryを足してoverflowになる。
+    fn test_adc_occur_overflow_plus_with_carry() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x69, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x6F;
+        cpu.status = 0x01;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x80);
+        assert_eq!(cpu.status, 0xC0); //overflow+negativeフラグが立つ
+    }
+
+    #[test]
+    // carryを足してoverflowになる。
+    fn test_adc_occur_overflow_minus() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x69, 0x81, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x81;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x02);
+        assert_eq!(cpu.status, 0x41); //overflow+carryフラグが立つ
+    }
+
+    #[test]
+    // carryを足してoverflowになる。
+    fn test_adc_occur_overflow_minus_with_carry() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x69, 0x80, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x80;
+        cpu.status = 0x01;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(cpu.status, 0x41); //overflow+carryフラグが立つ
+    }
+
+    #[test]
+    // 符号が違う足し算のoverflow
+    fn test_adc_occur_no_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x69, 0x7F, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x82;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(cpu.status, 0x01); //carryフラグが立つ
+    }
+
+    /* SBC */
+    #[test]
+    fn test_sbc_no_carry() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe9, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x20;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x0F);
+        // carry判定でなければcarryフラグが立つ
+        assert_eq!(cpu.status, 0x01);
+    }
+
+    #[test]
+    fn test_sbc_has_carry() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe9, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x20;
+        cpu.status = 0x01;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x10);
+        assert_eq!(cpu.status, 0x01);
+    }
+
+    #[test]
+    fn test_sbc_occur_carry() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe9, 0x02, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x01;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0xFE);
+        // negativeが立つ
+        assert_eq!(cpu.status, 0x80);
+    }
+
+    #[test]
+    fn test_sbc_occur_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe9, 0x81, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x7F;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0xFD);
+        // negative+overflowが立つ
+        assert_eq!(cpu.status, 0xC0);
+    }
+
+    #[test]
+    fn test_sbc_occur_overflow_with_carry() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe9, 0x81, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x7F;
+        cpu.status = 0x01;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0xFE);
+        // negative+overflowが立つ
+        assert_eq!(cpu.status, 0xC0);
+    }
+
+    #[test]
+    fn test_sbc_no_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe9, 0x7F, 0x00]).unwrap();
+        cpu.reset();
+        cpu.register_a = 0x7E;
+        cpu.status = 0x01;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0xFF);
+        // negativeが立つ
+        assert_eq!(cpu.status, 0x80);
+    }
+}
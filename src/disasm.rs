@@ -0,0 +1,63 @@
+use crate::bus::Bus;
+use crate::cpu::AddressingMode;
+use crate::opcodes;
+
+// addr番地の命令を逆アセンブルして文字列と命令長を返す
+// peekだけを使うのでエミュレーションの状態は変わらない
+pub fn disassemble(bus: &Bus, addr: u16) -> (String, u16) {
+    let code = bus.peek(addr);
+    let Some(opcode) = opcodes::lookup(code) else {
+        return (format!(".DB ${:02X}", code), 1);
+    };
+    let lo = bus.peek(addr.wrapping_add(1));
+    let hi = bus.peek(addr.wrapping_add(2));
+    let word = (hi as u16) << 8 | lo as u16;
+
+    let operand = match opcode.mode {
+        AddressingMode::Immediate => format!(" #${:02X}", lo),
+        AddressingMode::ZeroPage => format!(" ${:02X}", lo),
+        AddressingMode::ZeroPage_X => format!(" ${:02X},X", lo),
+        AddressingMode::ZeroPage_Y => format!(" ${:02X},Y", lo),
+        AddressingMode::Absolute => format!(" ${:04X}", word),
+        AddressingMode::Absolute_X => format!(" ${:04X},X", word),
+        AddressingMode::Absolute_Y => format!(" ${:04X},Y", word),
+        AddressingMode::Indirect_X => format!(" (${:02X},X)", lo),
+        AddressingMode::Indirect_Y => format!(" (${:02X}),Y", lo),
+        // 分岐は飛び先の番地で表す
+        AddressingMode::Relative => {
+            let target = addr.wrapping_add(2).wrapping_add(lo as i8 as u16);
+            format!(" ${:04X}", target)
+        }
+        AddressingMode::Indirect => format!(" (${:04X})", word),
+        AddressingMode::NoneAddressing => String::new(),
+    };
+    (format!("{}{}", opcode.mnemonic, operand), opcode.len as u16)
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let mut bus = Bus::new();
+        for (i, byte) in [0xb5, 0x10, 0x8d, 0x14, 0x40, 0xb1, 0x20, 0xff]
+            .iter()
+            .enumerate()
+        {
+            bus.mem_write(0x8000 + i as u16, *byte);
+        }
+        assert_eq!(disassemble(&bus, 0x8000), ("LDA $10,X".to_string(), 2));
+        assert_eq!(disassemble(&bus, 0x8002), ("STA $4014".to_string(), 3));
+        assert_eq!(disassemble(&bus, 0x8005), ("LDA ($20),Y".to_string(), 2));
+        assert_eq!(disassemble(&bus, 0x8007), (".DB $FF".to_string(), 1));
+
+        // BNE -3 と JMP ($1234)
+        for (i, byte) in [0xd0, 0xfd, 0x6c, 0x34, 0x12].iter().enumerate() {
+            bus.mem_write(0x8100 + i as u16, *byte);
+        }
+        assert_eq!(disassemble(&bus, 0x8100), ("BNE $80FF".to_string(), 2));
+        assert_eq!(disassemble(&bus, 0x8102), ("JMP ($1234)".to_string(), 3));
+    }
+}
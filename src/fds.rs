@@ -0,0 +1,219 @@
+use crate::cartridge::{ConsoleType, Mirroring, Rom, RomError, Timing};
+
+// ディスクシステムのディスクイメージ (.fds/.qd)
+// .fdsは各面65500byteでブロックのCRCとギャップを含まない 先頭に16byteのヘッダが付くことがある
+// .qdは各面65536byteで、ブロックごとに2byteのCRCを含む
+
+// マッパー番号の代わり (iNESでディスクシステム用に予約されている番号)
+pub const MAPPER: u16 = 20;
+pub const SIDE_SIZE: usize = 65500;
+pub const BIOS_SIZE: usize = 0x2000;
+const QD_SIDE_SIZE: usize = 0x10000;
+const HEADER: &[u8; 4] = b"FDS\x1A";
+const HEADER_SIZE: usize = 16;
+const DISK_INFO: &[u8] = b"*NINTENDO-HVC*";
+
+// ドライブが読む時のギャップ 先頭は28300bit、ブロックの間は976bit
+pub const LEADING_GAP: usize = 28300 / 8;
+pub const BLOCK_GAP: usize = 976 / 8;
+
+pub fn is_disk_image(raw: &[u8]) -> bool {
+    raw.starts_with(HEADER)
+        || (raw.get(1..1 + DISK_INFO.len()) == Some(DISK_INFO)
+            && (raw.len().is_multiple_of(SIDE_SIZE) || raw.len().is_multiple_of(QD_SIDE_SIZE)))
+}
+
+// ブロックの大きさ 種類1-4以外ならそこで面が終わる
+// ファイルの中身(4)の大きさは直前のファイルヘッダ(3)に書かれている
+fn block_len(side: &[u8], pos: usize, file_size: usize) -> Option<usize> {
+    match side.get(pos)? {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+    .filter(|len| pos + len <= side.len())
+}
+
+// ブロックを順に返す crcがtrueなら各ブロックの後ろの2byteを飛ばす
+fn blocks(side: &[u8], crc: bool) -> Vec<&[u8]> {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    let mut file_size = 0;
+    while let Some(len) = block_len(side, pos, file_size) {
+        let block = &side[pos..pos + len];
+        if block[0] == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+        blocks.push(block);
+        pos += len + if crc { 2 } else { 0 };
+    }
+    blocks
+}
+
+// ディスクのCRC (多項式0x8408、下位bitから)
+pub fn update_crc(crc: u16, data: u8) -> u16 {
+    let mut crc = crc;
+    for bit in 0..8 {
+        let carry = crc & 1 != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= 0x8408;
+        }
+        if data & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+    crc
+}
+
+// ドライブのヘッドが読むバイト列にする
+// 各ブロックの前にギャップとその終わりの印(0x80)、後ろにCRCを置く
+pub fn encode_side(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEADING_GAP];
+    for block in blocks(side, false) {
+        let mut crc = update_crc(0, 0x80);
+        raw.push(0x80);
+        for &byte in block {
+            crc = update_crc(crc, byte);
+        }
+        raw.extend_from_slice(block);
+        crc = update_crc(update_crc(crc, 0), 0);
+        raw.extend(crc.to_le_bytes());
+        raw.extend(vec![0; BLOCK_GAP]);
+    }
+    raw
+}
+
+// ディスクイメージとBIOSからRomを作る
+pub fn load(raw: &[u8], bios: &[u8]) -> Result<Rom, RomError> {
+    if bios.len() != BIOS_SIZE {
+        return Err(RomError::Unsupported("FDS BIOS that is not 8KB"));
+    }
+    let sides: Vec<Vec<u8>> = if raw.starts_with(HEADER) {
+        raw[HEADER_SIZE.min(raw.len())..]
+            .chunks(SIDE_SIZE)
+            .map(|side| side.to_vec())
+            .collect()
+    } else if raw.len().is_multiple_of(SIDE_SIZE) {
+        raw.chunks(SIDE_SIZE).map(|side| side.to_vec()).collect()
+    } else if raw.len().is_multiple_of(QD_SIDE_SIZE) {
+        // CRCを取り除いて.fdsと同じ並びにする
+        raw.chunks(QD_SIDE_SIZE)
+            .map(|side| {
+                let mut data: Vec<u8> = blocks(side, true).concat();
+                data.resize(SIDE_SIZE, 0);
+                data
+            })
+            .collect()
+    } else {
+        return Err(RomError::BadMagic);
+    };
+    if sides.is_empty() || sides.iter().any(|side| !side.starts_with(&[1])) {
+        return Err(RomError::Unsupported(
+            "disk image without a disk info block",
+        ));
+    }
+
+    Ok(Rom {
+        prg_rom: bios.to_vec(),
+        chr_rom: Vec::new(),
+        trainer: None,
+        mapper: MAPPER,
+        submapper: 0,
+        screen_mirroring: Mirroring::Horizontal,
+        // 書き換えたディスクを保存する
+        battery: true,
+        nes2: false,
+        prg_ram_size: 0x8000,
+        prg_nvram_size: 0,
+        chr_ram_size: 0x2000,
+        chr_nvram_size: 0,
+        timing: Timing::Ntsc,
+        console_type: ConsoleType::Nes,
+        misc_rom: Vec::new(),
+        expansion_device: 0,
+        disk_sides: sides,
+    })
+}
+
+// テスト
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // ファイルを1つ持つ面を組み立てる
+    pub fn create_side(file: &[u8]) -> Vec<u8> {
+        let mut side = vec![1];
+        side.extend(DISK_INFO);
+        side.resize(56, 0);
+        side.extend([2, 1]);
+        let mut header = vec![3, 0, 0];
+        header.extend(b"FILENAME");
+        header.extend([0x00, 0x60]);
+        header.extend((file.len() as u16).to_le_bytes());
+        header.push(0);
+        side.extend(header);
+        side.push(4);
+        side.extend(file);
+        side.resize(SIDE_SIZE, 0);
+        side
+    }
+
+    #[test]
+    fn test_load() {
+        let side = create_side(&[0xAA, 0xBB]);
+        let bios = vec![0; BIOS_SIZE];
+
+        let mut raw = HEADER.to_vec();
+        raw.push(2);
+        raw.resize(HEADER_SIZE, 0);
+        raw.extend(&side);
+        raw.extend(&side);
+        assert!(is_disk_image(&raw));
+        let rom = load(&raw, &bios).unwrap();
+        assert_eq!(rom.mapper, MAPPER);
+        assert_eq!(rom.disk_sides.len(), 2);
+        assert_eq!(rom.disk_sides[1], side);
+
+        // ヘッダ無し
+        assert!(is_disk_image(&side));
+        assert_eq!(load(&side, &bios).unwrap().disk_sides.len(), 1);
+
+        assert!(load(&side, &[0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_qd() {
+        // ブロックごとにCRCが付く
+        let side = create_side(&[0xAA, 0xBB]);
+        let mut qd = Vec::new();
+        for block in blocks(&side, false) {
+            qd.extend(block);
+            qd.extend([0x12, 0x34]);
+        }
+        qd.resize(QD_SIDE_SIZE, 0);
+        assert!(is_disk_image(&qd));
+        let rom = load(&qd, &[0; BIOS_SIZE]).unwrap();
+        assert_eq!(rom.disk_sides, vec![side]);
+    }
+
+    #[test]
+    fn test_encode_side() {
+        let side = create_side(&[0xAA, 0xBB]);
+        let raw = encode_side(&side);
+        // ギャップ、0x80、ディスク情報ブロック
+        assert!(raw[..LEADING_GAP].iter().all(|&b| b == 0));
+        assert_eq!(raw[LEADING_GAP], 0x80);
+        assert_eq!(raw[LEADING_GAP + 1], 1);
+        // 4つのブロックそれぞれに印・CRC・ギャップが付く
+        assert_eq!(
+            raw.len(),
+            LEADING_GAP + (56 + 2 + 16 + 3) + 4 * (1 + 2 + BLOCK_GAP)
+        );
+        // CRCまで含めて計算すると0になる
+        let block = &raw[LEADING_GAP..LEADING_GAP + 1 + 56 + 2];
+        assert_eq!(block.iter().fold(0, |crc, &b| update_crc(crc, b)), 0);
+    }
+}
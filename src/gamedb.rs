@@ -0,0 +1,372 @@
+use crate::cartridge::{Mirroring, Rom, Timing};
+use crate::hash::{self, Crc32, Sha1};
+use std::fmt;
+use std::sync::OnceLock;
+
+// ヘッダが間違っているダンプを正すためのゲームデータベース
+// PRG-ROMとCHR-ROMをつなげたもの（ヘッダとトレーナーを除く）のハッシュで引く
+const BUILTIN: &str = include_str!("gamedb.txt");
+
+// データベースの1行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameInfo {
+    pub crc32: u32,
+    // 書かれていればCRC32が一致した後にSHA-1でも確かめる
+    pub sha1: Option<[u8; 20]>,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    // NES 2.0のbyte 15と同じ番号
+    pub expansion_device: u8,
+    pub title: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DbError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for DbError {}
+
+// ヘッダから書き換えた項目
+#[derive(Debug, PartialEq, Eq)]
+pub struct Correction {
+    pub field: &'static str,
+    pub from: String,
+    pub to: String,
+}
+
+impl fmt::Display for Correction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.from, self.to)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct GameDb {
+    games: Vec<GameInfo>,
+}
+
+impl GameDb {
+    // 1行に1本 空白区切りで
+    // crc32 sha1 mapper submapper mirroring battery prg_ram prg_nvram chr_ram chr_nvram region input [title]
+    // sha1は省略時'-'、mirroringはH/V/4、regionはNTSC/PAL/MULTI/DENDY、大きさはbyte
+    // '#'から行末まではコメント
+    pub fn parse(text: &str) -> Result<GameDb, DbError> {
+        let mut games = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |reason| DbError {
+                line: index + 1,
+                reason,
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 12 {
+                return Err(error("expected at least 12 fields"));
+            }
+            let crc32 = u32::from_str_radix(fields[0], 16).map_err(|_| error("bad CRC32"))?;
+            let sha1 = match fields[1] {
+                "-" => None,
+                text => Some(
+                    hash::from_hex(text)
+                        .and_then(|bytes| bytes.try_into().ok())
+                        .ok_or(error("bad SHA-1"))?,
+                ),
+            };
+            let number = |field: &str| field.parse::<usize>().map_err(|_| error("bad number"));
+            let mirroring = match fields[4] {
+                "H" => Mirroring::Horizontal,
+                "V" => Mirroring::Vertical,
+                "4" => Mirroring::FourScreen,
+                _ => return Err(error("bad mirroring")),
+            };
+            let battery = match fields[5] {
+                "0" => false,
+                "1" => true,
+                _ => return Err(error("bad battery flag")),
+            };
+            let timing = match fields[10] {
+                "NTSC" => Timing::Ntsc,
+                "PAL" => Timing::Pal,
+                "MULTI" => Timing::MultiRegion,
+                "DENDY" => Timing::Dendy,
+                _ => return Err(error("bad region")),
+            };
+            let mapper = number(fields[2])?;
+            let submapper = number(fields[3])?;
+            let expansion_device = number(fields[11])?;
+            if mapper > 0xFFF || submapper > 0x0F || expansion_device > 0x3F {
+                return Err(error("number out of range"));
+            }
+            games.push(GameInfo {
+                crc32,
+                sha1,
+                mapper: mapper as u16,
+                submapper: submapper as u8,
+                mirroring,
+                battery,
+                prg_ram_size: number(fields[6])?,
+                prg_nvram_size: number(fields[7])?,
+                chr_ram_size: number(fields[8])?,
+                chr_nvram_size: number(fields[9])?,
+                timing,
+                expansion_device: expansion_device as u8,
+                title: fields[12..].join(" "),
+            });
+        }
+        Ok(GameDb { games })
+    }
+
+    // 組み込みのデータベース
+    pub fn builtin() -> &'static GameDb {
+        static DB: OnceLock<GameDb> = OnceLock::new();
+        DB.get_or_init(|| GameDb::parse(BUILTIN).expect("built-in game database is malformed"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    pub fn find(&self, rom: &Rom) -> Option<&GameInfo> {
+        let mut crc = Crc32::new();
+        crc.update(&rom.prg_rom);
+        crc.update(&rom.chr_rom);
+        let crc = crc.finish();
+        // SHA-1はCRC32が一致した時だけ計算する
+        self.games.iter().find(|game| game.crc32 == crc)?;
+        let mut sha1 = Sha1::new();
+        sha1.update(&rom.prg_rom);
+        sha1.update(&rom.chr_rom);
+        self.find_hash(crc, &sha1.finish())
+    }
+
+    // PRG-ROMとCHR-ROMのハッシュで引く
+    pub fn find_hash(&self, crc32: u32, sha1: &[u8; 20]) -> Option<&GameInfo> {
+        self.games
+            .iter()
+            .find(|game| game.crc32 == crc32 && game.sha1.is_none_or(|expected| expected == *sha1))
+    }
+
+    // 一致するゲームがあればヘッダの内容を書き換え、変えた項目を返す
+    pub fn correct(&self, rom: &mut Rom) -> Option<(&GameInfo, Vec<Correction>)> {
+        let game = self.find(rom)?;
+        Some((game, game.apply(rom)))
+    }
+}
+
+impl GameInfo {
+    // ヘッダの内容をデータベースの値に書き換え、変えた項目を返す
+    pub fn apply(&self, rom: &mut Rom) -> Vec<Correction> {
+        let mut corrections = Vec::new();
+        fn update<T: PartialEq + fmt::Debug>(
+            corrections: &mut Vec<Correction>,
+            field: &'static str,
+            value: &mut T,
+            correct: T,
+        ) {
+            if *value != correct {
+                corrections.push(Correction {
+                    field,
+                    from: format!("{:?}", value),
+                    to: format!("{:?}", correct),
+                });
+                *value = correct;
+            }
+        }
+        let c = &mut corrections;
+        update(c, "mapper", &mut rom.mapper, self.mapper);
+        update(c, "submapper", &mut rom.submapper, self.submapper);
+        update(c, "mirroring", &mut rom.screen_mirroring, self.mirroring);
+        update(c, "battery", &mut rom.battery, self.battery);
+        // iNES 1.0のヘッダはRAMの大きさを持たず、既定の8KBを電池の有無でRAMかNVRAMに振り分けている
+        // 振り分けは直した電池に合わせ、データベースで0の項目は既定の大きさのまま残す
+        let ines = !rom.nes2;
+        if ines {
+            let total = rom.prg_ram_size + rom.prg_nvram_size;
+            if rom.battery {
+                (rom.prg_ram_size, rom.prg_nvram_size) = (0, total);
+            } else {
+                (rom.prg_ram_size, rom.prg_nvram_size) = (total, 0);
+            }
+        }
+        for (field, value, correct) in [
+            ("PRG-RAM", &mut rom.prg_ram_size, self.prg_ram_size),
+            ("PRG-NVRAM", &mut rom.prg_nvram_size, self.prg_nvram_size),
+            ("CHR-RAM", &mut rom.chr_ram_size, self.chr_ram_size),
+            ("CHR-NVRAM", &mut rom.chr_nvram_size, self.chr_nvram_size),
+        ] {
+            if !(ines && correct == 0) {
+                update(c, field, value, correct);
+            }
+        }
+        update(c, "region", &mut rom.timing, self.timing);
+        // 0は「書かれていない」なので、埋めるだけで直したことにはしない
+        if rom.expansion_device == 0 {
+            rom.expansion_device = self.expansion_device;
+        } else {
+            update(
+                c,
+                "input device",
+                &mut rom.expansion_device,
+                self.expansion_device,
+            );
+        }
+        corrections
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn db_line(rom: &Rom, sha1: bool, rest: &str) -> String {
+        let mut data = rom.prg_rom.clone();
+        data.extend(&rom.chr_rom);
+        let sha1 = if sha1 {
+            hash::to_hex(&hash::sha1(&data))
+        } else {
+            "-".to_string()
+        };
+        format!("{:08x} {} {}", hash::crc32(&data), sha1, rest)
+    }
+
+    #[test]
+    fn test_builtin() {
+        // 組み込みのデータベースは読めなければならない
+        let _ = GameDb::builtin().len();
+    }
+
+    // 組み込みの行で、ヘッダの間違ったダンプが正される
+    #[test]
+    fn test_builtin_corrects_header() {
+        let sha1: [u8; 20] = hash::from_hex("ea343f4e445a9050d4b4fbac2c77d0693b1d0922")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let game = GameDb::builtin().find_hash(0x3337EC46, &sha1).unwrap();
+        assert_eq!(game.title, "Super Mario Bros. (World)");
+        // SHA-1が違えば別物
+        assert!(GameDb::builtin().find_hash(0x3337EC46, &[0; 20]).is_none());
+
+        // マッパー1・水平ミラーリング・電池付きと書かれたヘッダ
+        let mut rom = Rom::new(&create_rom(0x12, 0, 2, 1)).unwrap();
+        let corrections = game.apply(&mut rom);
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(!rom.battery);
+        assert_eq!(rom.expansion_device, 1);
+        assert_eq!(
+            corrections
+                .iter()
+                .map(|c| c.field)
+                .take(3)
+                .collect::<Vec<_>>(),
+            vec!["mapper", "mirroring", "battery"]
+        );
+    }
+
+    // 正しいiNES 1.0のヘッダには既定のRAMの大きさを理由に何も報告しない
+    #[test]
+    fn test_ines_defaults_not_corrected() {
+        let mut rom = Rom::new(&create_rom(0x01, 0, 2, 1)).unwrap();
+        let text = db_line(&rom, true, "0 0 V 0 0 0 0 0 NTSC 1 Test Game");
+        let db = GameDb::parse(&text).unwrap();
+        let (_, corrections) = db.correct(&mut rom).unwrap();
+        assert!(corrections.is_empty(), "{:?}", corrections);
+        assert_eq!(rom.prg_ram_size, 0x2000);
+        assert_eq!(rom.expansion_device, 1);
+
+        // NES 2.0のヘッダに書かれた大きさは直す
+        let mut raw = create_rom(0x01, 0x08, 2, 1);
+        raw[10] = 0x07;
+        let mut rom = Rom::new(&raw).unwrap();
+        let (_, corrections) = db.correct(&mut rom).unwrap();
+        assert_eq!(corrections[0].to_string(), "PRG-RAM: 8192 -> 0");
+        assert_eq!(rom.prg_ram_size, 0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(GameDb::parse("# only a comment\n\n").unwrap().is_empty());
+        assert_eq!(
+            GameDb::parse("1234 - 4 0 H 1").unwrap_err(),
+            DbError {
+                line: 1,
+                reason: "expected at least 12 fields"
+            }
+        );
+        assert_eq!(
+            GameDb::parse("\nxyz - 4 0 H 1 0 8192 0 0 NTSC 1")
+                .unwrap_err()
+                .reason,
+            "bad CRC32"
+        );
+        assert_eq!(
+            GameDb::parse("0 - 4 0 X 1 0 8192 0 0 NTSC 1")
+                .unwrap_err()
+                .reason,
+            "bad mirroring"
+        );
+    }
+
+    #[test]
+    fn test_correct_header() {
+        let mut rom = Rom::new(&create_rom(0x10, 0, 2, 1)).unwrap();
+        let text = db_line(&rom, true, "4 0 V 1 0 8192 0 0 NTSC 1 Test Game");
+        let db = GameDb::parse(&text).unwrap();
+        let (game, corrections) = db.correct(&mut rom).unwrap();
+        assert_eq!(game.title, "Test Game");
+        assert_eq!(rom.mapper, 4);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+        assert_eq!(rom.expansion_device, 1);
+        assert_eq!(corrections[0].to_string(), "mapper: 1 -> 4");
+        // 既定の8KBがNVRAMに移るだけで、RAMの大きさは直したことにしない
+        assert_eq!(
+            corrections.iter().map(|c| c.field).collect::<Vec<_>>(),
+            vec!["mapper", "mirroring", "battery"]
+        );
+
+        // 正しくなった後は何も変えない
+        assert!(db.correct(&mut rom).unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn test_sha1_mismatch() {
+        let rom = Rom::new(&create_rom(0, 0, 1, 1)).unwrap();
+        let good = db_line(&rom, false, "0 0 H 0 8192 0 0 0 NTSC 0");
+        assert!(GameDb::parse(&good).unwrap().find(&rom).is_some());
+
+        // CRC32が同じでもSHA-1が違えば別物
+        let crc = good.split_whitespace().next().unwrap();
+        let bad = format!("{} {} 0 0 H 0 8192 0 0 0 NTSC 0", crc, "00".repeat(20));
+        assert!(GameDb::parse(&bad).unwrap().find(&rom).is_none());
+
+        let other = Rom::new(&create_rom(0, 0, 2, 1)).unwrap();
+        assert!(GameDb::parse(&good).unwrap().find(&other).is_none());
+    }
+}
@@ -0,0 +1,70 @@
+use std::ops::RangeInclusive;
+
+// フックが呼ばれるメモリアクセスの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    // 命令の読み込み（valueはopscode）
+    Execute,
+}
+
+// 取り外しに使うフックの識別子 番号は使い回さない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookId(pub(crate) u32);
+
+// アドレス・値・その時点のサイクル数を受け取るコールバック
+pub type HookFn = Box<dyn FnMut(u16, u8, u64)>;
+
+struct Hook {
+    id: HookId,
+    access: Access,
+    range: RangeInclusive<u16>,
+    callback: HookFn,
+}
+
+// 登録済みのフック一覧
+// バスはフックが一つも無い時はこれを持たないので、通常のアクセスには影響しない
+// 取り外すと消えるので、識別子の番号はバスが数える
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+}
+
+impl Hooks {
+    pub fn add(
+        &mut self,
+        id: HookId,
+        access: Access,
+        range: RangeInclusive<u16>,
+        callback: HookFn,
+    ) -> HookId {
+        self.hooks.push(Hook {
+            id,
+            access,
+            range,
+            callback,
+        });
+        id
+    }
+
+    // 取り外せたらtrue
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.hooks.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    // アクセスに該当するフックをすべて呼ぶ
+    pub fn call(&mut self, access: Access, addr: u16, value: u8, cycle: u64) {
+        for hook in self.hooks.iter_mut() {
+            if hook.access == access && hook.range.contains(&addr) {
+                (hook.callback)(addr, value, cycle);
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::fmt;
+
+// DEFLATE (RFC 1951) の展開
+// ZIPとgzipの中身に使う 圧縮は実装しない
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InflateError {
+    // 最後のブロックの前にデータが終わっている
+    Truncated,
+    // 符号や距離がおかしい
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InflateError::Truncated => write!(f, "compressed data is truncated"),
+            InflateError::Corrupt(reason) => write!(f, "compressed data is corrupt: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for InflateError {}
+
+const MAX_BITS: usize = 15;
+
+// 長さ符号257-285の基本値と追加bit数
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// 距離符号0-29の基本値と追加bit数
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// 動的ハフマンで符号長の符号長が並ぶ順番
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// 下位bitから読む
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+    bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            bit: 0,
+            bits: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, InflateError> {
+        while self.bits < count {
+            let byte = *self.data.get(self.pos).ok_or(InflateError::Truncated)?;
+            self.pos += 1;
+            self.bit |= (byte as u32) << self.bits;
+            self.bits += 8;
+        }
+        let value = self.bit & ((1 << count) - 1);
+        self.bit >>= count;
+        self.bits -= count;
+        Ok(value)
+    }
+
+    // 無圧縮ブロックはバイト境界から始まる
+    fn align(&mut self) {
+        self.bit = 0;
+        self.bits = 0;
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], InflateError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(InflateError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+// 正規ハフマン符号 符号長ごとの数と、符号順に並べた記号
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, InflateError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        // 符号が多すぎないか (足りないのは1つだけの距離符号などで許される)
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err(InflateError::Corrupt("oversubscribed Huffman code"));
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    // 1bitずつ読んで、その長さの符号の範囲に入ったら決まる
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, InflateError> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError::Corrupt("invalid Huffman code"))
+    }
+}
+
+// 固定ハフマンの符号表
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (
+        Huffman::new(&lengths).unwrap(),
+        Huffman::new(&[5; 30]).unwrap(),
+    )
+}
+
+// 動的ハフマンの符号表はブロックの先頭に符号長として書かれている
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(InflateError::Corrupt("too many Huffman codes"));
+    }
+
+    let mut lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[index] = reader.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code.decode(reader)?;
+        // 16は直前の長さを、17/18は0を繰り返す
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 if i > 0 => (lengths[i - 1], 3 + reader.bits(2)? as usize),
+            16 => return Err(InflateError::Corrupt("repeat with no previous length")),
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(InflateError::Corrupt("too many code lengths"));
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(InflateError::Corrupt("missing end-of-block code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literals])?,
+        Huffman::new(&lengths[literals..])?,
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literal: &Huffman,
+    distance: &Huffman,
+) -> Result<(), InflateError> {
+    loop {
+        let symbol = literal.decode(reader)? as usize;
+        match symbol {
+            0..=255 => {
+                if out.len() >= limit {
+                    return Err(TOO_LARGE);
+                }
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            257..=285 => {
+                let index = symbol - 257;
+                let len =
+                    LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = distance.decode(reader)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err(InflateError::Corrupt("invalid distance code"));
+                }
+                let dist = DISTANCE_BASE[index] as usize
+                    + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                if dist > out.len() {
+                    return Err(InflateError::Corrupt("distance before start of output"));
+                }
+                if out.len() + len > limit {
+                    return Err(TOO_LARGE);
+                }
+                // 重なっていてもよいので1byteずつ写す
+                let start = out.len() - dist;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+            _ => return Err(InflateError::Corrupt("invalid length code")),
+        }
+    }
+}
+
+const TOO_LARGE: InflateError = InflateError::Corrupt("output is larger than expected");
+
+// 展開した中身がlimit byteを超えたらそこで止める 小さな爆弾でメモリを使い切らないようにする
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            // 無圧縮 長さとその補数が続く
+            0 => {
+                reader.align();
+                let header = reader.bytes(4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err(InflateError::Corrupt("stored block length mismatch"));
+                }
+                if out.len() + len as usize > limit {
+                    return Err(TOO_LARGE);
+                }
+                out.extend_from_slice(reader.bytes(len as usize)?);
+            }
+            1 => {
+                let (literal, distance) = fixed_tables();
+                inflate_block(&mut reader, &mut out, limit, &literal, &distance)?;
+            }
+            2 => {
+                let (literal, distance) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, limit, &literal, &distance)?;
+            }
+            _ => return Err(InflateError::Corrupt("invalid block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::hash::from_hex;
+
+    #[test]
+    fn test_stored() {
+        let data = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'N', b'E', b'S'];
+        assert_eq!(inflate(&data, usize::MAX).unwrap(), b"NES");
+        assert_eq!(
+            inflate(&[0x01, 0x03, 0x00, 0xFC, 0xFE], usize::MAX),
+            Err(InflateError::Corrupt("stored block length mismatch"))
+        );
+        assert_eq!(
+            inflate(&data[..6], usize::MAX),
+            Err(InflateError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_fixed() {
+        // zlib.compressobj(9, zlib.DEFLATED, -15).compress(b"abcabcabcabc")
+        let data = from_hex(FIXED).unwrap();
+        assert_eq!(inflate(&data, usize::MAX).unwrap(), b"abcabcabcabc");
+    }
+
+    #[test]
+    fn test_dynamic() {
+        // 偏りのある80byteの乱数列は動的ハフマンになる
+        let mut x = 1u32;
+        let expected: Vec<u8> = (0..80)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7FFF_FFFF;
+                b"aaaaaabc"[(x >> 16) as usize % 8]
+            })
+            .collect();
+        let data = from_hex(DYNAMIC).unwrap();
+        assert_eq!(data[0] >> 1 & 0x03, 2);
+        assert_eq!(inflate(&data, usize::MAX).unwrap(), expected);
+    }
+
+    // 固定ハフマンで0を1つ置き、距離1・長さ258の繰り返しをcount回続ける
+    pub fn create_bomb(count: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut bits = 0u32;
+        let mut len = 0;
+        // ハフマン符号は上位bitから詰める
+        let mut put = |code: u32, width: u32, out: &mut Vec<u8>| {
+            for i in (0..width).rev() {
+                bits |= (code >> i & 1) << len;
+                len += 1;
+                if len == 8 {
+                    out.push(bits as u8);
+                    bits = 0;
+                    len = 0;
+                }
+            }
+        };
+        // 最後のブロック、固定ハフマン ヘッダは下位bitからなので並びを逆にする
+        put(0b110, 3, &mut out);
+        put(0x30, 8, &mut out);
+        for _ in 0..count {
+            // 長さ258は285、距離1は0
+            put(0b1100_0101, 8, &mut out);
+            put(0, 5, &mut out);
+        }
+        put(0, 7, &mut out);
+        put(0, 7, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_limit() {
+        let data = from_hex(FIXED).unwrap();
+        assert_eq!(inflate(&data, 12).unwrap(), b"abcabcabcabc");
+        assert_eq!(inflate(&data, 11), Err(TOO_LARGE));
+        let data = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'N', b'E', b'S'];
+        assert_eq!(inflate(&data, 2), Err(TOO_LARGE));
+
+        // 500byte足らずで64KBを超える
+        let bomb = create_bomb(300);
+        assert!(bomb.len() < 500);
+        assert_eq!(inflate(&bomb, usize::MAX).unwrap().len(), 1 + 258 * 300);
+        assert_eq!(inflate(&bomb, 0x10000), Err(TOO_LARGE));
+    }
+
+    const FIXED: &str = "4b4c4a4e842100";
+    const DYNAMIC: &str =
+        "3d8a870d000008c26ea5fcff831807445251d00839e998a08daf3fa57ba6cffdc5ae33a200";
+}
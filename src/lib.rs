@@ -0,0 +1,21 @@
+pub mod apu;
+pub mod archive;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod disasm;
+pub mod fds;
+pub mod gamedb;
+pub mod hash;
+pub mod hooks;
+pub mod inflate;
+pub mod loader;
+pub mod mapper;
+pub mod nsf;
+pub mod nsf_player;
+pub mod opcodes;
+pub mod patch;
+pub mod save;
+pub mod unif;
+pub mod vs;
+pub mod wav;
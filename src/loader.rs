@@ -0,0 +1,318 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// メモリに書き込む連続したバイト列
+#[derive(Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: u16,
+    pub data: Vec<u8>,
+}
+
+// ローダーが読み取ったプログラム全体
+// entryはファイルに開始アドレスが書かれていた場合だけSomeになる
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub entry: Option<u16>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // 64KBのアドレス空間に収まらない
+    OutOfRange { addr: u32, len: usize },
+    // レコードの書式が壊れている
+    Syntax { line: usize, reason: &'static str },
+    // レコードのチェックサムが合わない
+    Checksum { line: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::OutOfRange { addr, len } => write!(
+                f,
+                "{} bytes at ${:04X} do not fit in the 64KB address space",
+                len, addr
+            ),
+            LoadError::Syntax { line, reason } => write!(f, "line {}: {}", line, reason),
+            LoadError::Checksum { line } => write!(f, "line {}: checksum mismatch", line),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+// addrからlenバイトが64KBに収まるか確かめる
+pub fn check_range(addr: u32, len: usize) -> Result<(), LoadError> {
+    if addr as usize + len > 0x10000 {
+        return Err(LoadError::OutOfRange { addr, len });
+    }
+    Ok(())
+}
+
+impl Image {
+    // 生のバイナリをaddrから配置する
+    pub fn raw(addr: u16, bytes: &[u8]) -> Result<Image, LoadError> {
+        check_range(addr as u32, bytes.len())?;
+        Ok(Image {
+            segments: vec![Segment {
+                addr,
+                data: bytes.to_vec(),
+            }],
+            entry: None,
+        })
+    }
+
+    // 新しいセグメントを追加する 直前のセグメントと連続していればつなげる
+    fn push(&mut self, addr: u32, data: &[u8]) -> Result<(), LoadError> {
+        check_range(addr, data.len())?;
+        if let Some(last) = self.segments.last_mut()
+            && last.addr as usize + last.data.len() == addr as usize
+        {
+            last.data.extend_from_slice(data);
+            return Ok(());
+        }
+        self.segments.push(Segment {
+            addr: addr as u16,
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+}
+
+// 16進文字列をバイト列に変換する
+fn decode_hex(text: &str, line: usize) -> Result<Vec<u8>, LoadError> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(LoadError::Syntax {
+            line,
+            reason: "odd number of hex digits",
+        });
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| LoadError::Syntax {
+                line,
+                reason: "invalid hex digit",
+            })
+        })
+        .collect()
+}
+
+// Intel HEX形式 (:LLAAAATT[DD...]CC)
+pub fn parse_intel_hex(text: &str) -> Result<Image, LoadError> {
+    let mut image = Image::default();
+    // レコード種別02/04で設定される上位アドレス
+    let mut base: u32 = 0;
+
+    for (i, record) in text.lines().enumerate() {
+        let line = i + 1;
+        let record = record.trim();
+        if record.is_empty() {
+            continue;
+        }
+        let Some(hex) = record.strip_prefix(':') else {
+            return Err(LoadError::Syntax {
+                line,
+                reason: "record does not start with ':'",
+            });
+        };
+        let bytes = decode_hex(hex, line)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(LoadError::Syntax {
+                line,
+                reason: "record length does not match byte count",
+            });
+        }
+        // 全バイトの合計が0になる
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(LoadError::Checksum { line });
+        }
+
+        let offset = (bytes[1] as u32) << 8 | bytes[2] as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            // データ
+            0x00 => image.push(base + offset, data)?,
+            // 終端
+            0x01 => break,
+            // 拡張セグメントアドレス
+            0x02 if data.len() == 2 => base = ((data[0] as u32) << 8 | data[1] as u32) << 4,
+            // 開始セグメントアドレス (CS:IP)
+            0x03 if data.len() == 4 => {
+                let cs = (data[0] as u32) << 8 | data[1] as u32;
+                let ip = (data[2] as u32) << 8 | data[3] as u32;
+                image.entry = Some(entry_address((cs << 4) + ip, line)?);
+            }
+            // 拡張リニアアドレス
+            0x04 if data.len() == 2 => base = ((data[0] as u32) << 8 | data[1] as u32) << 16,
+            // 開始リニアアドレス
+            0x05 if data.len() == 4 => {
+                let addr = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                image.entry = Some(entry_address(addr, line)?);
+            }
+            _ => {
+                return Err(LoadError::Syntax {
+                    line,
+                    reason: "unsupported record type",
+                });
+            }
+        }
+    }
+    Ok(image)
+}
+
+// Motorola S-record形式 (S<type><count><address><data><checksum>)
+pub fn parse_srec(text: &str) -> Result<Image, LoadError> {
+    let mut image = Image::default();
+
+    for (i, record) in text.lines().enumerate() {
+        let line = i + 1;
+        let record = record.trim();
+        if record.is_empty() {
+            continue;
+        }
+        let mut chars = record.chars();
+        if chars.next() != Some('S') {
+            return Err(LoadError::Syntax {
+                line,
+                reason: "record does not start with 'S'",
+            });
+        }
+        let kind = chars.next().unwrap_or(' ');
+        let bytes = decode_hex(chars.as_str(), line)?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(LoadError::Syntax {
+                line,
+                reason: "record length does not match byte count",
+            });
+        }
+        // カウントからデータまでの合計の1の補数がチェックサム
+        let sum = bytes[..bytes.len() - 1]
+            .iter()
+            .fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if !sum != bytes[bytes.len() - 1] {
+            return Err(LoadError::Checksum { line });
+        }
+
+        let addr_len = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => {
+                return Err(LoadError::Syntax {
+                    line,
+                    reason: "unsupported record type",
+                });
+            }
+        };
+        if bytes.len() < addr_len + 2 {
+            return Err(LoadError::Syntax {
+                line,
+                reason: "record too short",
+            });
+        }
+        let addr = bytes[1..1 + addr_len]
+            .iter()
+            .fold(0u32, |addr, b| addr << 8 | *b as u32);
+        let data = &bytes[1 + addr_len..bytes.len() - 1];
+        match kind {
+            '1' | '2' | '3' => image.push(addr, data)?,
+            '7' | '8' | '9' => image.entry = Some(entry_address(addr, line)?),
+            // S0はヘッダ、S5/S6はレコード数なので読み飛ばす
+            _ => {}
+        }
+    }
+    Ok(image)
+}
+
+fn entry_address(addr: u32, line: usize) -> Result<u16, LoadError> {
+    u16::try_from(addr).map_err(|_| LoadError::Syntax {
+        line,
+        reason: "entry address outside 64KB",
+    })
+}
+
+// 拡張子から形式を判断してファイルを読み込む
+// .hex/.ihx はIntel HEX、.s19/.s28/.s37/.srec/.mot はS-record、それ以外はraw_addrに置く生バイナリ
+pub fn load_file(path: &Path, raw_addr: u16) -> Result<Image, LoadError> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("hex" | "ihx") => parse_intel_hex(&fs::read_to_string(path)?),
+        Some("s19" | "s28" | "s37" | "srec" | "mot") => parse_srec(&fs::read_to_string(path)?),
+        _ => Image::raw(raw_addr, &fs::read(path)?),
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_raw_out_of_range() {
+        assert!(Image::raw(0x8000, &[0; 0x8000]).is_ok());
+        assert!(matches!(
+            Image::raw(0x8001, &[0; 0x8000]),
+            Err(LoadError::OutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let text = ":03C00000A905008F\n:040000050000C0FF38\n:00000001FF\n";
+        let image = parse_intel_hex(text).unwrap();
+        assert_eq!(
+            image.segments,
+            vec![Segment {
+                addr: 0xC000,
+                data: vec![0xA9, 0x05, 0x00],
+            }]
+        );
+        assert_eq!(image.entry, Some(0xC0FF));
+    }
+
+    #[test]
+    fn test_intel_hex_checksum() {
+        assert!(matches!(
+            parse_intel_hex(":03C00000A905008E\n"),
+            Err(LoadError::Checksum { line: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_srec() {
+        let text = "S00600004844521B\nS1068000A90500CB\nS1058003AA00CD\nS90380007C\n";
+        let image = parse_srec(text).unwrap();
+        assert_eq!(
+            image.segments,
+            vec![Segment {
+                addr: 0x8000,
+                data: vec![0xA9, 0x05, 0x00, 0xAA, 0x00],
+            }]
+        );
+        assert_eq!(image.entry, Some(0x8000));
+    }
+
+    #[test]
+    fn test_srec_out_of_range() {
+        // S2で64KBを超えるアドレス
+        assert!(matches!(
+            parse_srec("S2060100000102F5\n"),
+            Err(LoadError::OutOfRange { .. })
+        ));
+    }
+}
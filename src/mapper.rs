@@ -0,0 +1,254 @@
+pub mod action53;
+pub mod axrom;
+pub mod bandai;
+pub mod bnrom;
+pub mod camerica;
+pub mod cnrom;
+pub mod color_dreams;
+pub mod eeprom;
+pub mod fds;
+pub mod fds_audio;
+pub mod flash;
+pub mod fme7;
+pub mod gtrom;
+pub mod gxrom;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod namco163;
+pub mod nrom;
+pub mod nsf;
+pub mod opll;
+pub mod unrom512;
+pub mod uxrom;
+pub mod vrc2;
+pub mod vrc6;
+pub mod vrc7;
+pub mod vrc_irq;
+pub mod vs_system;
+
+use crate::cartridge::{Mirroring, Rom, RomError};
+
+// カートリッジ基板ごとのバンク切り替えなどを実装するトレイト
+// CPU側は$4020-$FFFF、PPU側は$0000-$1FFF（パターンテーブル）のアドレスを受け取る
+pub trait Mapper {
+    // CPUからの読み込み 副作用のあるマッパーだけが上書きする
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.cpu_peek(addr)
+    }
+    // 副作用なしの読み込み
+    fn cpu_peek(&self, addr: u16) -> u8;
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    // PPUからの読み込み
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.ppu_peek(addr)
+    }
+    fn ppu_peek(&self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    // 現在のネームテーブルのミラーリング
+    fn mirroring(&self) -> Mirroring;
+
+    // IRQ線の状態（trueでアサート）
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // CPUの1サイクルごとに呼ばれる
+    fn clock(&mut self) {}
+
+    // PPUが1ライン描き終えた時に呼ばれる
+    fn notify_scanline(&mut self) {}
+
+    // PPUがアドレスバスに出した番地（ネームテーブルやパレットも含む）
+    // MMC3のようにA12の変化を見るマッパーが使う
+    fn ppu_bus(&mut self, _addr: u16) {}
+
+    // ネームテーブル($2000-$2FFF)をマッパーが供給する場合はSome Noneなら本体のCIRAMを使う
+    fn nametable_read(&mut self, addr: u16) -> Option<u8> {
+        self.nametable_peek(addr)
+    }
+    fn nametable_peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+    // マッパーが書き込みを受け取ったらtrue
+    fn nametable_write(&mut self, _addr: u16, _data: u8) -> bool {
+        false
+    }
+
+    // CPUからPPUレジスタ($2000-$2007)への書き込み MMC5がスプライトの大きさなどを知るのに使う
+    fn ppu_register_write(&mut self, _addr: u16, _data: u8) {}
+
+    // CPUから$4016への書き込み Vs. SystemはCHRバンクの切り替えに使う
+    fn controller_write(&mut self, _data: u8) {}
+
+    // 拡張音源の出力 (0.0-1.0) 本体のAPUとの混ぜ方は呼び出し側が決める
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // 電池で保持されうるメモリ (PRG-RAMやEEPROM) 電池の有無はカートリッジ側が判断する
+    fn save_data(&self) -> Option<&[u8]> {
+        None
+    }
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    // $6000-$7FFFに置かれるPRG-RAM トレーナーの読み込みに使う
+    // 電源投入時にRAMを無効にしているマッパーでも、レジスタを通さずに書き込める
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    // ディスクシステムのディスクの面の数と、入っている面 Noneは取り出した状態
+    fn disk_sides(&self) -> usize {
+        0
+    }
+    fn inserted_disk(&self) -> Option<usize> {
+        None
+    }
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+
+    // 基板上のLEDの点灯状態 bitごとに1つ
+    fn leds(&self) -> u8 {
+        0
+    }
+}
+
+// ROMのマッパー番号から実装を選ぶ
+pub fn create(rom: &Rom) -> Result<Box<dyn Mapper>, RomError> {
+    match rom.mapper {
+        0 => Ok(Box::new(nrom::Nrom::new(rom))),
+        1 => Ok(Box::new(mmc1::Mmc1::new(rom))),
+        2 => Ok(Box::new(uxrom::Uxrom::new(rom))),
+        3 => Ok(Box::new(cnrom::Cnrom::new(rom))),
+        4 => Ok(Box::new(mmc3::Mmc3::new(rom))),
+        5 => Ok(Box::new(mmc5::Mmc5::new(rom))),
+        7 => Ok(Box::new(axrom::Axrom::new(rom))),
+        9 => Ok(Box::new(mmc2::Mmc2::new(rom, mmc2::Chip::Mmc2))),
+        10 => Ok(Box::new(mmc2::Mmc2::new(rom, mmc2::Chip::Mmc4))),
+        11 => Ok(Box::new(color_dreams::ColorDreams::new(rom))),
+        16 | 159 => Ok(Box::new(bandai::Bandai::new(rom))),
+        19 => Ok(Box::new(namco163::Namco163::new(rom))),
+        // ディスクシステムはディスクイメージとBIOSから作ったROMでしか動かない
+        20 if rom.disk_sides.is_empty() || rom.prg_rom.len() != crate::fds::BIOS_SIZE => Err(
+            RomError::Unsupported("mapper 20 without a disk image and the FDS BIOS"),
+        ),
+        20 => Ok(Box::new(fds::Fds::new(rom))),
+        21..=23 | 25 => Ok(Box::new(vrc2::Vrc2::new(rom))),
+        24 | 26 => Ok(Box::new(vrc6::Vrc6::new(rom))),
+        28 => Ok(Box::new(action53::Action53::new(rom))),
+        30 => Ok(Box::new(unrom512::Unrom512::new(rom))),
+        34 => Ok(Box::new(bnrom::Mapper34::new(rom))),
+        66 => Ok(Box::new(gxrom::Gxrom::new(rom))),
+        69 => Ok(Box::new(fme7::Fme7::new(rom))),
+        71 => Ok(Box::new(camerica::Camerica::new(rom))),
+        85 => Ok(Box::new(vrc7::Vrc7::new(rom))),
+        crate::nsf::MAPPER => Ok(Box::new(nsf::Nsf::new(rom))),
+        99 => Ok(Box::new(vs_system::VsSystem::new(rom))),
+        111 => Ok(Box::new(gtrom::Gtrom::new(rom))),
+        mapper => Err(RomError::UnsupportedMapper(mapper)),
+    }
+}
+
+// 大きさsizeのbank番目のバンクにあるaddrの位置 ROMより大きい番号は折り返す
+pub fn bank_offset(len: usize, bank: usize, size: usize, addr: u16) -> usize {
+    (bank * size + (addr as usize & (size - 1))) % len
+}
+
+// バス競合のある基板では、書き込んだ値と同じ番地のROMの値のANDがレジスタに入る
+pub fn bus_conflict(enabled: bool, rom_value: u8, data: u8) -> u8 {
+    if enabled { data & rom_value } else { data }
+}
+
+// PRG-RAMの大きさ 通常のRAMとバッテリーバックアップされるRAMの合計
+pub fn prg_ram_size(rom: &Rom) -> usize {
+    rom.prg_ram_size + rom.prg_nvram_size
+}
+
+// パターンテーブルのメモリ CHR-ROMが無いカートリッジはCHR-RAMを持つ
+pub struct ChrMemory {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl ChrMemory {
+    pub fn new(rom: &Rom) -> Self {
+        Self::with_min_ram(rom, 0x2000)
+    }
+
+    // iNES 1.0のヘッダではCHR-RAMの大きさが分からないので、基板の決まった大きさまで広げる
+    pub fn with_min_ram(rom: &Rom, min_size: usize) -> Self {
+        if rom.chr_rom.is_empty() {
+            let size = (rom.chr_ram_size + rom.chr_nvram_size).max(min_size);
+            ChrMemory {
+                data: vec![0; size],
+                writable: true,
+            }
+        } else {
+            ChrMemory {
+                data: rom.chr_rom.clone(),
+                writable: false,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn is_ram(&self) -> bool {
+        self.writable
+    }
+
+    // 大きさsizeのbank番目のバンクから読む
+    pub fn read(&self, bank: usize, size: usize, addr: u16) -> u8 {
+        self.data[bank_offset(self.data.len(), bank, size, addr)]
+    }
+
+    // CHR-RAMの時だけ書き込める
+    pub fn write(&mut self, bank: usize, size: usize, addr: u16, data: u8) {
+        if self.writable {
+            let offset = bank_offset(self.data.len(), bank, size, addr);
+            self.data[offset] = data;
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    // NES 2.0やUNIFではPRG-ROMがバンクより小さいことがある 小さい分は折り返して見える
+    #[test]
+    fn test_prg_smaller_than_bank() {
+        let mut rom = Rom::new(&create_rom(0x00, 0x00, 1, 1)).unwrap();
+        rom.prg_rom.truncate(0x2000);
+        rom.chr_rom.truncate(0x0400);
+        for mapper in [
+            0, 1, 2, 3, 4, 5, 7, 9, 10, 11, 16, 19, 21, 22, 23, 24, 25, 26, 28, 30, 34, 66, 69, 71,
+            85, 99, 111,
+        ] {
+            rom.mapper = mapper;
+            let mut mapper = create(&rom).unwrap();
+            for addr in (0x8000..=0xFFFF).step_by(0x1000) {
+                assert_eq!(mapper.cpu_read(addr), 0x00);
+            }
+            mapper.ppu_read(0x1FFF);
+        }
+        // ディスクを持たないmapper 20は作らずにエラーにする
+        rom.mapper = 20;
+        assert!(matches!(create(&rom), Err(RomError::Unsupported(_))));
+        rom.prg_rom.truncate(0x1000);
+        assert!(matches!(create(&rom), Err(RomError::Unsupported(_))));
+    }
+}
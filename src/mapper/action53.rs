@@ -0,0 +1,159 @@
+use super::{ChrMemory, Mapper, bank_offset};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー28 (Action 53)
+// 複数のゲームを1本に入れるための基板 $5000-$5FFFでレジスタを選び、$8000-$FFFFに値を書く
+// 外側のバンク(32KB単位)の中で、ゲームの大きさの分だけ内側のバンクで切り替える
+pub struct Action53 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    // $00 CHRバンク, $01 内側のPRGバンク, $80 モード, $81 外側のPRGバンク
+    select: u8,
+    chr_bank: u8,
+    inner_bank: u8,
+    // bit0-1 ミラーリング, bit2-3 PRGバンクのモード, bit4-5 ゲームの大きさ
+    mode: u8,
+    outer_bank: u8,
+}
+
+impl Action53 {
+    pub fn new(rom: &Rom) -> Self {
+        Action53 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::with_min_ram(rom, 0x8000),
+            select: 0,
+            chr_bank: 0,
+            inner_bank: 0,
+            mode: 0,
+            // 電源投入時は最後の32KBにあるメニューから始める
+            outer_bank: 0xFF,
+        }
+    }
+
+    // addrの16KBバンク
+    fn prg_bank(&self, addr: u16) -> usize {
+        let a14 = ((addr >> 14) & 1) as usize;
+        let outer = (self.outer_bank as usize) << 1;
+        // 16KB単位のゲームの大きさ-1 (32KB/64KB/128KB/256KB)
+        let mask = (2 << ((self.mode >> 4) & 0x03)) - 1;
+        let inner = match (self.mode >> 2) & 0x03 {
+            // 32KB単位で切り替える
+            0 | 1 => (self.inner_bank as usize) << 1 | a14,
+            // $8000か$C000が外側のバンクの先頭・末尾に固定される
+            2 if a14 == 0 => return outer,
+            3 if a14 == 1 => return outer | 1,
+            _ => self.inner_bank as usize,
+        };
+        (outer & !mask) | (inner & mask)
+    }
+
+    // 1画面のモードでは$00/$01への書き込みのbit4でページを選ぶ
+    fn write_one_screen(&mut self, data: u8) {
+        if self.mode & 0x02 == 0 {
+            self.mode = (self.mode & !0x01) | (data >> 4) & 0x01;
+        }
+    }
+}
+
+impl Mapper for Action53 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank = self.prg_bank(addr);
+                self.prg_rom[bank_offset(self.prg_rom.len(), bank, 0x4000, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5FFF => self.select = data & 0x81,
+            0x8000..=0xFFFF => match self.select {
+                0x00 => {
+                    self.chr_bank = data & 0x03;
+                    self.write_one_screen(data);
+                }
+                0x01 => {
+                    self.inner_bank = data & 0x0F;
+                    self.write_one_screen(data);
+                }
+                0x80 => self.mode = data & 0x3F,
+                _ => self.outer_bank = data,
+            },
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank as usize, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank as usize, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mode & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn write(mapper: &mut Action53, register: u8, data: u8) {
+        mapper.cpu_write(0x5000, register);
+        mapper.cpu_write(0x8000, data);
+    }
+
+    #[test]
+    fn test_power_on() {
+        // 256KB: 最後の32KBから始まる
+        let mapper = Action53::new(&Rom::new(&create_rom(0x00, 0x10, 16, 0)).unwrap());
+        assert_eq!(mapper.cpu_peek(0x8000), 14);
+        assert_eq!(mapper.cpu_peek(0xC000), 15);
+    }
+
+    #[test]
+    fn test_outer_and_inner_banks() {
+        let mut mapper = Action53::new(&Rom::new(&create_rom(0x00, 0x10, 16, 0)).unwrap());
+        // 64KBのUNROM風のゲームを外側のバンク2-3に置く ($C000を固定)
+        write(&mut mapper, 0x80, 0x10 | 0x0C | 0x02);
+        write(&mut mapper, 0x81, 0x03);
+        write(&mut mapper, 0x01, 0x02);
+        assert_eq!(mapper.cpu_peek(0x8000), 6);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+        // 内側のバンクはゲームの大きさで折り返す
+        write(&mut mapper, 0x01, 0x05);
+        assert_eq!(mapper.cpu_peek(0x8000), 5);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        // 32KBのモード
+        write(&mut mapper, 0x80, 0x00);
+        write(&mut mapper, 0x81, 0x01);
+        assert_eq!(mapper.cpu_peek(0x8000), 2);
+        assert_eq!(mapper.cpu_peek(0xC000), 3);
+    }
+
+    #[test]
+    fn test_chr_and_one_screen() {
+        let mut mapper = Action53::new(&Rom::new(&create_rom(0x00, 0x10, 2, 0)).unwrap());
+        write(&mut mapper, 0x80, 0x00);
+        write(&mut mapper, 0x00, 0x10 | 0x01);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+        mapper.ppu_write(0x0000, 0x55);
+        write(&mut mapper, 0x00, 0x02);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        assert_eq!(mapper.ppu_peek(0x0000), 0x00);
+        write(&mut mapper, 0x00, 0x01);
+        assert_eq!(mapper.ppu_peek(0x0000), 0x55);
+    }
+}
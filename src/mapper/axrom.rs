@@ -0,0 +1,78 @@
+use super::{ChrMemory, Mapper, bank_offset, bus_conflict};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー7 (AxROM)
+// bit0-2で32KBのPRGバンク、bit4で1画面ミラーリングのページを選ぶ
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    register: u8,
+    // NES 2.0のサブマッパー2 (AMROM) はバス競合あり
+    bus_conflicts: bool,
+}
+
+impl Axrom {
+    pub fn new(rom: &Rom) -> Self {
+        Axrom {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            register: 0,
+            bus_conflicts: rom.submapper == 2,
+        }
+    }
+
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank = (self.register & 0x07) as usize;
+                self.prg_rom[bank_offset(self.prg_rom.len(), bank, 0x8000, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.register = bus_conflict(self.bus_conflicts, self.cpu_peek(addr), data);
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(0, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.register & 0x10 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_bank_and_single_screen() {
+        let mut mapper = Axrom::new(&Rom::new(&create_rom(0x70, 0, 8, 0)).unwrap());
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        mapper.cpu_write(0x8000, 0x12);
+        assert_eq!(mapper.cpu_peek(0x8000), 4);
+        assert_eq!(mapper.cpu_peek(0xC000), 5);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+}
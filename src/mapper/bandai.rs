@@ -0,0 +1,259 @@
+use super::eeprom::{Eeprom, Kind};
+use super::{ChrMemory, Mapper, bank_offset};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー16/159 (バンダイ FCG-1/2, LZ93D50)
+// FCGは$6000-$7FFF、LZ93D50は$8000-$FFFFにレジスタがあり、下位4bitで選ぶ
+// LZ93D50はセーブ用にシリアルEEPROM (16は24C02、159は24C01) を持つ
+pub struct Bandai {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    registers_at_6000: bool,
+    registers_at_8000: bool,
+    // LZ93D50はラッチに書いて$800Aでカウンタに移す FCGはカウンタに直接書く
+    irq_latch_mode: bool,
+    chr_banks: [u8; 8],
+    prg_bank: u8,
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq_latch: u16,
+    irq_pending: bool,
+    eeprom: Option<Eeprom>,
+}
+
+impl Bandai {
+    pub fn new(rom: &Rom) -> Self {
+        // サブマッパーが無い時は両方の基板の振る舞いを兼ねる
+        let (registers_at_6000, registers_at_8000) = match (rom.mapper, rom.submapper) {
+            (16, 4) => (true, false),
+            (16, 5) | (159, _) => (false, true),
+            _ => (true, true),
+        };
+        let eeprom = match (rom.mapper, rom.submapper) {
+            (159, _) => Some(Eeprom::new(Kind::C01)),
+            (16, 4) => None,
+            _ if rom.battery || rom.prg_nvram_size > 0 => Some(Eeprom::new(Kind::C02)),
+            _ => None,
+        };
+        Bandai {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            registers_at_6000,
+            registers_at_8000,
+            irq_latch_mode: registers_at_8000,
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            mirroring: rom.screen_mirroring,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_pending: false,
+            eeprom,
+        }
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0x0..=0x7 => self.chr_banks[register as usize] = data,
+            0x8 => self.prg_bank = data & 0x0F,
+            0x9 => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            0xA => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.irq_pending = false;
+                if self.irq_latch_mode {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xB => {
+                self.irq_latch = (self.irq_latch & 0xFF00) | data as u16;
+                if self.registers_at_6000 {
+                    self.irq_counter = (self.irq_counter & 0xFF00) | data as u16;
+                }
+            }
+            0xC => {
+                self.irq_latch = (self.irq_latch & 0x00FF) | (data as u16) << 8;
+                if self.registers_at_6000 {
+                    self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8;
+                }
+            }
+            // bit5 SCL, bit6 SDA
+            0xD => {
+                if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.write(data & 0x20 != 0, data & 0x40 != 0);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Bandai {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        match addr {
+            // EEPROMのSDAはbit4に出る
+            0x6000..=0x7FFF => match &self.eeprom {
+                Some(eeprom) if eeprom.output() => 0x10,
+                _ => 0,
+            },
+            0x8000..=0xBFFF => self.prg_rom[bank_offset(len, self.prg_bank as usize, 0x4000, addr)],
+            0xC000..=0xFFFF => {
+                self.prg_rom[bank_offset(len, (len / 0x4000).saturating_sub(1), 0x4000, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.registers_at_6000 => self.write_register(addr & 0x0F, data),
+            0x8000..=0xFFFF if self.registers_at_8000 => self.write_register(addr & 0x0F, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr
+            .read(self.chr_banks[(addr >> 10) as usize] as usize, 0x0400, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+        self.chr.write(bank, 0x0400, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        self.eeprom.as_ref().map(|eeprom| eeprom.data())
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.eeprom.as_mut().map(|eeprom| eeprom.data_mut())
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // カウンタは0になった次のサイクルでIRQを出す
+    fn clock(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.irq_pending = true;
+        }
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn create_bandai(mapper: u8, submapper: u8, battery: bool) -> Bandai {
+        let flags6 = ((mapper & 0x0F) << 4) | if battery { 0x02 } else { 0 };
+        let mut raw = create_rom(flags6, (mapper & 0xF0) | 0x08, 8, 16);
+        raw[8] = submapper << 4;
+        let chr = 16 + 8 * 0x4000;
+        for bank in 0..128 {
+            raw[chr + bank * 0x0400] = bank as u8;
+        }
+        Bandai::new(&Rom::new(&raw).unwrap())
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = create_bandai(16, 5, false);
+        mapper.cpu_write(0x8008, 3);
+        mapper.cpu_write(0x8003, 0x25);
+        mapper.cpu_write(0x8009, 1);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+        assert_eq!(mapper.ppu_peek(0x0C00), 0x25);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        // LZ93D50は$6000のレジスタを持たない
+        mapper.cpu_write(0x6008, 1);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+
+        let mut mapper = create_bandai(16, 4, false);
+        mapper.cpu_write(0x6008, 1);
+        assert_eq!(mapper.cpu_peek(0x8000), 1);
+    }
+
+    #[test]
+    fn test_irq() {
+        // FCGはカウンタに直接書く
+        let mut mapper = create_bandai(16, 4, false);
+        mapper.cpu_write(0x600B, 2);
+        mapper.cpu_write(0x600C, 0);
+        mapper.cpu_write(0x600A, 1);
+        for _ in 0..2 {
+            mapper.clock();
+        }
+        assert!(!mapper.irq());
+        mapper.clock();
+        assert!(mapper.irq());
+        mapper.cpu_write(0x600A, 0);
+        assert!(!mapper.irq());
+
+        // LZ93D50は有効にした時にラッチから読み込む
+        let mut mapper = create_bandai(16, 5, false);
+        mapper.cpu_write(0x800B, 1);
+        mapper.cpu_write(0x800A, 1);
+        mapper.clock();
+        assert!(!mapper.irq());
+        mapper.clock();
+        assert!(mapper.irq());
+    }
+
+    #[test]
+    fn test_eeprom() {
+        assert!(create_bandai(16, 5, false).save_data().is_none());
+        assert_eq!(
+            create_bandai(16, 5, true).save_data().map(|d| d.len()),
+            Some(256)
+        );
+        let mut mapper = create_bandai(159, 0, true);
+        assert_eq!(mapper.save_data().map(|d| d.len()), Some(128));
+
+        // $800DでSCL/SDAを動かし、$6000のbit4でEEPROMの出力を読む
+        mapper.save_data_mut().unwrap()[0] = 0x5A;
+        let mut lines = |scl: bool, sda: bool| -> u8 {
+            mapper.cpu_write(0x800D, (scl as u8) << 5 | (sda as u8) << 6);
+            mapper.cpu_peek(0x6000) & 0x10
+        };
+        // スタートの後、アドレス0と読み込みをLSBから送る
+        lines(true, true);
+        lines(true, false);
+        for bit in [false, false, false, false, false, false, false, true] {
+            lines(false, bit);
+            lines(true, bit);
+        }
+        lines(false, true);
+        assert_eq!(lines(true, true), 0, "ACK");
+        lines(false, true);
+        let mut byte = 0;
+        for i in 0..8 {
+            if lines(true, true) != 0 {
+                byte |= 1 << i;
+            }
+            lines(false, true);
+        }
+        assert_eq!(byte, 0x5A);
+    }
+}
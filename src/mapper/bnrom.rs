@@ -0,0 +1,167 @@
+use super::{ChrMemory, Mapper, bank_offset, bus_conflict, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー34はBNROMとNINA-001の2種類の基板をまとめている
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    // $8000-$FFFFへの書き込みで32KBのPRGバンクを選ぶ CHR-RAM、バス競合あり
+    Bnrom,
+    // $7FFD-$7FFFのレジスタでPRG 32KBとCHR 4KB x2を選ぶ PRG-RAMあり
+    Nina001,
+}
+
+pub struct Mapper34 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+    board: Board,
+    prg_bank: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    bus_conflicts: bool,
+}
+
+impl Mapper34 {
+    pub fn new(rom: &Rom) -> Self {
+        // サブマッパー1はNINA-001、2はBNROM 無ければCHR-ROMが8KBを超えるかで判断する
+        let board = match rom.submapper {
+            1 => Board::Nina001,
+            2 => Board::Bnrom,
+            _ if rom.chr_rom.len() > 0x2000 => Board::Nina001,
+            _ => Board::Bnrom,
+        };
+        let prg_ram = match board {
+            Board::Nina001 => vec![0; prg_ram_size(rom).max(0x2000)],
+            Board::Bnrom => Vec::new(),
+        };
+        Mapper34 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram,
+            mirroring: rom.screen_mirroring,
+            board,
+            prg_bank: 0,
+            chr_bank0: 0,
+            chr_bank1: 1,
+            bus_conflicts: board == Board::Bnrom,
+        }
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+}
+
+impl Mapper for Mapper34 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => {
+                let bank = self.prg_bank as usize;
+                self.prg_rom[bank_offset(self.prg_rom.len(), bank, 0x8000, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match (self.board, addr) {
+            (Board::Bnrom, 0x8000..=0xFFFF) => {
+                self.prg_bank = bus_conflict(self.bus_conflicts, self.cpu_peek(addr), data);
+            }
+            (Board::Nina001, 0x6000..=0x7FFF) => {
+                // レジスタへの書き込みはRAMにも書かれる
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+                match addr {
+                    0x7FFD => self.prg_bank = data & 0x01,
+                    0x7FFE => self.chr_bank0 = data & 0x0F,
+                    0x7FFF => self.chr_bank1 = data & 0x0F,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        match self.board {
+            Board::Bnrom => self.chr.read(0, 0x2000, addr),
+            Board::Nina001 if addr < 0x1000 => self.chr.read(self.chr_bank0 as usize, 0x1000, addr),
+            Board::Nina001 => self.chr.read(self.chr_bank1 as usize, 0x1000, addr),
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        match self.board {
+            Board::Bnrom => self.chr.write(0, 0x2000, addr, data),
+            Board::Nina001 => {
+                let bank = if addr < 0x1000 {
+                    self.chr_bank0
+                } else {
+                    self.chr_bank1
+                };
+                self.chr.write(bank as usize, 0x1000, addr, data);
+            }
+        }
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_bnrom() {
+        let mut raw = create_rom(0x20, 0x20, 8, 0);
+        // $8000-$FFFFのどこでもバス競合が起きない値を置く
+        raw[16 + 0x7FFF] = 0xFF;
+        let mut mapper = Mapper34::new(&Rom::new(&raw).unwrap());
+        assert_eq!(mapper.board(), Board::Bnrom);
+        mapper.cpu_write(0xFFFF, 0x02);
+        assert_eq!(mapper.cpu_peek(0x8000), 4);
+        assert_eq!(mapper.cpu_peek(0xC000), 5);
+    }
+
+    #[test]
+    fn test_nina001() {
+        let mut raw = create_rom(0x20, 0x20, 4, 2);
+        for bank in 0..4 {
+            raw[16 + 4 * 0x4000 + bank * 0x1000] = 0x30 + bank as u8;
+        }
+        let mut mapper = Mapper34::new(&Rom::new(&raw).unwrap());
+        assert_eq!(mapper.board(), Board::Nina001);
+        mapper.cpu_write(0x7FFD, 1);
+        mapper.cpu_write(0x7FFE, 3);
+        mapper.cpu_write(0x7FFF, 2);
+        assert_eq!(mapper.cpu_peek(0x8000), 2);
+        assert_eq!(mapper.cpu_peek(0x7FFE), 3);
+        assert_eq!(mapper.ppu_read(0x0000), 0x33);
+        assert_eq!(mapper.ppu_read(0x1000), 0x32);
+    }
+}
@@ -0,0 +1,90 @@
+use super::{ChrMemory, Mapper, bank_offset};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー71 (Camerica BF9093/BF9097)
+// $C000-$FFFFへの書き込みで$8000の16KBバンクを選び、$C000は最後のバンクに固定
+// Fire Hawk (サブマッパー1, BF9097) は$8000-$9FFFのbit4で1画面ミラーリングを選ぶ
+pub struct Camerica {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    prg_bank: u8,
+    mirroring_control: bool,
+}
+
+impl Camerica {
+    pub fn new(rom: &Rom) -> Self {
+        Camerica {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            mirroring: rom.screen_mirroring,
+            prg_bank: 0,
+            mirroring_control: rom.submapper == 1,
+        }
+    }
+}
+
+impl Mapper for Camerica {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank as usize,
+            0xC000..=0xFFFF => (self.prg_rom.len() / 0x4000).saturating_sub(1),
+            _ => return 0,
+        };
+        self.prg_rom[bank_offset(self.prg_rom.len(), bank, 0x4000, addr)]
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9FFF if self.mirroring_control => {
+                self.mirroring = if data & 0x10 == 0 {
+                    Mirroring::SingleScreenLower
+                } else {
+                    Mirroring::SingleScreenUpper
+                };
+            }
+            0xC000..=0xFFFF => self.prg_bank = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(0, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_prg_bank() {
+        let mut mapper = Camerica::new(&Rom::new(&create_rom(0x70, 0x40, 8, 0)).unwrap());
+        mapper.cpu_write(0x8000, 0x10);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.cpu_write(0xC000, 2);
+        assert_eq!(mapper.cpu_peek(0x8000), 2);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+    }
+
+    #[test]
+    fn test_fire_hawk_mirroring() {
+        let mut raw = create_rom(0x70, 0x48, 8, 0);
+        raw[8] = 0x10;
+        let mut mapper = Camerica::new(&Rom::new(&raw).unwrap());
+        mapper.cpu_write(0x9000, 0x10);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+        mapper.cpu_write(0x9000, 0x00);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+    }
+}
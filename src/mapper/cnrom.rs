@@ -0,0 +1,76 @@
+use super::{ChrMemory, Mapper, bus_conflict};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー3 (CNROM)
+// PRGは固定で、8KBのCHRバンクを切り替える
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    chr_bank: u8,
+    // NES 2.0のサブマッパー2はバス競合あり
+    bus_conflicts: bool,
+}
+
+impl Cnrom {
+    pub fn new(rom: &Rom) -> Self {
+        Cnrom {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            mirroring: rom.screen_mirroring,
+            chr_bank: 0,
+            bus_conflicts: rom.submapper == 2,
+        }
+    }
+
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.chr_bank = bus_conflict(self.bus_conflicts, self.cpu_peek(addr), data);
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank as usize, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank as usize, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_chr_bank() {
+        let mut raw = create_rom(0x30, 0, 2, 4);
+        for bank in 0..4 {
+            raw[16 + 0x8000 + bank * 0x2000] = bank as u8;
+        }
+        let mut mapper = Cnrom::new(&Rom::new(&raw).unwrap());
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        mapper.cpu_write(0x8000, 5);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+    }
+}
@@ -0,0 +1,83 @@
+use super::{ChrMemory, Mapper, bank_offset, bus_conflict};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー11 (Color Dreams)
+// bit0-1で32KBのPRGバンク、bit4-7で8KBのCHRバンクを選ぶ バス競合あり
+pub struct ColorDreams {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    register: u8,
+    bus_conflicts: bool,
+}
+
+impl ColorDreams {
+    pub fn new(rom: &Rom) -> Self {
+        ColorDreams {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            mirroring: rom.screen_mirroring,
+            register: 0,
+            bus_conflicts: true,
+        }
+    }
+
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+}
+
+impl Mapper for ColorDreams {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank = (self.register & 0x03) as usize;
+                self.prg_rom[bank_offset(self.prg_rom.len(), bank, 0x8000, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.register = bus_conflict(self.bus_conflicts, self.cpu_peek(addr), data);
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read((self.register >> 4) as usize, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr
+            .write((self.register >> 4) as usize, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_prg_and_chr_bank() {
+        let mut raw = create_rom(0xB0, 0, 4, 4);
+        for bank in 0..4 {
+            raw[16 + 4 * 0x4000 + bank * 0x2000] = 0x20 + bank as u8;
+        }
+        let mut mapper = ColorDreams::new(&Rom::new(&raw).unwrap());
+        // バス競合: $8000の値は0なので書き込みは0になる
+        mapper.cpu_write(0x8000, 0x31);
+        assert_eq!(mapper.cpu_peek(0x8000), 0);
+
+        mapper.set_bus_conflicts(false);
+        mapper.cpu_write(0x8000, 0x31);
+        assert_eq!(mapper.cpu_peek(0x8000), 2);
+        assert_eq!(mapper.ppu_read(0x0000), 0x23);
+    }
+}
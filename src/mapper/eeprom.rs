@@ -0,0 +1,305 @@
+// バンダイの基板に載っているI2CのシリアルEEPROM
+// 24C02はデバイスアドレス・ワードアドレスの順にMSBから送る
+// 24C01(X24C01)はデバイスアドレスが無く、7bitのアドレスとR/WをLSBから送る
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    C01,
+    C02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Idle,
+    Device,
+    Address,
+    Read,
+    Write,
+    // 受け取ったバイトにACKを返す
+    SendAck,
+    // 送ったバイトへのマスターのACKを待つ
+    WaitAck,
+}
+
+pub struct Eeprom {
+    kind: Kind,
+    data: Vec<u8>,
+    mode: Mode,
+    next_mode: Mode,
+    device: u8,
+    address: u8,
+    buffer: u8,
+    counter: u8,
+    scl: bool,
+    sda: bool,
+    // EEPROMがSDAに出している値
+    output: bool,
+}
+
+impl Eeprom {
+    pub fn new(kind: Kind) -> Self {
+        Eeprom {
+            kind,
+            data: vec![
+                0;
+                match kind {
+                    Kind::C01 => 128,
+                    Kind::C02 => 256,
+                }
+            ],
+            mode: Mode::Idle,
+            next_mode: Mode::Idle,
+            device: 0,
+            address: 0,
+            buffer: 0,
+            counter: 0,
+            scl: false,
+            sda: false,
+            output: true,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    fn address_mask(&self) -> u8 {
+        (self.data.len() - 1) as u8
+    }
+
+    // 24C01はLSBから、24C02はMSBから並べる
+    fn bit_mask(&self) -> u8 {
+        match self.kind {
+            Kind::C01 => 1 << self.counter,
+            Kind::C02 => 0x80 >> self.counter,
+        }
+    }
+
+    fn shift_in(&mut self, value: bool) -> u8 {
+        let mask = self.bit_mask();
+        self.counter += 1;
+        if value { mask } else { 0 }
+    }
+
+    // SCLとSDAの線の状態を書き込む
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        if self.scl && scl && self.sda && !sda {
+            // SCLがHighの間にSDAが下がるとスタートコンディション
+            self.mode = match self.kind {
+                Kind::C01 => Mode::Address,
+                Kind::C02 => Mode::Device,
+            };
+            self.device = 0;
+            if self.kind == Kind::C01 {
+                self.address = 0;
+            }
+            self.counter = 0;
+            self.output = true;
+        } else if self.scl && scl && !self.sda && sda {
+            // 上がるとストップコンディション
+            self.mode = Mode::Idle;
+            self.output = true;
+        } else if !self.scl && scl {
+            self.rising_edge(sda);
+        } else if self.scl && !scl {
+            self.falling_edge();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn rising_edge(&mut self, sda: bool) {
+        match self.mode {
+            Mode::Device if self.counter < 8 => self.device |= self.shift_in(sda),
+            Mode::Address if self.kind == Kind::C01 && self.counter == 7 => {
+                // 24C01は8bit目がR/W
+                self.counter += 1;
+                self.next_mode = if sda { Mode::Read } else { Mode::Write };
+                self.buffer = self.data[self.address as usize];
+            }
+            Mode::Address if self.counter < 8 => {
+                if self.counter == 0 {
+                    self.address = 0;
+                }
+                self.address |= self.shift_in(sda);
+            }
+            Mode::Write if self.counter < 8 => {
+                if self.counter == 0 {
+                    self.buffer = 0;
+                }
+                self.buffer |= self.shift_in(sda);
+            }
+            Mode::Read if self.counter < 8 => {
+                self.output = self.buffer & self.bit_mask() != 0;
+                self.counter += 1;
+            }
+            Mode::SendAck => self.output = false,
+            Mode::WaitAck if !sda => {
+                // ACKなら続けて次のバイトを送る
+                self.next_mode = Mode::Read;
+                self.buffer = self.data[self.address as usize];
+            }
+            Mode::WaitAck => self.next_mode = Mode::Idle,
+            _ => {}
+        }
+    }
+
+    fn falling_edge(&mut self) {
+        if self.counter < 8 && !matches!(self.mode, Mode::SendAck | Mode::WaitAck) {
+            return;
+        }
+        match self.mode {
+            Mode::Device => {
+                // 24C02のデバイスアドレスは1010xxxR/W
+                if self.device & 0xF0 == 0xA0 {
+                    self.next_mode = if self.device & 0x01 != 0 {
+                        self.buffer = self.data[self.address as usize];
+                        Mode::Read
+                    } else {
+                        Mode::Address
+                    };
+                    self.mode = Mode::SendAck;
+                } else {
+                    self.mode = Mode::Idle;
+                }
+                self.counter = 0;
+                self.output = true;
+            }
+            Mode::Address => {
+                if self.kind == Kind::C02 {
+                    self.next_mode = Mode::Write;
+                }
+                self.mode = Mode::SendAck;
+                self.counter = 0;
+                self.output = true;
+            }
+            Mode::Read => {
+                self.mode = Mode::WaitAck;
+                self.address = self.address.wrapping_add(1) & self.address_mask();
+                self.output = true;
+            }
+            Mode::Write => {
+                self.data[self.address as usize] = self.buffer;
+                self.address = self.address.wrapping_add(1) & self.address_mask();
+                self.next_mode = Mode::Write;
+                self.mode = Mode::SendAck;
+                self.counter = 0;
+                self.output = true;
+            }
+            Mode::SendAck | Mode::WaitAck => {
+                self.mode = self.next_mode;
+                self.counter = 0;
+                self.output = true;
+            }
+            Mode::Idle => {}
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // マスター側の操作
+    fn start(eeprom: &mut Eeprom) {
+        eeprom.write(false, true);
+        eeprom.write(true, true);
+        eeprom.write(true, false);
+        eeprom.write(false, false);
+    }
+
+    fn stop(eeprom: &mut Eeprom) {
+        eeprom.write(false, false);
+        eeprom.write(true, false);
+        eeprom.write(true, true);
+    }
+
+    fn send_bit(eeprom: &mut Eeprom, bit: bool) {
+        eeprom.write(false, bit);
+        eeprom.write(true, bit);
+        eeprom.write(false, bit);
+    }
+
+    // SCLがHighの間にSDAを読む
+    fn receive_bit(eeprom: &mut Eeprom) -> bool {
+        eeprom.write(false, true);
+        eeprom.write(true, true);
+        let bit = eeprom.output();
+        eeprom.write(false, true);
+        bit
+    }
+
+    fn send_byte(eeprom: &mut Eeprom, byte: u8, lsb_first: bool) -> bool {
+        for i in 0..8 {
+            let bit = if lsb_first {
+                byte >> i
+            } else {
+                byte >> (7 - i)
+            };
+            send_bit(eeprom, bit & 1 != 0);
+        }
+        // ACKは0
+        !receive_bit(eeprom)
+    }
+
+    fn receive_byte(eeprom: &mut Eeprom, lsb_first: bool, ack: bool) -> u8 {
+        let mut byte = 0;
+        for i in 0..8 {
+            if receive_bit(eeprom) {
+                byte |= if lsb_first { 1 << i } else { 0x80 >> i };
+            }
+        }
+        send_bit(eeprom, !ack);
+        byte
+    }
+
+    #[test]
+    fn test_24c02() {
+        let mut eeprom = Eeprom::new(Kind::C02);
+        start(&mut eeprom);
+        assert!(send_byte(&mut eeprom, 0xA0, false));
+        assert!(send_byte(&mut eeprom, 0x10, false));
+        assert!(send_byte(&mut eeprom, 0x12, false));
+        assert!(send_byte(&mut eeprom, 0x34, false));
+        stop(&mut eeprom);
+        assert_eq!(&eeprom.data()[0x10..0x12], &[0x12, 0x34]);
+
+        // ランダムリード: アドレスを書いてから読み出しで始め直す
+        start(&mut eeprom);
+        assert!(send_byte(&mut eeprom, 0xA0, false));
+        assert!(send_byte(&mut eeprom, 0x10, false));
+        start(&mut eeprom);
+        assert!(send_byte(&mut eeprom, 0xA1, false));
+        assert_eq!(receive_byte(&mut eeprom, false, true), 0x12);
+        assert_eq!(receive_byte(&mut eeprom, false, false), 0x34);
+        stop(&mut eeprom);
+
+        // 違うデバイスアドレスには応答しない
+        start(&mut eeprom);
+        assert!(!send_byte(&mut eeprom, 0x50, false));
+    }
+
+    #[test]
+    fn test_24c01() {
+        let mut eeprom = Eeprom::new(Kind::C01);
+        start(&mut eeprom);
+        // アドレス0x05と書き込み(R/W=0)
+        assert!(send_byte(&mut eeprom, 0x05, true));
+        assert!(send_byte(&mut eeprom, 0xA5, true));
+        stop(&mut eeprom);
+        assert_eq!(eeprom.data()[0x05], 0xA5);
+
+        start(&mut eeprom);
+        assert!(send_byte(&mut eeprom, 0x85, true));
+        assert_eq!(receive_byte(&mut eeprom, true, false), 0xA5);
+        stop(&mut eeprom);
+    }
+}
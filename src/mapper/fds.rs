@@ -0,0 +1,468 @@
+use super::fds_audio::FdsAudio;
+use super::{ChrMemory, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+use crate::fds;
+
+// 面の先頭に戻ってからデータが流れてくるまでと、1byteごとのCPUサイクル数
+const REWIND_CYCLES: u32 = 50000;
+const BYTE_CYCLES: u32 = 150;
+// 書き換えでファイルが増えても収まるように、各面はこの大きさまで空けておく
+const MIN_SIDE_CAPACITY: usize = 0x14000;
+
+// ディスクシステム (RAMアダプタ)
+// $6000-$DFFFが32KBのPRG-RAM、$E000-$FFFFがBIOS
+// ディスクは各面をヘッドが読むバイト列（ギャップとCRC付き）にして同じ間隔で並べ、そのまま保存する
+pub struct Fds {
+    bios: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    disk: Vec<u8>,
+    side_capacity: usize,
+    sides: usize,
+    inserted: Option<usize>,
+
+    // $4023: bit0 ディスクのレジスタ有効, bit1 音源有効
+    io_enable: u8,
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+
+    // $4025: bit0 モーター, bit1 転送リセット, bit2 読み込み, bit3 水平ミラー,
+    // bit4 CRCを転送, bit6 データ転送開始, bit7 転送ごとにIRQ
+    control: u8,
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    transfer_complete: bool,
+    disk_irq: bool,
+    read_data: u8,
+    write_data: u8,
+    crc: u16,
+    previous_crc_control: bool,
+
+    audio: FdsAudio,
+}
+
+impl Fds {
+    pub fn new(rom: &Rom) -> Self {
+        let encoded: Vec<Vec<u8>> = rom
+            .disk_sides
+            .iter()
+            .map(|side| fds::encode_side(side))
+            .collect();
+        let side_capacity = encoded
+            .iter()
+            .map(|side| side.len())
+            .max()
+            .unwrap_or(0)
+            .max(MIN_SIDE_CAPACITY);
+        let mut disk = Vec::with_capacity(side_capacity * encoded.len());
+        for side in &encoded {
+            disk.extend(side);
+            disk.resize(disk.len() + side_capacity - side.len(), 0);
+        }
+        Fds {
+            bios: rom.prg_rom.clone(),
+            prg_ram: vec![0; 0x8000],
+            chr: ChrMemory::new(rom),
+            disk,
+            side_capacity,
+            sides: encoded.len(),
+            inserted: (!encoded.is_empty()).then_some(0),
+            io_enable: 0,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+            control: 0,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            transfer_complete: false,
+            disk_irq: false,
+            read_data: 0,
+            write_data: 0,
+            crc: 0,
+            previous_crc_control: false,
+            audio: FdsAudio::new(),
+        }
+    }
+
+    fn disk_registers(&self) -> bool {
+        self.io_enable & 0x01 != 0
+    }
+
+    fn motor_on(&self) -> bool {
+        self.control & 0x01 != 0
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if self.timer_irq {
+            status |= 0x01;
+        }
+        if self.transfer_complete {
+            status |= 0x02;
+        }
+        if self.end_of_head {
+            status |= 0x40;
+        }
+        status
+    }
+
+    // $4032: bit0 ディスク無し, bit1 準備中, bit2 書き込み禁止（ディスクが無い時も1）
+    fn drive_status(&self) -> u8 {
+        match self.inserted {
+            None => 0x07,
+            Some(_) if !self.scanning => 0x02,
+            Some(_) => 0x00,
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled || !self.disk_registers() {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    // ヘッドの下を1byte進める
+    fn clock_disk(&mut self) {
+        let Some(side) = self.inserted else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if !self.motor_on() {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.control & 0x02 != 0 && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let offset = side * self.side_capacity + self.position;
+        let data_ready = self.control & 0x40 != 0;
+        let crc_control = self.control & 0x10 != 0;
+        let mut irq = self.control & 0x80 != 0;
+        if self.control & 0x04 != 0 {
+            let data = self.disk[offset];
+            if !self.previous_crc_control {
+                self.crc = fds::update_crc(self.crc, data);
+            }
+            if !data_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if data != 0 && !self.gap_ended {
+                // ギャップの終わりの印(0x80)ではIRQを出さない
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let mut data = 0;
+            if !crc_control {
+                self.transfer_complete = true;
+                data = self.write_data;
+                self.disk_irq |= irq;
+            }
+            if !data_ready {
+                data = 0;
+            }
+            if !crc_control {
+                self.crc = fds::update_crc(self.crc, data);
+            } else {
+                if !self.previous_crc_control {
+                    self.crc = fds::update_crc(fds::update_crc(self.crc, 0), 0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+            self.disk[offset] = data;
+            self.gap_ended = false;
+        }
+        self.previous_crc_control = crc_control;
+
+        self.position += 1;
+        if self.position >= self.side_capacity {
+            // 面の終わりでモーターが止まる
+            self.control &= !0x01;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let value = self.cpu_peek(addr);
+        match addr {
+            0x4030 => {
+                self.timer_irq = false;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            _ => {}
+        }
+        value
+    }
+
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4030 => self.status(),
+            0x4031 => self.read_data,
+            0x4032 => self.drive_status(),
+            // 拡張端子 bit7はバッテリーの状態
+            0x4033 => 0x80,
+            0x4040..=0x4092 => self.audio.read(addr),
+            0x6000..=0xDFFF => self.prg_ram[(addr - 0x6000) as usize],
+            0xE000..=0xFFFF => self.bios[(addr - 0xE000) as usize],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4020 if self.disk_registers() => {
+                self.irq_reload = (self.irq_reload & 0xFF00) | data as u16
+            }
+            0x4021 if self.disk_registers() => {
+                self.irq_reload = (self.irq_reload & 0x00FF) | (data as u16) << 8
+            }
+            0x4022 if self.disk_registers() => {
+                self.irq_repeat = data & 0x01 != 0;
+                self.irq_enabled = data & 0x02 != 0;
+                self.irq_counter = self.irq_reload;
+                if !self.irq_enabled {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.io_enable = data;
+                if !self.disk_registers() {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                }
+            }
+            0x4024 if self.disk_registers() => {
+                self.write_data = data;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_registers() => {
+                self.control = data;
+                self.disk_irq = false;
+            }
+            0x4040..=0x408A if self.io_enable & 0x02 != 0 => self.audio.write(addr, data),
+            0x6000..=0xDFFF => self.prg_ram[(addr - 0x6000) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(0, 0x2000, addr, data);
+    }
+
+    // 書き換えられたディスクを保存する
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.disk)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.disk)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.control & 0x08 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn clock(&mut self) {
+        self.clock_timer();
+        self.clock_disk();
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.sides
+    }
+
+    fn inserted_disk(&self) -> Option<usize> {
+        self.inserted
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.inserted = side.filter(|&side| side < self.sides);
+        self.end_of_head = true;
+        self.scanning = false;
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fds::test::create_side;
+    use crate::fds::{BIOS_SIZE, LEADING_GAP};
+
+    fn create_fds(sides: usize) -> Fds {
+        let mut raw = Vec::new();
+        for _ in 0..sides {
+            raw.extend(create_side(&[0xAA, 0xBB]));
+        }
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[0x1FFC] = 0x55;
+        Fds::new(&fds::load(&raw, &bios).unwrap())
+    }
+
+    // 転送フラグが立つまで回して読む 先頭のギャップを読み飛ばすだけ待つ
+    fn next_byte(mapper: &mut Fds) -> u8 {
+        for _ in 0..REWIND_CYCLES + (BYTE_CYCLES + 1) * (LEADING_GAP as u32 + 2) {
+            mapper.clock();
+            if mapper.cpu_peek(0x4030) & 0x02 != 0 {
+                return mapper.cpu_read(0x4031);
+            }
+        }
+        panic!("no data");
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut mapper = create_fds(1);
+        assert_eq!(mapper.cpu_peek(0xFFFC), 0x55);
+        mapper.cpu_write(0x6000, 0x12);
+        mapper.cpu_write(0xDFFF, 0x34);
+        assert_eq!(mapper.cpu_peek(0x6000), 0x12);
+        assert_eq!(mapper.cpu_peek(0xDFFF), 0x34);
+        mapper.ppu_write(0x1FFF, 0x56);
+        assert_eq!(mapper.ppu_peek(0x1FFF), 0x56);
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut mapper = create_fds(1);
+        // $4023で有効にするまで書けない
+        mapper.cpu_write(0x4020, 2);
+        mapper.cpu_write(0x4022, 0x02);
+        mapper.clock();
+        assert!(!mapper.irq());
+
+        mapper.cpu_write(0x4023, 0x01);
+        mapper.cpu_write(0x4020, 2);
+        mapper.cpu_write(0x4021, 0);
+        mapper.cpu_write(0x4022, 0x03);
+        for _ in 0..2 {
+            mapper.clock();
+        }
+        assert!(!mapper.irq());
+        mapper.clock();
+        assert!(mapper.irq());
+        assert_eq!(mapper.cpu_read(0x4030) & 0x01, 0x01);
+        assert!(!mapper.irq());
+        // 繰り返し
+        for _ in 0..3 {
+            mapper.clock();
+        }
+        assert!(mapper.irq());
+    }
+
+    #[test]
+    fn test_read_disk() {
+        let mut mapper = create_fds(1);
+        assert_eq!(mapper.cpu_peek(0x4032) & 0x01, 0);
+        mapper.cpu_write(0x4023, 0x01);
+        // モーターを回し、読み込みモードでギャップの終わりを待つ
+        mapper.cpu_write(0x4025, 0x45);
+        assert_eq!(next_byte(&mut mapper), 0x80);
+        assert_eq!(next_byte(&mut mapper), 0x01);
+        assert_eq!(next_byte(&mut mapper), b'*');
+        assert_eq!(mapper.cpu_peek(0x4032), 0);
+    }
+
+    #[test]
+    fn test_write_disk() {
+        let mut mapper = create_fds(1);
+        mapper.cpu_write(0x4023, 0x01);
+        // ギャップの途中から書き込みモードで書く
+        mapper.cpu_write(0x4025, 0x41);
+        mapper.cpu_write(0x4024, 0x80);
+        for _ in 0..REWIND_CYCLES + 3 + BYTE_CYCLES {
+            mapper.clock();
+        }
+        assert_eq!(mapper.disk[0], 0x80);
+        assert_eq!(mapper.disk[1], 0x80);
+        assert!(mapper.save_data().is_some_and(|data| data[1] == 0x80));
+        assert_eq!(mapper.disk[LEADING_GAP], 0x80);
+    }
+
+    #[test]
+    fn test_insert_disk() {
+        let mut mapper = create_fds(2);
+        assert_eq!(mapper.disk_sides(), 2);
+        assert_eq!(mapper.inserted_disk(), Some(0));
+        mapper.insert_disk(None);
+        assert_eq!(mapper.cpu_peek(0x4032), 0x07);
+        mapper.insert_disk(Some(1));
+        assert_eq!(mapper.inserted_disk(), Some(1));
+        mapper.insert_disk(Some(5));
+        assert_eq!(mapper.inserted_disk(), None);
+
+        // 2面目を読む
+        mapper.insert_disk(Some(1));
+        mapper.cpu_write(0x4023, 0x01);
+        mapper.cpu_write(0x4025, 0x45);
+        assert_eq!(next_byte(&mut mapper), 0x80);
+        assert_eq!(next_byte(&mut mapper), 0x01);
+    }
+}
@@ -0,0 +1,312 @@
+// ディスクシステムの音源 ($4040-$408A)
+// 64サンプルの波形メモリを周波数変調(モジュレータ)付きで鳴らす
+
+// $4089のマスター音量 (2/2, 2/3, 2/4, 2/5)
+const MASTER_VOLUMES: [u32; 4] = [36, 24, 17, 14];
+// モジュレータの表の値ごとのカウンタの変化 4はカウンタを0に戻す
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+// 音量とモジュレータの強さのエンベロープ
+struct Envelope {
+    speed: u8,
+    gain: u8,
+    increase: bool,
+    disabled: bool,
+    timer: u32,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            speed: 0,
+            gain: 0,
+            increase: false,
+            disabled: true,
+            timer: 0,
+        }
+    }
+
+    // bit7 エンベロープ無効(bit0-5をそのまま強さにする), bit6 増加, bit0-5 速さ
+    fn write(&mut self, data: u8, master_speed: u8) {
+        self.disabled = data & 0x80 != 0;
+        self.increase = data & 0x40 != 0;
+        self.speed = data & 0x3F;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.reset_timer(master_speed);
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+}
+
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool,
+    wave_position: u8,
+    wave_accumulator: u32,
+    frequency: u16,
+    wave_halt: bool,
+    envelope_halt: bool,
+    volume: Envelope,
+    master_volume: u8,
+    master_speed: u8,
+    modulator: Envelope,
+    mod_table: [u8; 64],
+    mod_position: u8,
+    // 7bitの符号付き
+    mod_counter: i8,
+    mod_frequency: u16,
+    mod_halt: bool,
+    mod_accumulator: u32,
+    output: u8,
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        FdsAudio {
+            wave: [0; 64],
+            wave_write: false,
+            wave_position: 0,
+            wave_accumulator: 0,
+            frequency: 0,
+            wave_halt: true,
+            envelope_halt: false,
+            volume: Envelope::new(),
+            master_volume: 0,
+            master_speed: 0xE8,
+            modulator: Envelope::new(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_counter: 0,
+            mod_frequency: 0,
+            mod_halt: true,
+            mod_accumulator: 0,
+            output: 0,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4040..=0x407F => self.wave[(addr & 0x3F) as usize] | 0x40,
+            0x4090 => self.volume.gain | 0x40,
+            0x4092 => self.modulator.gain | 0x40,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => self.wave[(addr & 0x3F) as usize] = data & 0x3F,
+            0x4080 => self.volume.write(data, self.master_speed),
+            0x4082 => self.frequency = (self.frequency & 0x0F00) | data as u16,
+            0x4083 => {
+                self.frequency = (self.frequency & 0x00FF) | (data as u16 & 0x0F) << 8;
+                self.wave_halt = data & 0x80 != 0;
+                self.envelope_halt = data & 0x40 != 0;
+                if self.wave_halt {
+                    self.wave_position = 0;
+                    self.wave_accumulator = 0;
+                }
+                if self.envelope_halt {
+                    self.volume.reset_timer(self.master_speed);
+                    self.modulator.reset_timer(self.master_speed);
+                }
+            }
+            0x4084 => self.modulator.write(data, self.master_speed),
+            // 7bitの値を符号付きにする
+            0x4085 => self.mod_counter = ((data << 1) as i8) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | data as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | (data as u16 & 0x0F) << 8;
+                self.mod_halt = data & 0x80 != 0;
+                if self.mod_halt {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // 止めている間だけ表に書ける 1回で2つ埋まる
+            0x4088 if self.mod_halt => {
+                let position = self.mod_position as usize;
+                self.mod_table[position] = data & 0x07;
+                self.mod_table[(position + 1) & 0x3F] = data & 0x07;
+                self.mod_position = ((position + 2) & 0x3F) as u8;
+            }
+            0x4089 => {
+                self.master_volume = data & 0x03;
+                self.wave_write = data & 0x80 != 0;
+            }
+            0x408A => self.master_speed = data,
+            _ => {}
+        }
+    }
+
+    // モジュレータによる周波数の変化
+    fn pitch_offset(&self) -> i32 {
+        let mut temp = self.mod_counter as i32 * self.modulator.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= self.frequency as i32;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        temp
+    }
+
+    // CPUの1サイクルごとに呼ぶ
+    pub fn clock(&mut self) {
+        if !self.wave_halt && !self.envelope_halt {
+            self.volume.clock(self.master_speed);
+            self.modulator.clock(self.master_speed);
+        }
+
+        if !self.mod_halt && self.mod_frequency > 0 {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator >= 0x10000 {
+                self.mod_accumulator &= 0xFFFF;
+                let step = self.mod_table[self.mod_position as usize];
+                let counter = if step == 4 {
+                    0
+                } else {
+                    self.mod_counter as i32 + MOD_STEPS[step as usize] as i32
+                };
+                // 7bitで折り返す
+                self.mod_counter = (((counter as u8) << 1) as i8) >> 1;
+                self.mod_position = (self.mod_position + 1) & 0x3F;
+            }
+        }
+
+        if self.wave_halt {
+            self.wave_position = 0;
+        } else if !self.wave_write {
+            let frequency = self.frequency as i32 + self.pitch_offset();
+            if frequency > 0 {
+                self.wave_accumulator += frequency as u32;
+                if self.wave_accumulator >= 0x10000 {
+                    self.wave_accumulator &= 0xFFFF;
+                    self.wave_position = (self.wave_position + 1) & 0x3F;
+                }
+            }
+        }
+
+        // 波形メモリに書き込んでいる間は直前の値を保つ
+        if !self.wave_write {
+            let level =
+                self.volume.gain.min(32) as u32 * MASTER_VOLUMES[self.master_volume as usize];
+            self.output = (self.wave[self.wave_position as usize] as u32 * level / 1152) as u8;
+        }
+    }
+
+    // 0.0-1.0
+    pub fn output(&self) -> f32 {
+        self.output as f32 / 63.0
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_wave(audio: &mut FdsAudio) {
+        audio.write(0x4089, 0x80);
+        for i in 0..64 {
+            audio.write(0x4040 + i, if i < 32 { 63 } else { 0 });
+        }
+        audio.write(0x4089, 0x00);
+    }
+
+    #[test]
+    fn test_wave() {
+        let mut audio = FdsAudio::new();
+        write_wave(&mut audio);
+        assert_eq!(audio.read(0x4040), 0x7F);
+        // 音量32で直接指定
+        audio.write(0x4080, 0x80 | 32);
+        assert_eq!(audio.read(0x4090), 0x40 | 32);
+        // 周波数0x400: 64サイクルで1サンプル進む
+        audio.write(0x4082, 0x00);
+        audio.write(0x4083, 0x04);
+        audio.clock();
+        assert_eq!(audio.output(), 1.0);
+        for _ in 0..32 * 64 {
+            audio.clock();
+        }
+        assert_eq!(audio.output(), 0.0);
+
+        // 止めると先頭に戻る
+        audio.write(0x4083, 0x84);
+        audio.clock();
+        assert_eq!(audio.output(), 1.0);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut audio = FdsAudio::new();
+        write_wave(&mut audio);
+        audio.write(0x408A, 1);
+        // 速さ0で増加: 8サイクルごとに1上がる
+        audio.write(0x4080, 0x40);
+        audio.write(0x4083, 0x00);
+        for _ in 0..8 * 4 {
+            audio.clock();
+        }
+        assert_eq!(audio.read(0x4090) & 0x3F, 4);
+    }
+
+    #[test]
+    fn test_modulator() {
+        let mut audio = FdsAudio::new();
+        // 表は止めている間だけ書ける
+        for _ in 0..32 {
+            audio.write(0x4088, 1);
+        }
+        audio.write(0x4084, 0x80 | 1);
+        audio.write(0x4085, 0x7F);
+        assert_eq!(audio.mod_counter, -1);
+        audio.write(0x4086, 0x00);
+        audio.write(0x4087, 0x08);
+        // 0x800で32サイクルごとに表を1つ進める
+        for _ in 0..32 {
+            audio.clock();
+        }
+        assert_eq!(audio.mod_counter, 0);
+        for _ in 0..32 * 64 {
+            audio.clock();
+        }
+        // 7bitで折り返す
+        assert_eq!(audio.mod_counter, -64);
+    }
+}
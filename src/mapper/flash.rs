@@ -0,0 +1,151 @@
+// 自分で書き換えられるPRG用のフラッシュメモリ (SST39SF040)
+// $5555に$AA、$2AAAに$55を書いてから命令を書く 命令の番地は下位15bitだけを見る
+// 書き込み・消去はすぐに終わったことにする
+
+// ソフトウェアIDで読めるメーカーと品番
+const MANUFACTURER_ID: u8 = 0xBF;
+const DEVICE_ID: u8 = 0xB7;
+const SECTOR_SIZE: usize = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ready,
+    // $AAを受け取った
+    Unlock1,
+    // $55を受け取った
+    Unlock2,
+    // 次の書き込みで1byte書く
+    Program,
+    // $80の後の$AA/$55待ち
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+    SoftwareId,
+}
+
+pub struct Flash {
+    data: Vec<u8>,
+    state: State,
+    // ソフトウェアIDの途中でも解除の手順は受け付ける
+    id_mode: bool,
+}
+
+impl Flash {
+    pub fn new(data: Vec<u8>) -> Self {
+        Flash {
+            data,
+            state: State::Ready,
+            id_mode: false,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    // offsetはチップの先頭からの位置
+    pub fn read(&self, offset: usize) -> u8 {
+        if self.id_mode {
+            return match offset & 1 {
+                0 => MANUFACTURER_ID,
+                _ => DEVICE_ID,
+            };
+        }
+        self.data[offset % self.data.len()]
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) {
+        let command = offset & 0x7FFF;
+        self.state = match (self.state, command, data) {
+            // 1byte書く 消えている(1の)bitを0にすることしかできない
+            (State::Program, _, _) => {
+                let len = self.data.len();
+                self.data[offset % len] &= data;
+                State::Ready
+            }
+            (_, _, 0xF0) => {
+                self.id_mode = false;
+                State::Ready
+            }
+            (State::Ready | State::SoftwareId, 0x5555, 0xAA) => State::Unlock1,
+            (State::Unlock1, 0x2AAA, 0x55) => State::Unlock2,
+            (State::Unlock2, 0x5555, 0xA0) => State::Program,
+            (State::Unlock2, 0x5555, 0x80) => State::Erase,
+            (State::Unlock2, 0x5555, 0x90) => {
+                self.id_mode = true;
+                State::SoftwareId
+            }
+            (State::Erase, 0x5555, 0xAA) => State::EraseUnlock1,
+            (State::EraseUnlock1, 0x2AAA, 0x55) => State::EraseUnlock2,
+            (State::EraseUnlock2, 0x5555, 0x10) => {
+                self.data.fill(0xFF);
+                State::Ready
+            }
+            (State::EraseUnlock2, _, 0x30) => {
+                let start = (offset % self.data.len()) & !(SECTOR_SIZE - 1);
+                let end = (start + SECTOR_SIZE).min(self.data.len());
+                self.data[start..end].fill(0xFF);
+                State::Ready
+            }
+            (State::SoftwareId, _, _) => State::SoftwareId,
+            _ => State::Ready,
+        };
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn command(flash: &mut Flash, data: u8) {
+        flash.write(0x5555, 0xAA);
+        flash.write(0x2AAA, 0x55);
+        flash.write(0x5555, data);
+    }
+
+    #[test]
+    fn test_program() {
+        let mut flash = Flash::new(vec![0xFF; 0x10000]);
+        command(&mut flash, 0xA0);
+        flash.write(0x1234, 0x5A);
+        assert_eq!(flash.read(0x1234), 0x5A);
+        // 手順を踏まない書き込みは無視する
+        flash.write(0x1235, 0x00);
+        assert_eq!(flash.read(0x1235), 0xFF);
+        // 0を1には戻せない
+        command(&mut flash, 0xA0);
+        flash.write(0x1234, 0xFF);
+        assert_eq!(flash.read(0x1234), 0x5A);
+    }
+
+    #[test]
+    fn test_erase() {
+        let mut flash = Flash::new(vec![0; 0x10000]);
+        command(&mut flash, 0x80);
+        flash.write(0x5555, 0xAA);
+        flash.write(0x2AAA, 0x55);
+        flash.write(0x3456, 0x30);
+        assert_eq!(flash.read(0x2FFF), 0x00);
+        assert_eq!(flash.read(0x3000), 0xFF);
+        assert_eq!(flash.read(0x3FFF), 0xFF);
+        assert_eq!(flash.read(0x4000), 0x00);
+
+        command(&mut flash, 0x80);
+        command(&mut flash, 0x10);
+        assert!(flash.data().iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn test_software_id() {
+        let mut flash = Flash::new(vec![0; 0x10000]);
+        command(&mut flash, 0x90);
+        assert_eq!((flash.read(0), flash.read(1)), (MANUFACTURER_ID, DEVICE_ID));
+        flash.write(0, 0xF0);
+        assert_eq!(flash.read(0), 0);
+    }
+}
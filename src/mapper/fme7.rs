@@ -0,0 +1,459 @@
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+// Sunsoft 5BのトーンとノイズはCPUの16サイクルごとに進む
+const AUDIO_DIVIDER: u8 = 16;
+
+// 5Bの矩形波 (AY-3-8910互換)
+struct Tone {
+    period: u16,
+    counter: u16,
+    output: bool,
+}
+
+impl Tone {
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period.max(1) {
+            self.counter = 0;
+            self.output = !self.output;
+        }
+    }
+}
+
+// エンベロープ 32段階で上下する
+struct Envelope {
+    period: u16,
+    counter: u16,
+    // RD: bit3 継続, bit2 上昇から, bit1 往復, bit0 保持
+    shape: u8,
+    step: u8,
+    attack: bool,
+    holding: bool,
+}
+
+impl Envelope {
+    fn restart(&mut self, shape: u8) {
+        self.shape = shape & 0x0F;
+        self.attack = shape & 0x04 != 0;
+        self.step = 0;
+        self.counter = 0;
+        self.holding = false;
+    }
+
+    fn clock(&mut self) {
+        if self.holding {
+            return;
+        }
+        self.counter += 1;
+        if self.counter < self.period.max(1) {
+            return;
+        }
+        self.counter = 0;
+        if self.step < 31 {
+            self.step += 1;
+            return;
+        }
+        // 1周期終わった後の動き
+        if self.shape & 0x08 == 0 {
+            self.attack = false;
+            self.holding = true;
+            return;
+        }
+        if self.shape & 0x01 != 0 {
+            if self.shape & 0x02 != 0 {
+                self.attack = !self.attack;
+            }
+            self.holding = true;
+            return;
+        }
+        if self.shape & 0x02 != 0 {
+            self.attack = !self.attack;
+        }
+        self.step = 0;
+    }
+
+    fn level(&self) -> u8 {
+        if self.holding && self.shape & 0x08 == 0 {
+            return 0;
+        }
+        let level = if self.holding { 31 } else { self.step };
+        if self.attack { level } else { 31 - level }
+    }
+}
+
+// Sunsoft 5B拡張音源 $C000にレジスタ番号、$E000に値を書く
+pub(super) struct Sunsoft5b {
+    pub(super) register: u8,
+    tones: [Tone; 3],
+    noise_period: u8,
+    noise_counter: u8,
+    // 17bitのLFSR
+    noise_shift: u32,
+    // R7: bit0-2 トーン無効, bit3-5 ノイズ無効
+    mixer: u8,
+    // R8-RA: bit4 エンベロープを使う, bit0-3 音量
+    volumes: [u8; 3],
+    envelope: Envelope,
+    divider: u8,
+}
+
+impl Sunsoft5b {
+    pub(super) fn new() -> Self {
+        let tone = || Tone {
+            period: 0,
+            counter: 0,
+            output: false,
+        };
+        Sunsoft5b {
+            register: 0,
+            tones: [tone(), tone(), tone()],
+            noise_period: 0,
+            noise_counter: 0,
+            noise_shift: 1,
+            mixer: 0xFF,
+            volumes: [0; 3],
+            envelope: Envelope {
+                period: 0,
+                counter: 0,
+                shape: 0,
+                step: 0,
+                attack: false,
+                holding: true,
+            },
+            divider: 0,
+        }
+    }
+
+    pub(super) fn write(&mut self, data: u8) {
+        match self.register {
+            0x00..=0x05 => {
+                let tone = &mut self.tones[(self.register / 2) as usize];
+                tone.period = if self.register & 1 == 0 {
+                    (tone.period & 0x0F00) | data as u16
+                } else {
+                    (tone.period & 0x00FF) | ((data & 0x0F) as u16) << 8
+                };
+            }
+            0x06 => self.noise_period = data & 0x1F,
+            0x07 => self.mixer = data,
+            0x08..=0x0A => self.volumes[(self.register - 8) as usize] = data & 0x1F,
+            0x0B => self.envelope.period = (self.envelope.period & 0xFF00) | data as u16,
+            0x0C => self.envelope.period = (self.envelope.period & 0x00FF) | (data as u16) << 8,
+            0x0D => self.envelope.restart(data),
+            _ => {}
+        }
+    }
+
+    pub(super) fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < AUDIO_DIVIDER {
+            return;
+        }
+        self.divider = 0;
+        for tone in self.tones.iter_mut() {
+            tone.clock();
+        }
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period.max(1) {
+            self.noise_counter = 0;
+            let bit = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | bit << 16;
+        }
+        self.envelope.clock();
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        let noise = self.noise_shift & 1 != 0;
+        let mut sum = 0.0;
+        for (i, tone) in self.tones.iter().enumerate() {
+            let tone_on = tone.output || self.mixer & (1 << i) != 0;
+            let noise_on = noise || self.mixer & (8 << i) != 0;
+            if !(tone_on && noise_on) {
+                continue;
+            }
+            // 4bitの音量はエンベロープの32段階の奇数段に相当する
+            let level = if self.volumes[i] & 0x10 != 0 {
+                self.envelope.level()
+            } else {
+                let volume = self.volumes[i] & 0x0F;
+                if volume == 0 { 0 } else { volume * 2 + 1 }
+            };
+            sum += volume_level(level);
+        }
+        sum / 3.0
+    }
+}
+
+// 1段1.5dBの対数音量
+fn volume_level(level: u8) -> f32 {
+    if level == 0 {
+        0.0
+    } else {
+        10f32.powf((level as f32 - 31.0) * 1.5 / 20.0)
+    }
+}
+
+// マッパー69 (Sunsoft FME-7 / 5A / 5B)
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    command: u8,
+    chr_banks: [u8; 8],
+    // コマンド8: bit7 RAM有効, bit6 RAMを選ぶ, bit0-5 バンク
+    prg_bank_6000: u8,
+    prg_banks: [u8; 3],
+    mirroring: Mirroring,
+    // コマンドD: bit0 IRQ有効, bit7 カウンタ動作
+    irq_control: u8,
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5b,
+}
+
+impl Fme7 {
+    pub fn new(rom: &Rom) -> Self {
+        Fme7 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram: vec![0; prg_ram_size(rom)],
+            command: 0,
+            chr_banks: [0; 8],
+            prg_bank_6000: 0,
+            prg_banks: [0; 3],
+            mirroring: Mirroring::Vertical,
+            irq_control: 0,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    fn ram_selected(&self) -> bool {
+        self.prg_bank_6000 & 0x40 != 0
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8 => self.prg_bank_6000 = data,
+            0x9..=0xB => self.prg_banks[(self.command - 9) as usize] = data & 0x3F,
+            0xC => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0xD => {
+                self.irq_control = data;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        match addr {
+            0x6000..=0x7FFF if self.ram_selected() => {
+                if self.prg_bank_6000 & 0x80 == 0 || self.prg_ram.is_empty() {
+                    return 0;
+                }
+                let bank = (self.prg_bank_6000 & 0x3F) as usize;
+                self.prg_ram[bank_offset(self.prg_ram.len(), bank, 0x2000, addr)]
+            }
+            0x6000..=0x7FFF => {
+                self.prg_rom[bank_offset(len, (self.prg_bank_6000 & 0x3F) as usize, 0x2000, addr)]
+            }
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) >> 13) as usize] as usize;
+                self.prg_rom[bank_offset(len, bank, 0x2000, addr)]
+            }
+            0xE000..=0xFFFF => {
+                self.prg_rom[bank_offset(len, (len / 0x2000).saturating_sub(1), 0x2000, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF
+                if self.ram_selected()
+                    && self.prg_bank_6000 & 0x80 != 0
+                    && !self.prg_ram.is_empty() =>
+            {
+                let bank = (self.prg_bank_6000 & 0x3F) as usize;
+                let offset = bank_offset(self.prg_ram.len(), bank, 0x2000, addr);
+                self.prg_ram[offset] = data;
+            }
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.register = data & 0x0F,
+            0xE000..=0xFFFF => self.audio.write(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr
+            .read(self.chr_banks[(addr >> 10) as usize] as usize, 0x0400, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+        self.chr.write(bank, 0x0400, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock(&mut self) {
+        if self.irq_control & 0x80 != 0 {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_control & 0x01 != 0 {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn create_fme7() -> Fme7 {
+        let mut raw = create_rom(0x50, 0x40, 8, 1);
+        for bank in 0..16 {
+            raw[16 + bank * 0x2000] = bank as u8;
+        }
+        Fme7::new(&Rom::new(&raw).unwrap())
+    }
+
+    fn command(mapper: &mut Fme7, command: u8, data: u8) {
+        mapper.cpu_write(0x8000, command);
+        mapper.cpu_write(0xA000, data);
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = create_fme7();
+        command(&mut mapper, 0x9, 3);
+        command(&mut mapper, 0xB, 7);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+        assert_eq!(mapper.cpu_peek(0xE000), 15);
+
+        // $6000にROMを出す
+        command(&mut mapper, 0x8, 5);
+        assert_eq!(mapper.cpu_peek(0x6000), 5);
+        // RAMを選んでも有効でなければ書けない
+        command(&mut mapper, 0x8, 0x40);
+        mapper.cpu_write(0x6000, 0x12);
+        assert_eq!(mapper.cpu_peek(0x6000), 0);
+        command(&mut mapper, 0x8, 0xC0);
+        mapper.cpu_write(0x6000, 0x12);
+        assert_eq!(mapper.cpu_peek(0x6000), 0x12);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = create_fme7();
+        command(&mut mapper, 0xE, 2);
+        command(&mut mapper, 0xF, 0);
+        command(&mut mapper, 0xD, 0x81);
+        mapper.clock();
+        mapper.clock();
+        assert!(!mapper.irq());
+        // 0から$FFFFに戻る時にIRQ
+        mapper.clock();
+        assert!(mapper.irq());
+        command(&mut mapper, 0xD, 0x81);
+        assert!(!mapper.irq());
+    }
+
+    fn audio(mapper: &mut Fme7, register: u8, data: u8) {
+        mapper.cpu_write(0xC000, register);
+        mapper.cpu_write(0xE000, data);
+    }
+
+    #[test]
+    fn test_tone() {
+        let mut mapper = create_fme7();
+        // チャンネルAのトーンだけ、周期2、最大音量
+        audio(&mut mapper, 0x00, 2);
+        audio(&mut mapper, 0x07, 0b0011_1110);
+        audio(&mut mapper, 0x08, 0x0F);
+        let mut changes = 0;
+        let mut last = mapper.audio_output();
+        for _ in 0..16 * 8 {
+            mapper.clock();
+            let output = mapper.audio_output();
+            if output != last {
+                changes += 1;
+            }
+            last = output;
+        }
+        // 16サイクル×2ごとに反転する
+        assert_eq!(changes, 4);
+        assert!(last <= 1.0 / 3.0);
+    }
+
+    #[test]
+    fn test_envelope_shapes() {
+        let mut envelope = Sunsoft5b::new().envelope;
+        envelope.period = 1;
+        // 下降して0で止まる
+        envelope.restart(0x00);
+        assert_eq!(envelope.level(), 31);
+        for _ in 0..32 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 0);
+
+        // 上昇して最大で止まる
+        envelope.restart(0x0D);
+        assert_eq!(envelope.level(), 0);
+        for _ in 0..40 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 31);
+
+        // 三角波 頂点の値は2回続く
+        envelope.restart(0x0E);
+        for _ in 0..33 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 30);
+    }
+}
@@ -0,0 +1,149 @@
+use super::flash::Flash;
+use super::{ChrMemory, Mapper, bank_offset};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー111 (GTROM)
+// $5000-$5FFFか$7000-$7FFFのレジスタで32KBのPRGバンク・8KBのCHR-RAMバンク・ネームテーブルのページを選ぶ
+// ネームテーブルは基板上のRAMの4画面で、PRGは$8000-$FFFFへの書き込みで書き換えられるフラッシュ
+pub struct Gtrom {
+    flash: Flash,
+    chr: ChrMemory,
+    // 8KBずつ2ページ 4画面に使うのは各ページの先頭4KB
+    nametables: Vec<u8>,
+    // bit0-3 PRGバンク, bit4 CHRバンク, bit5 ネームテーブルのページ, bit6 緑のLED, bit7 赤のLED (0で点灯)
+    register: u8,
+}
+
+impl Gtrom {
+    pub fn new(rom: &Rom) -> Self {
+        Gtrom {
+            flash: Flash::new(rom.prg_rom.clone()),
+            chr: ChrMemory::with_min_ram(rom, 0x4000),
+            nametables: vec![0; 0x4000],
+            register: 0,
+        }
+    }
+
+    fn prg_bank(&self) -> usize {
+        (self.register & 0x0F) as usize
+    }
+
+    fn chr_bank(&self) -> usize {
+        ((self.register >> 4) & 0x01) as usize
+    }
+
+    fn nametable_offset(&self, addr: u16) -> usize {
+        ((self.register >> 5) & 0x01) as usize * 0x2000 + (addr & 0x0FFF) as usize
+    }
+}
+
+impl Mapper for Gtrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let len = self.flash.data().len();
+                self.flash
+                    .read(bank_offset(len, self.prg_bank(), 0x8000, addr))
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5FFF | 0x7000..=0x7FFF => self.register = data,
+            // フラッシュの番地はPRGバンクの番号とA0-A14
+            0x8000..=0xFFFF => {
+                let offset = self.prg_bank() << 15 | (addr & 0x7FFF) as usize;
+                self.flash.write(offset, data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank(), 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank(), 0x2000, addr, data);
+    }
+
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        Some(self.nametables[self.nametable_offset(addr)])
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8) -> bool {
+        let offset = self.nametable_offset(addr);
+        self.nametables[offset] = data;
+        true
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(self.flash.data())
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(self.flash.data_mut())
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::FourScreen
+    }
+
+    // bit0 赤, bit1 緑
+    fn leds(&self) -> u8 {
+        !self.register >> 7 & 0x01 | (!self.register >> 5) & 0x02
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = Gtrom::new(&Rom::new(&create_rom(0xF8, 0x60, 8, 0)).unwrap());
+        mapper.cpu_write(0x5000, 0x02);
+        assert_eq!(mapper.cpu_peek(0x8000), 4);
+        assert_eq!(mapper.cpu_peek(0xC000), 5);
+
+        mapper.ppu_write(0x0000, 0x55);
+        mapper.cpu_write(0x7000, 0x10);
+        assert_eq!(mapper.ppu_peek(0x0000), 0x00);
+
+        // ネームテーブルは4画面でページを切り替えられる
+        assert!(mapper.nametable_write(0x2C00, 0x66));
+        assert_eq!(mapper.nametable_peek(0x2C00), Some(0x66));
+        assert_eq!(mapper.nametable_peek(0x2000), Some(0x00));
+        mapper.cpu_write(0x7000, 0x20);
+        assert_eq!(mapper.nametable_peek(0x2C00), Some(0x00));
+    }
+
+    #[test]
+    fn test_leds() {
+        let mut mapper = Gtrom::new(&Rom::new(&create_rom(0xF8, 0x60, 8, 0)).unwrap());
+        assert_eq!(mapper.leds(), 0b11);
+        mapper.cpu_write(0x5000, 0x80);
+        assert_eq!(mapper.leds(), 0b10);
+        mapper.cpu_write(0x5000, 0x40);
+        assert_eq!(mapper.leds(), 0b01);
+    }
+
+    #[test]
+    fn test_self_flash() {
+        let mut mapper = Gtrom::new(&Rom::new(&create_rom(0xF8, 0x60, 8, 0)).unwrap());
+        mapper.cpu_write(0x5000, 0x01);
+        // $5555はバンク0の$D555、$2AAAはバンク0の$AAAA
+        for (bank, addr, data) in [(0, 0xD555, 0xAA), (0, 0xAAAA, 0x55), (0, 0xD555, 0xA0)] {
+            mapper.cpu_write(0x5000, bank);
+            mapper.cpu_write(addr, data);
+        }
+        mapper.cpu_write(0x5000, 0x01);
+        mapper.cpu_write(0x8123, 0x00);
+        assert_eq!(mapper.cpu_peek(0x8123), 0x00);
+        assert_eq!(mapper.cpu_peek(0x8124), 2);
+    }
+}
@@ -0,0 +1,363 @@
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+// SxROM基板の種類 CHRバンクレジスタの上位ビットの使い方が違う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    // 通常のSxROM
+    Standard,
+    // SEROM/SHROM: PRGは32KB固定
+    Serom,
+    // SOROM: CHRバンクのbit3で16KBのPRG-RAMを切り替える
+    Sorom,
+    // SUROM: CHRバンクのbit4で512KBのPRG-ROMの256KB単位を切り替える
+    Surom,
+    // SXROM: SUROMに加えてbit2-3で32KBのPRG-RAMを切り替える
+    Sxrom,
+}
+
+impl Board {
+    // NES 2.0のサブマッパー（1-3は非推奨だが明示的な指定として扱う）か、ROM/RAMの大きさから判断する
+    fn detect(rom: &Rom) -> Board {
+        match rom.submapper {
+            1 => return Board::Surom,
+            2 => return Board::Sorom,
+            3 => return Board::Sxrom,
+            5 => return Board::Serom,
+            _ => {}
+        }
+        let ram = prg_ram_size(rom);
+        if ram >= 0x8000 {
+            Board::Sxrom
+        } else if rom.prg_rom.len() > 0x40000 {
+            Board::Surom
+        } else if ram >= 0x4000 {
+            Board::Sorom
+        } else {
+            Board::Standard
+        }
+    }
+}
+
+// マッパー1 (MMC1)
+// レジスタには$8000-$FFFFへの5回の書き込みでbit0を1bitずつ送る
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    board: Board,
+
+    shift_register: u8,
+    shift_count: u8,
+    // $8000-$9FFF: bit0-1 ミラーリング, bit2-3 PRGバンクモード, bit4 CHRバンクモード
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    // bit0-3 PRGバンク, bit4 PRG-RAM無効
+    prg_bank: u8,
+
+    // 連続したサイクルの書き込み（RMW命令など）の2回目は無視される
+    cycle: u64,
+    last_write_cycle: Option<u64>,
+    // 4KB CHRモードで最後にPPUがアクセスした側（SUROMなどの上位ビットの選択に使う）
+    chr_a12: bool,
+}
+
+impl Mmc1 {
+    pub fn new(rom: &Rom) -> Self {
+        Mmc1 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram: vec![0; prg_ram_size(rom)],
+            board: Board::detect(rom),
+            shift_register: 0,
+            shift_count: 0,
+            // 電源投入時は最後のバンクが$C000に固定されている
+            control: 0x0C,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write_cycle: None,
+            chr_a12: false,
+        }
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    // PRG-ROMの上位ビットやPRG-RAMのバンクに使われるCHRバンクレジスタ
+    fn outer_register(&self) -> u8 {
+        if self.control & 0x10 != 0 && self.chr_a12 {
+            self.chr_bank1
+        } else {
+            self.chr_bank0
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank0 = value,
+            0xC000..=0xDFFF => self.chr_bank1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    // $8000-$FFFFの16KBバンク番号
+    fn prg_bank_for(&self, addr: u16) -> usize {
+        if self.board == Board::Serom {
+            return ((addr - 0x8000) / 0x4000) as usize;
+        }
+        let outer = match self.board {
+            Board::Surom | Board::Sxrom => (self.outer_register() & 0x10) as usize,
+            _ => 0,
+        };
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let bank = match ((self.control >> 2) & 0b11, addr) {
+            // 32KBモード
+            (0 | 1, 0x8000..=0xBFFF) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            // 最初のバンクを$8000に固定
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            // 最後のバンクを$C000に固定
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => 0x0F,
+        };
+        outer | bank
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.prg_bank & 0x10 == 0
+    }
+
+    fn prg_ram_offset(&self, addr: u16) -> usize {
+        let bank = match self.board {
+            Board::Sorom => ((self.outer_register() >> 3) & 0b1) as usize,
+            Board::Sxrom => ((self.outer_register() >> 2) & 0b11) as usize,
+            _ => 0,
+        };
+        bank_offset(self.prg_ram.len(), bank, 0x2000, addr)
+    }
+
+    // $0000-$1FFFの4KBバンク番号
+    fn chr_bank_for(&self, addr: u16) -> usize {
+        if self.control & 0x10 == 0 {
+            // 8KBモード
+            (self.chr_bank0 & 0x1E) as usize | (addr >> 12) as usize
+        } else if addr < 0x1000 {
+            self.chr_bank0 as usize
+        } else {
+            self.chr_bank1 as usize
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[self.prg_ram_offset(addr)],
+            0x8000..=0xFFFF => {
+                let offset = bank_offset(self.prg_rom.len(), self.prg_bank_for(addr), 0x4000, addr);
+                self.prg_rom[offset]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let offset = self.prg_ram_offset(addr);
+                self.prg_ram[offset] = data;
+            }
+            0x8000..=0xFFFF => {
+                let consecutive = self
+                    .last_write_cycle
+                    .is_some_and(|last| self.cycle.wrapping_sub(last) < 2);
+                self.last_write_cycle = Some(self.cycle);
+                if consecutive {
+                    return;
+                }
+
+                // bit7が立っていればシフトレジスタをリセットしPRGモード3にする
+                if data & 0x80 != 0 {
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0C;
+                    return;
+                }
+                self.shift_register |= (data & 1) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    let value = self.shift_register;
+                    self.write_register(addr, value);
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr_a12 = addr & 0x1000 != 0;
+        self.ppu_peek(addr)
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank_for(addr), 0x1000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr_a12 = addr & 0x1000 != 0;
+        self.chr.write(self.chr_bank_for(addr), 0x1000, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    // 5回に分けてレジスタに書き込む
+    fn write_serial(mapper: &mut Mmc1, addr: u16, value: u8) {
+        for i in 0..5 {
+            mapper.cpu_write(addr, (value >> i) & 1);
+            mapper.clock();
+            mapper.clock();
+        }
+    }
+
+    fn create_mmc1(prg_pages: u8, chr_pages: u8) -> Mmc1 {
+        Mmc1::new(&Rom::new(&create_rom(0x10, 0, prg_pages, chr_pages)).unwrap())
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mapper = create_mmc1(8, 1);
+        assert_eq!(mapper.cpu_peek(0x8000), 0);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+    }
+
+    #[test]
+    fn test_prg_bank_modes() {
+        let mut mapper = create_mmc1(8, 1);
+        write_serial(&mut mapper, 0xE000, 3);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+
+        // 最初のバンクを固定
+        write_serial(&mut mapper, 0x8000, 0b01000);
+        assert_eq!(mapper.cpu_peek(0x8000), 0);
+        assert_eq!(mapper.cpu_peek(0xC000), 3);
+
+        // 32KBモードは下位ビットを無視する
+        write_serial(&mut mapper, 0x8000, 0b00000);
+        assert_eq!(mapper.cpu_peek(0x8000), 2);
+        assert_eq!(mapper.cpu_peek(0xC000), 3);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn test_reset_bit_and_consecutive_writes() {
+        let mut mapper = create_mmc1(8, 1);
+        mapper.cpu_write(0xE000, 1);
+        mapper.clock();
+        mapper.clock();
+        mapper.cpu_write(0xE000, 0x80);
+        mapper.clock();
+        mapper.clock();
+        write_serial(&mut mapper, 0xE000, 5);
+        assert_eq!(mapper.cpu_peek(0x8000), 5);
+
+        // RMW命令のように連続したサイクルの書き込みは2回目が無視される
+        mapper.cpu_write(0xE000, 0);
+        mapper.clock();
+        mapper.cpu_write(0xE000, 1);
+        for _ in 0..4 {
+            mapper.clock();
+            mapper.clock();
+            mapper.cpu_write(0xE000, 0);
+        }
+        assert_eq!(mapper.cpu_peek(0x8000), 0);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut raw = create_rom(0x10, 0, 2, 4);
+        // CHR 4KBごとに番号を振る
+        for bank in 0..8 {
+            let start = 16 + 2 * 0x4000 + bank * 0x1000;
+            raw[start] = bank as u8;
+        }
+        let mut mapper = Mmc1::new(&Rom::new(&raw).unwrap());
+        write_serial(&mut mapper, 0xA000, 5);
+        assert_eq!(mapper.ppu_read(0x0000), 4);
+        assert_eq!(mapper.ppu_read(0x1000), 5);
+
+        write_serial(&mut mapper, 0x8000, 0b10000);
+        write_serial(&mut mapper, 0xC000, 7);
+        assert_eq!(mapper.ppu_read(0x0000), 5);
+        assert_eq!(mapper.ppu_read(0x1000), 7);
+    }
+
+    #[test]
+    fn test_surom_outer_bank() {
+        let mapper = create_mmc1(32, 0);
+        assert_eq!(mapper.board(), Board::Surom);
+        let mut mapper = mapper;
+        write_serial(&mut mapper, 0xA000, 0x10);
+        assert_eq!(mapper.cpu_peek(0xC000), 31);
+        write_serial(&mut mapper, 0xA000, 0x00);
+        assert_eq!(mapper.cpu_peek(0xC000), 15);
+    }
+
+    #[test]
+    fn test_sorom_prg_ram_bank() {
+        let mut raw = create_rom(0x12, 0x08, 2, 0);
+        raw[10] = 0x77; // PRG-RAM 8KB + PRG-NVRAM 8KB
+        raw[11] = 0x07; // CHR-RAM 8KB
+        let mut mapper = Mmc1::new(&Rom::new(&raw).unwrap());
+        assert_eq!(mapper.board(), Board::Sorom);
+
+        mapper.cpu_write(0x6000, 0x11);
+        write_serial(&mut mapper, 0xA000, 0x08);
+        assert_eq!(mapper.cpu_peek(0x6000), 0x00);
+        mapper.cpu_write(0x6000, 0x22);
+        write_serial(&mut mapper, 0xA000, 0x00);
+        assert_eq!(mapper.cpu_peek(0x6000), 0x11);
+
+        // PRG-RAM無効
+        write_serial(&mut mapper, 0xE000, 0x10);
+        assert_eq!(mapper.cpu_peek(0x6000), 0x00);
+    }
+}
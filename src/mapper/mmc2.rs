@@ -0,0 +1,208 @@
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    // マッパー9: $8000に8KBのPRGバンク、残り24KBは固定 (Punch-Out!!)
+    Mmc2,
+    // マッパー10: $8000に16KBのPRGバンク、$C000は固定 PRG-RAMあり (Fire Emblem)
+    Mmc4,
+}
+
+// マッパー9 (MMC2) / マッパー10 (MMC4)
+// PPUがタイル$FD/$FEのパターンを読むとラッチが切り替わり、それ以降のCHRバンクが変わる
+pub struct Mmc2 {
+    chip: Chip,
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    prg_bank: u8,
+    // [ラッチがFDの時, FEの時]の4KB CHRバンク
+    chr_banks0: [u8; 2],
+    chr_banks1: [u8; 2],
+    // false: $FD, true: $FE
+    latch0: bool,
+    latch1: bool,
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(rom: &Rom, chip: Chip) -> Self {
+        let prg_ram = match chip {
+            Chip::Mmc2 => Vec::new(),
+            Chip::Mmc4 => vec![0; prg_ram_size(rom)],
+        };
+        Mmc2 {
+            chip,
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram,
+            prg_bank: 0,
+            chr_banks0: [0; 2],
+            chr_banks1: [0; 2],
+            latch0: true,
+            latch1: true,
+            mirroring: Mirroring::Vertical,
+        }
+    }
+
+    fn chr_bank_for(&self, addr: u16) -> usize {
+        if addr < 0x1000 {
+            self.chr_banks0[self.latch0 as usize] as usize
+        } else {
+            self.chr_banks1[self.latch1 as usize] as usize
+        }
+    }
+
+    // パターンの読み込み後にラッチを更新する
+    // MMC2の$0000側は$0FD8/$0FE8ちょうどだけ、それ以外は8byteの範囲で反応する
+    fn update_latch(&mut self, addr: u16) {
+        match (self.chip, addr) {
+            (Chip::Mmc2, 0x0FD8) | (Chip::Mmc4, 0x0FD8..=0x0FDF) => self.latch0 = false,
+            (Chip::Mmc2, 0x0FE8) | (Chip::Mmc4, 0x0FE8..=0x0FEF) => self.latch0 = true,
+            (_, 0x1FD8..=0x1FDF) => self.latch1 = false,
+            (_, 0x1FE8..=0x1FEF) => self.latch1 = true,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        match (self.chip, addr) {
+            (_, 0x6000..=0x7FFF) if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            (Chip::Mmc2, 0x8000..=0x9FFF) => {
+                self.prg_rom[bank_offset(len, (self.prg_bank & 0x0F) as usize, 0x2000, addr)]
+            }
+            (Chip::Mmc2, 0xA000..=0xFFFF) => {
+                // 最後の3バンク
+                let bank = (len / 0x2000).saturating_sub(4) + ((addr - 0x8000) / 0x2000) as usize;
+                self.prg_rom[bank_offset(len, bank, 0x2000, addr)]
+            }
+            (Chip::Mmc4, 0x8000..=0xBFFF) => {
+                self.prg_rom[bank_offset(len, (self.prg_bank & 0x0F) as usize, 0x4000, addr)]
+            }
+            (Chip::Mmc4, 0xC000..=0xFFFF) => {
+                self.prg_rom[bank_offset(len, (len / 0x4000).saturating_sub(1), 0x4000, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0xA000..=0xAFFF => self.prg_bank = data,
+            0xB000..=0xBFFF => self.chr_banks0[0] = data & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks0[1] = data & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks1[0] = data & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks1[1] = data & 0x1F,
+            0xF000..=0xFFFF => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let data = self.ppu_peek(addr);
+        self.update_latch(addr);
+        data
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank_for(addr), 0x1000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank_for(addr), 0x1000, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn create(flags6: u8, prg_pages: u8, chip: Chip) -> Mmc2 {
+        let mut raw = create_rom(flags6, 0, prg_pages, 4);
+        // CHR 4KBごとに番号を振る
+        for bank in 0..8 {
+            raw[16 + prg_pages as usize * 0x4000 + bank * 0x1000] = 0x40 + bank as u8;
+        }
+        Mmc2::new(&Rom::new(&raw).unwrap(), chip)
+    }
+
+    #[test]
+    fn test_mmc2_prg() {
+        let mut mapper = create(0x90, 8, Chip::Mmc2);
+        mapper.cpu_write(0xA000, 5);
+        assert_eq!(mapper.cpu_peek(0x8000), 2);
+        // $A000-$FFFFは最後の3バンク（8KBバンク13-15）
+        assert_eq!(mapper.cpu_peek(0xA000), 6);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+        assert_eq!(mapper.cpu_peek(0xE000), 7);
+    }
+
+    #[test]
+    fn test_mmc2_latch() {
+        let mut mapper = create(0x90, 8, Chip::Mmc2);
+        mapper.cpu_write(0xB000, 1);
+        mapper.cpu_write(0xC000, 2);
+        mapper.cpu_write(0xD000, 3);
+        mapper.cpu_write(0xE000, 4);
+        assert_eq!(mapper.ppu_read(0x0000), 0x42);
+        assert_eq!(mapper.ppu_read(0x1000), 0x44);
+
+        // 読み込んだタイル自体は切り替わる前のバンク
+        mapper.ppu_read(0x0FD8);
+        assert_eq!(mapper.ppu_read(0x0000), 0x41);
+        // MMC2の$0000側は$0FD8ちょうどでしか反応しない
+        mapper.ppu_read(0x0FE9);
+        assert_eq!(mapper.ppu_read(0x0000), 0x41);
+        mapper.ppu_read(0x1FDA);
+        assert_eq!(mapper.ppu_read(0x1000), 0x43);
+    }
+
+    #[test]
+    fn test_mmc4() {
+        let mut mapper = create(0xA0, 8, Chip::Mmc4);
+        mapper.cpu_write(0xA000, 3);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+
+        mapper.cpu_write(0x6000, 0x99);
+        assert_eq!(mapper.cpu_peek(0x6000), 0x99);
+
+        mapper.cpu_write(0xB000, 1);
+        mapper.ppu_read(0x0FDB);
+        assert_eq!(mapper.ppu_read(0x0000), 0x41);
+    }
+}
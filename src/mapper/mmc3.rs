@@ -0,0 +1,339 @@
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+// IRQカウンタの挙動の違い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Revision {
+    // MMC3B/C (Sharp): カウンタが0になるたびにIRQ ラッチが0なら毎ライン発生する
+    Sharp,
+    // MMC3A (NEC): デクリメントで0になった時と$C001によるリロードの時だけIRQ
+    Nec,
+}
+
+// A12がこのCPUサイクル数以上Lowだった後の立ち上がりだけを数える
+// スプライト取得中の短いLow（ネームテーブルのダミー読み込み）を無視するため
+const A12_FILTER_CYCLES: u64 = 3;
+
+// マッパー4 (MMC3)
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    four_screen: bool,
+
+    // $8000: bit0-2 書き込むレジスタ, bit6 PRGモード, bit7 CHRのA12反転
+    bank_select: u8,
+    // R0-R7
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    // $A001: bit7 PRG-RAM有効, bit6 書き込み禁止
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    revision: Revision,
+
+    cycle: u64,
+    a12: bool,
+    a12_low_since: u64,
+}
+
+impl Mmc3 {
+    pub fn new(rom: &Rom) -> Self {
+        Mmc3 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram: vec![0; prg_ram_size(rom)],
+            four_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.screen_mirroring,
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            // NES 2.0のサブマッパー4はMMC3A
+            revision: if rom.submapper == 4 {
+                Revision::Nec
+            } else {
+                Revision::Sharp
+            },
+            cycle: 0,
+            a12: false,
+            a12_low_since: 0,
+        }
+    }
+
+    pub fn set_revision(&mut self, revision: Revision) {
+        self.revision = revision;
+    }
+
+    // $8000-$FFFFの8KBバンク番号
+    fn prg_bank_for(&self, addr: u16) -> usize {
+        let second_last = (self.prg_rom.len() / 0x2000).saturating_sub(2);
+        let swap = self.bank_select & 0x40 != 0;
+        match (addr, swap) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => {
+                (self.registers[6] & 0x3F) as usize
+            }
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => (self.registers[7] & 0x3F) as usize,
+            _ => second_last + 1,
+        }
+    }
+
+    // $0000-$1FFFの1KBバンク番号
+    fn chr_bank_for(&self, addr: u16) -> usize {
+        // A12反転時は$0000側と$1000側を入れ替える
+        let addr = if self.bank_select & 0x80 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        match addr {
+            0x0000..=0x07FF => (self.registers[0] & 0xFE) as usize | ((addr >> 10) & 1) as usize,
+            0x0800..=0x0FFF => (self.registers[1] & 0xFE) as usize | ((addr >> 10) & 1) as usize,
+            _ => self.registers[2 + ((addr - 0x1000) >> 10) as usize] as usize,
+        }
+    }
+
+    fn prg_ram_readable(&self) -> bool {
+        !self.prg_ram.is_empty() && self.prg_ram_protect & 0x80 != 0
+    }
+
+    // A12の立ち上がりでスキャンラインカウンタを動かす
+    fn clock_irq_counter(&mut self) {
+        let before = self.irq_counter;
+        let reload = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        let fire = match self.revision {
+            Revision::Sharp => self.irq_counter == 0,
+            Revision::Nec => self.irq_counter == 0 && (before > 0 || reload),
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+        self.irq_reload = false;
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_readable() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => {
+                let offset = bank_offset(self.prg_rom.len(), self.prg_bank_for(addr), 0x2000, addr);
+                self.prg_rom[offset]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_readable() && self.prg_ram_protect & 0x40 == 0 => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => self.registers[(self.bank_select & 0x07) as usize] = data,
+            0xA000..=0xBFFF if even => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            0xA000..=0xBFFF => self.prg_ram_protect = data,
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank_for(addr), 0x0400, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank_for(addr), 0x0400, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FourScreen
+        } else {
+            self.mirroring
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn ppu_bus(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12 && self.cycle - self.a12_low_since >= A12_FILTER_CYCLES {
+            self.clock_irq_counter();
+        }
+        if !a12 && self.a12 {
+            self.a12_low_since = self.cycle;
+        }
+        self.a12 = a12;
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn create_mmc3() -> Mmc3 {
+        let mut raw = create_rom(0x40, 0, 8, 0);
+        // 8KBごとに番号を振る
+        for bank in 0..16 {
+            raw[16 + bank * 0x2000] = bank as u8;
+        }
+        Mmc3::new(&Rom::new(&raw).unwrap())
+    }
+
+    // 1ライン分のPPUのアクセスを真似る BGは$0000、スプライトは$1000から読む
+    fn scanline(mapper: &mut Mmc3) {
+        for _ in 0..34 {
+            mapper.ppu_bus(0x2000);
+            mapper.ppu_bus(0x0000);
+            mapper.clock();
+            mapper.clock();
+        }
+        for _ in 0..8 {
+            mapper.ppu_bus(0x2000);
+            mapper.ppu_bus(0x1000);
+            mapper.clock();
+            mapper.clock();
+        }
+        mapper.clock();
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = create_mmc3();
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 3);
+        mapper.cpu_write(0x8000, 7);
+        mapper.cpu_write(0x8001, 4);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+        assert_eq!(mapper.cpu_peek(0xA000), 4);
+        assert_eq!(mapper.cpu_peek(0xC000), 14);
+        assert_eq!(mapper.cpu_peek(0xE000), 15);
+
+        mapper.cpu_write(0x8000, 0x40);
+        assert_eq!(mapper.cpu_peek(0x8000), 14);
+        assert_eq!(mapper.cpu_peek(0xC000), 3);
+    }
+
+    #[test]
+    fn test_chr_inversion() {
+        let mut mapper = create_mmc3();
+        mapper.ppu_write(0x0400 * 5, 0x55);
+        mapper.ppu_write(0x1C00, 0x77);
+        // R5は初期値7なので$1C00は1KBバンク7
+        assert_eq!(mapper.ppu_read(0x1C00), 0x77);
+
+        mapper.cpu_write(0x8000, 0x80 | 2);
+        mapper.cpu_write(0x8001, 5);
+        assert_eq!(mapper.ppu_read(0x0000), 0x55);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mapper = create_mmc3();
+        mapper.cpu_write(0xC000, 2);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+
+        scanline(&mut mapper); // リロードして2
+        assert!(!mapper.irq());
+        scanline(&mut mapper); // 1
+        assert!(!mapper.irq());
+        scanline(&mut mapper); // 0でIRQ
+        assert!(mapper.irq());
+
+        mapper.cpu_write(0xE000, 0);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_a12_filter() {
+        let mut mapper = create_mmc3();
+        mapper.cpu_write(0xC000, 0);
+        mapper.cpu_write(0xE001, 0);
+        // 短いLowを挟んだ立ち上がりは数えない
+        mapper.ppu_bus(0x1000);
+        mapper.ppu_bus(0x2000);
+        mapper.ppu_bus(0x1000);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_latch_zero_by_revision() {
+        // Sharpはラッチ0だと毎ラインIRQ
+        let mut mapper = create_mmc3();
+        mapper.cpu_write(0xC000, 0);
+        mapper.cpu_write(0xE001, 0);
+        scanline(&mut mapper);
+        mapper.cpu_write(0xE000, 0);
+        mapper.cpu_write(0xE001, 0);
+        scanline(&mut mapper);
+        assert!(mapper.irq());
+
+        // NECは$C001によるリロードの時だけ
+        let mut mapper = create_mmc3();
+        mapper.set_revision(Revision::Nec);
+        mapper.cpu_write(0xC000, 0);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+        scanline(&mut mapper);
+        assert!(mapper.irq());
+        mapper.cpu_write(0xE000, 0);
+        mapper.cpu_write(0xE001, 0);
+        scanline(&mut mapper);
+        assert!(!mapper.irq());
+    }
+}
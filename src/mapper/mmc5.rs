@@ -0,0 +1,735 @@
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::apu::Pulse;
+use crate::cartridge::{Mirroring, Rom};
+
+// スキャンライン検出後のPPUの読み込み回数で、今どの取得をしているかがわかる
+// 背景32タイル(4回ずつ) → スプライト8個(4回ずつ) → 次のラインの先頭2タイル → ダミー2回
+const SPRITE_FETCH_START: u16 = 128;
+const PREFETCH_START: u16 = 160;
+const PREFETCH_END: u16 = 168;
+
+// PPUの読み込みがこのCPUサイクル数途切れたら描画外とみなす
+const IDLE_CYCLES: u8 = 3;
+
+// 音源のエンベロープと長さカウンタはフレームカウンタではなく240Hzで動く
+const AUDIO_FRAME_CYCLES: u16 = 7457;
+
+// MMC5拡張音源 ($5000-$5015)
+// 本体と同じ矩形波2つ (スイープなし) と、$5011に書いた値をそのまま出すPCM
+pub(super) struct Mmc5Audio {
+    pulses: [Pulse; 2],
+    pcm: u8,
+    // $5010 bit0 読み込みモード (未対応)
+    pcm_control: u8,
+    frame_divider: u16,
+    odd_cycle: bool,
+}
+
+impl Mmc5Audio {
+    pub(super) fn new() -> Self {
+        Mmc5Audio {
+            pulses: [Pulse::without_sweep(), Pulse::without_sweep()],
+            pcm: 0,
+            pcm_control: 0,
+            frame_divider: 0,
+            odd_cycle: false,
+        }
+    }
+
+    // $5015 長さカウンタが残っているか
+    pub(super) fn status(&self) -> u8 {
+        self.pulses[0].active() as u8 | (self.pulses[1].active() as u8) << 1
+    }
+
+    pub(super) fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulses[0].write(addr - 0x5000, data),
+            0x5004..=0x5007 => self.pulses[1].write(addr - 0x5004, data),
+            0x5010 => self.pcm_control = data,
+            // 書き込みモードで0を書いても変わらない
+            0x5011 if self.pcm_control & 0x01 == 0 && data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulses[0].set_enabled(data & 0x01 != 0);
+                self.pulses[1].set_enabled(data & 0x02 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    pub(super) fn clock(&mut self) {
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock();
+            }
+        }
+        self.frame_divider += 1;
+        if self.frame_divider == AUDIO_FRAME_CYCLES {
+            self.frame_divider = 0;
+            for pulse in self.pulses.iter_mut() {
+                pulse.quarter_frame();
+                pulse.half_frame();
+            }
+        }
+    }
+
+    // 矩形波とPCMを半分ずつ 0.0-1.0
+    pub(super) fn output(&self) -> f32 {
+        let pulse = (self.pulses[0].output() + self.pulses[1].output()) as f32 / 30.0;
+        (pulse + self.pcm as f32 / 255.0) / 2.0
+    }
+}
+
+// PRGの読み書き先
+enum Prg {
+    Rom(usize),
+    Ram(usize),
+}
+
+// マッパー5 (MMC5)
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    exram: Vec<u8>,
+
+    // $5100/$5101
+    prg_mode: u8,
+    chr_mode: u8,
+    // $5102/$5103 それぞれ2と1の時だけPRG-RAMに書き込める
+    prg_ram_protect: [u8; 2],
+    // $5104: 0 ネームテーブル, 1 拡張属性, 2 CPUから読み書き, 3 CPUから読み込みのみ
+    exram_mode: u8,
+    // $5105: ネームテーブルごとに2bit (0/1 CIRAM, 2 ExRAM, 3 フィルモード)
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    // $5113
+    prg_ram_bank: u8,
+    // $5114-$5117 bit7が1ならROM（$5117は常にROM）
+    prg_banks: [u8; 4],
+    // $5120-$5127（スプライト用）と$5128-$512B（背景用） $5130の上位ビットを含む
+    sprite_chr_banks: [u16; 8],
+    background_chr_banks: [u16; 4],
+    chr_upper: u8,
+    // 8x8スプライトの時は最後に書き込んだ方のバンクを背景とスプライトの両方に使う
+    last_write_background: bool,
+
+    // $5200: bit7 有効, bit6 右側, bit0-4 タイル数
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    // PPUの様子
+    sprite_8x16: bool,
+    in_frame: bool,
+    scanline: u8,
+    last_ppu_addr: u16,
+    same_nametable_reads: u8,
+    fetch: u16,
+    idle: u8,
+    // 直前に取得したタイルの拡張属性
+    tile_attribute: u8,
+
+    audio: Mmc5Audio,
+}
+
+impl Mmc5 {
+    pub fn new(rom: &Rom) -> Self {
+        // iNESヘッダでは大きさがわからないので最大の64KBを載せる
+        let prg_ram_size = if rom.nes2 { prg_ram_size(rom) } else { 0x10000 };
+        Mmc5 {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: vec![0; prg_ram_size],
+            chr: ChrMemory::new(rom),
+            exram: vec![0; 0x400],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_ram_bank: 0,
+            prg_banks: [0xFF; 4],
+            sprite_chr_banks: [0; 8],
+            background_chr_banks: [0; 4],
+            chr_upper: 0,
+            last_write_background: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            sprite_8x16: false,
+            in_frame: false,
+            scanline: 0,
+            last_ppu_addr: 0,
+            same_nametable_reads: 0,
+            fetch: 0,
+            idle: 0,
+            tile_attribute: 0,
+            audio: Mmc5Audio::new(),
+        }
+    }
+
+    fn prg_for(&self, addr: u16) -> Option<Prg> {
+        if let 0x6000..=0x7FFF = addr {
+            if self.prg_ram.is_empty() {
+                return None;
+            }
+            let bank = (self.prg_ram_bank & 0x07) as usize;
+            return Some(Prg::Ram(bank_offset(
+                self.prg_ram.len(),
+                bank,
+                0x2000,
+                addr,
+            )));
+        }
+        let (register, size) = match (self.prg_mode, addr) {
+            (0, _) => (3, 0x8000),
+            (1 | 2, 0x8000..=0xBFFF) => (1, 0x4000),
+            (1, _) => (3, 0x4000),
+            (2, 0xC000..=0xDFFF) => (2, 0x2000),
+            (2, _) => (3, 0x2000),
+            _ => (((addr - 0x8000) >> 13) as usize, 0x2000),
+        };
+        // 番号は常に8KB単位 大きいバンクでは下位ビットを無視する
+        let value = self.prg_banks[register];
+        let bank = (value & 0x7F) as usize / (size / 0x2000);
+        if register == 3 || value & 0x80 != 0 {
+            Some(Prg::Rom(bank_offset(self.prg_rom.len(), bank, size, addr)))
+        } else if self.prg_ram.is_empty() {
+            None
+        } else {
+            let bank = (value & 0x07) as usize / (size / 0x2000);
+            Some(Prg::Ram(bank_offset(self.prg_ram.len(), bank, size, addr)))
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01]
+    }
+
+    // 背景の取得中なら(画面上のタイルの列, ライン)
+    fn background_tile(&self) -> Option<(u16, u16)> {
+        if !self.in_frame {
+            return None;
+        }
+        match self.fetch {
+            0..SPRITE_FETCH_START => Some((self.fetch / 4 + 2, self.scanline as u16)),
+            PREFETCH_START..PREFETCH_END => {
+                Some(((self.fetch - PREFETCH_START) / 4, self.scanline as u16 + 1))
+            }
+            _ => None,
+        }
+    }
+
+    fn sprite_fetch(&self) -> bool {
+        self.in_frame && (SPRITE_FETCH_START..PREFETCH_START).contains(&self.fetch)
+    }
+
+    // 縦分割の領域内のタイルなら(列, 分割画面上のY座標)
+    fn split_tile(&self) -> Option<(u16, u16)> {
+        if self.split_control & 0x80 == 0 || self.exram_mode > 1 {
+            return None;
+        }
+        let (column, line) = self.background_tile()?;
+        let count = (self.split_control & 0x1F) as u16;
+        let inside = if self.split_control & 0x40 != 0 {
+            column >= count
+        } else {
+            column < count
+        };
+        inside.then_some((column % 32, (self.split_scroll as u16 + line) % 240))
+    }
+
+    // 3回続けて同じネームテーブルを読んだらラインの始まり
+    fn detect_scanline(&mut self, addr: u16) {
+        if (0x2000..=0x2FFF).contains(&addr) && addr == self.last_ppu_addr {
+            self.same_nametable_reads += 1;
+            if self.same_nametable_reads == 2 {
+                self.start_scanline();
+                return;
+            }
+        } else {
+            self.same_nametable_reads = 0;
+        }
+        self.fetch = self.fetch.saturating_add(1);
+    }
+
+    fn start_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+        self.fetch = 0;
+    }
+
+    // パターンテーブルのアドレスからCHRメモリの位置を求める
+    fn chr_bank_for(&self, addr: u16) -> (usize, usize, u16) {
+        if self.background_tile().is_some() {
+            if let Some((_, y)) = self.split_tile() {
+                // 分割画面は専用の4KBバンクから、分割側のY座標で読む
+                let addr = (addr & 0x0FF8) | (y & 7);
+                return (self.split_bank as usize, 0x1000, addr);
+            }
+            if self.exram_mode == 1 {
+                let bank = (self.chr_upper as usize) << 6 | (self.tile_attribute & 0x3F) as usize;
+                return (bank, 0x1000, addr);
+            }
+        }
+        let background = if self.sprite_8x16 && self.in_frame {
+            !self.sprite_fetch()
+        } else {
+            self.last_write_background
+        };
+        let (size, index) = match self.chr_mode {
+            0 => (0x2000, 7),
+            1 => (0x1000, (addr >> 12) * 4 + 3),
+            2 => (0x0800, (addr >> 11) * 2 + 1),
+            _ => (0x0400, addr >> 10),
+        };
+        let bank = if background {
+            self.background_chr_banks[(index & 3) as usize]
+        } else {
+            self.sprite_chr_banks[index as usize]
+        };
+        (bank as usize, size, addr)
+    }
+
+    fn status(&self) -> u8 {
+        (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let value = self.cpu_peek(addr);
+        match addr {
+            0x5204 => self.irq_pending = false,
+            // NMIベクタの読み込みは垂直帰線期間に入った印
+            0xFFFA | 0xFFFB => self.in_frame = false,
+            _ => {}
+        }
+        value
+    }
+
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x5015 => self.audio.status(),
+            0x5204 => self.status(),
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+            0x6000..=0xFFFF => match self.prg_for(addr) {
+                Some(Prg::Rom(offset)) => self.prg_rom[offset],
+                Some(Prg::Ram(offset)) => self.prg_ram[offset],
+                None => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5015 => self.audio.write(addr, data),
+            0x5100 => self.prg_mode = data & 0x03,
+            0x5101 => self.chr_mode = data & 0x03,
+            0x5102 | 0x5103 => self.prg_ram_protect[(addr - 0x5102) as usize] = data & 0x03,
+            0x5104 => self.exram_mode = data & 0x03,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0x03,
+            0x5113 => self.prg_ram_bank = data,
+            0x5114..=0x5117 => self.prg_banks[(addr - 0x5114) as usize] = data,
+            0x5120..=0x5127 => {
+                self.sprite_chr_banks[(addr - 0x5120) as usize] =
+                    (self.chr_upper as u16) << 8 | data as u16;
+                self.last_write_background = false;
+            }
+            0x5128..=0x512B => {
+                self.background_chr_banks[(addr - 0x5128) as usize] =
+                    (self.chr_upper as u16) << 8 | data as u16;
+                self.last_write_background = true;
+            }
+            0x5130 => self.chr_upper = data & 0x03,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5C00..=0x5FFF => {
+                let offset = (addr - 0x5C00) as usize;
+                match self.exram_mode {
+                    // 描画中でなければ0が書き込まれる
+                    0 | 1 => self.exram[offset] = if self.in_frame { data } else { 0 },
+                    2 => self.exram[offset] = data,
+                    _ => {}
+                }
+            }
+            0x6000..=0xFFFF if self.prg_ram_writable() => {
+                if let Some(Prg::Ram(offset)) = self.prg_for(addr) {
+                    self.prg_ram[offset] = data;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        let (bank, size, addr) = self.chr_bank_for(addr);
+        self.chr.read(bank, size, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let (bank, size, addr) = self.chr_bank_for(addr);
+        self.chr.write(bank, size, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        let page = |i: u8| (self.nametable_mapping >> (i * 2)) & 1;
+        Mirroring::Custom([page(0), page(1), page(2), page(3)])
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn clock(&mut self) {
+        if self.idle > 0 {
+            self.idle -= 1;
+            if self.idle == 0 {
+                self.in_frame = false;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn ppu_bus(&mut self, addr: u16) {
+        self.detect_scanline(addr);
+        self.last_ppu_addr = addr;
+        self.idle = IDLE_CYCLES;
+        // タイルのネームテーブル取得で拡張属性を覚えておく
+        if self.fetch.is_multiple_of(4)
+            && self.background_tile().is_some()
+            && (0x2000..=0x2FFF).contains(&addr)
+        {
+            self.tile_attribute = self.exram[(addr & 0x3FF) as usize];
+        }
+    }
+
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        let offset = addr & 0x3FF;
+        let attribute = offset >= 0x3C0;
+        if let Some((column, y)) = self.split_tile() {
+            return Some(if attribute {
+                let value = self.exram[(0x3C0 + (y / 32) * 8 + column / 4) as usize];
+                let shift = ((y / 16) & 1) * 4 + ((column / 2) & 1) * 2;
+                ((value >> shift) & 0x03) * 0x55
+            } else {
+                self.exram[((y / 8) * 32 + column) as usize]
+            });
+        }
+        if attribute && self.exram_mode == 1 && self.background_tile().is_some() {
+            return Some((self.tile_attribute >> 6) * 0x55);
+        }
+        match (self.nametable_mapping >> (((addr >> 10) & 3) * 2)) & 0x03 {
+            0 | 1 => None,
+            2 if self.exram_mode <= 1 => Some(self.exram[offset as usize]),
+            2 => Some(0),
+            _ if attribute => Some(self.fill_attribute * 0x55),
+            _ => Some(self.fill_tile),
+        }
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8) -> bool {
+        match (self.nametable_mapping >> (((addr >> 10) & 3) * 2)) & 0x03 {
+            0 | 1 => false,
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[(addr & 0x3FF) as usize] = data;
+                }
+                true
+            }
+            _ => true,
+        }
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x2000 => self.sprite_8x16 = data & 0x20 != 0,
+            // 描画を止めると次のフレームまでラインを数えない
+            0x2001 if data & 0x18 == 0 => self.in_frame = false,
+            _ => {}
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn create_mmc5() -> Mmc5 {
+        let mut raw = create_rom(0x50, 0, 8, 2);
+        // PRGは8KBごと、CHRは1KBごとに番号を振る
+        for bank in 0..16 {
+            raw[16 + bank * 0x2000] = bank as u8;
+        }
+        let chr = 16 + 8 * 0x4000;
+        for bank in 0..16 {
+            raw[chr + bank * 0x0400] = bank as u8;
+        }
+        Mmc5::new(&Rom::new(&raw).unwrap())
+    }
+
+    // ライン末尾の取得 次のラインの先頭2タイルと$2002のダミー読み込み2回
+    fn line_end(mapper: &mut Mmc5) {
+        for tile in 0..2 {
+            mapper.ppu_bus(0x2000 + tile);
+            mapper.ppu_bus(0x23C0);
+            mapper.ppu_bus(0x0000);
+            mapper.ppu_bus(0x0008);
+        }
+        mapper.ppu_bus(0x2002);
+        mapper.ppu_bus(0x2002);
+    }
+
+    // 1ライン分のPPUの読み込み 最初の$2002でラインの始まりを検出する
+    fn scanline(mapper: &mut Mmc5) {
+        for tile in 2..34 {
+            mapper.ppu_bus(0x2000 + tile);
+            mapper.ppu_bus(0x23C0);
+            mapper.ppu_bus(0x0000);
+            mapper.ppu_bus(0x0008);
+        }
+        for _ in 0..8 {
+            mapper.ppu_bus(0x2000);
+            mapper.ppu_bus(0x2000);
+            mapper.ppu_bus(0x1000);
+            mapper.ppu_bus(0x1008);
+        }
+        line_end(mapper);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = create_mmc5();
+        // 起動時はモード3で$E000が最後のバンク
+        assert_eq!(mapper.cpu_peek(0xE000), 15);
+
+        mapper.cpu_write(0x5100, 0);
+        mapper.cpu_write(0x5117, 0x85);
+        assert_eq!(mapper.cpu_peek(0x8000), 4);
+        assert_eq!(mapper.cpu_peek(0xE000), 7);
+
+        mapper.cpu_write(0x5100, 2);
+        mapper.cpu_write(0x5115, 0x83);
+        mapper.cpu_write(0x5116, 0x89);
+        assert_eq!(mapper.cpu_peek(0x8000), 2);
+        assert_eq!(mapper.cpu_peek(0xA000), 3);
+        assert_eq!(mapper.cpu_peek(0xC000), 9);
+        assert_eq!(mapper.cpu_peek(0xE000), 5);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mapper = create_mmc5();
+        mapper.cpu_write(0x6000, 0x11);
+        assert_eq!(mapper.cpu_peek(0x6000), 0);
+
+        mapper.cpu_write(0x5102, 0x02);
+        mapper.cpu_write(0x5103, 0x01);
+        mapper.cpu_write(0x5113, 3);
+        mapper.cpu_write(0x6000, 0x11);
+        // bit7が0ならROMの領域にもRAMが出る
+        mapper.cpu_write(0x5114, 0x03);
+        assert_eq!(mapper.cpu_peek(0x8000), 0x11);
+        mapper.cpu_write(0x5113, 0);
+        assert_eq!(mapper.cpu_peek(0x6000), 0);
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let mut mapper = create_mmc5();
+        mapper.cpu_write(0x5205, 200);
+        mapper.cpu_write(0x5206, 100);
+        assert_eq!(mapper.cpu_peek(0x5205), (20000 & 0xFF) as u8);
+        assert_eq!(mapper.cpu_peek(0x5206), (20000 >> 8) as u8);
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut mapper = create_mmc5();
+        assert_eq!(mapper.audio_output(), 0.0);
+        // PCMは書き込みモードで$5011の値がそのまま出る 0は無視
+        mapper.cpu_write(0x5011, 0xFF);
+        mapper.cpu_write(0x5011, 0x00);
+        assert_eq!(mapper.audio_output(), 0.5);
+        mapper.cpu_write(0x5011, 0x01);
+
+        // 一定音量15・周期3 本体と違って周期が8未満でも鳴る
+        mapper.cpu_write(0x5015, 0x02);
+        mapper.cpu_write(0x5004, 0xBF);
+        mapper.cpu_write(0x5006, 0x03);
+        mapper.cpu_write(0x5007, 0x08);
+        assert_eq!(mapper.cpu_peek(0x5015), 0x02);
+        let mut levels = Vec::new();
+        for _ in 0..16 {
+            for _ in 0..8 {
+                mapper.clock();
+            }
+            levels.push(mapper.audio_output());
+        }
+        assert!(levels.iter().any(|&level| level > 0.2));
+        assert!(levels.iter().any(|&level| level < 0.01));
+
+        mapper.cpu_write(0x5015, 0x00);
+        assert_eq!(mapper.cpu_peek(0x5015), 0x00);
+    }
+
+    #[test]
+    fn test_exram_modes() {
+        let mut mapper = create_mmc5();
+        // モード0/1では描画中以外は0が書き込まれる
+        mapper.cpu_write(0x5C00, 0x12);
+        mapper.cpu_write(0x5104, 2);
+        assert_eq!(mapper.cpu_peek(0x5C00), 0);
+        mapper.cpu_write(0x5C00, 0x34);
+        assert_eq!(mapper.cpu_peek(0x5C00), 0x34);
+        mapper.cpu_write(0x5104, 3);
+        mapper.cpu_write(0x5C00, 0x56);
+        assert_eq!(mapper.cpu_peek(0x5C00), 0x34);
+    }
+
+    #[test]
+    fn test_nametable_mapping() {
+        let mut mapper = create_mmc5();
+        // $2000 CIRAM0, $2400 CIRAM1, $2800 ExRAM, $2C00 フィルモード
+        mapper.cpu_write(0x5105, 0b11_10_01_00);
+        mapper.cpu_write(0x5106, 0x42);
+        mapper.cpu_write(0x5107, 2);
+        assert_eq!(mapper.mirroring(), Mirroring::Custom([0, 1, 0, 1]));
+        assert_eq!(mapper.nametable_peek(0x2000), None);
+        assert!(mapper.nametable_write(0x2805, 0x99));
+        assert_eq!(mapper.nametable_peek(0x2805), Some(0x99));
+        assert_eq!(mapper.nametable_peek(0x2C00), Some(0x42));
+        assert_eq!(mapper.nametable_peek(0x2FC0), Some(0xAA));
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mapper = create_mmc5();
+        mapper.cpu_write(0x5203, 2);
+        mapper.cpu_write(0x5204, 0x80);
+        line_end(&mut mapper);
+        scanline(&mut mapper);
+        assert_eq!(mapper.cpu_peek(0x5204), 0x40);
+        scanline(&mut mapper);
+        assert!(!mapper.irq());
+        scanline(&mut mapper);
+        assert!(mapper.irq());
+        assert_eq!(mapper.cpu_read(0x5204), 0xC0);
+        assert!(!mapper.irq());
+
+        // PPUの読み込みが止まると描画外になる
+        for _ in 0..IDLE_CYCLES {
+            mapper.clock();
+        }
+        assert_eq!(mapper.cpu_peek(0x5204), 0);
+    }
+
+    #[test]
+    fn test_8x16_sprite_banks() {
+        let mut mapper = create_mmc5();
+        mapper.cpu_write(0x5101, 3);
+        mapper.cpu_write(0x5127, 5);
+        mapper.cpu_write(0x512B, 9);
+        // 8x8では最後に書いた背景用のバンクを使う
+        assert_eq!(mapper.ppu_peek(0x1C00), 9);
+
+        mapper.ppu_register_write(0x2000, 0x20);
+        line_end(&mut mapper);
+        mapper.ppu_bus(0x2002);
+        assert_eq!(mapper.ppu_peek(0x1C00), 9);
+        for _ in 0..SPRITE_FETCH_START {
+            mapper.ppu_bus(0x1000);
+        }
+        assert_eq!(mapper.ppu_peek(0x1C00), 5);
+    }
+
+    #[test]
+    fn test_extended_attributes() {
+        let mut mapper = create_mmc5();
+        mapper.cpu_write(0x5104, 1);
+        line_end(&mut mapper);
+        scanline(&mut mapper);
+        // 描画中に書き込む
+        mapper.cpu_write(0x5C02, 0xC1);
+        line_end(&mut mapper);
+        mapper.ppu_bus(0x2002);
+        mapper.ppu_bus(0x23C0);
+        assert_eq!(mapper.nametable_peek(0x23C0), Some(0xFF));
+        // 4KBバンク1の先頭は1KBバンク4
+        mapper.ppu_bus(0x0000);
+        assert_eq!(mapper.ppu_peek(0x0000), 4);
+    }
+
+    #[test]
+    fn test_vertical_split() {
+        let mut mapper = create_mmc5();
+        // 左側4タイルを分割画面にする
+        mapper.cpu_write(0x5200, 0x80 | 4);
+        mapper.cpu_write(0x5201, 8);
+        mapper.cpu_write(0x5202, 3);
+        line_end(&mut mapper);
+        mapper.ppu_bus(0x2002);
+        mapper.cpu_write(0x5C00 + 32 + 2, 0x77);
+        // 2列目 スクロール8なのでExRAMの2行目を読む
+        assert_eq!(mapper.nametable_peek(0x2002), Some(0x77));
+        mapper.ppu_bus(0x23C0);
+        mapper.ppu_bus(0x0000);
+        assert_eq!(mapper.ppu_peek(0x0000), 12);
+
+        // 分割の外は普通のネームテーブル
+        for _ in 0..12 {
+            mapper.ppu_bus(0x0000);
+        }
+        assert_eq!(mapper.nametable_peek(0x2005), None);
+    }
+}
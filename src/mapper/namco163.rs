@@ -0,0 +1,379 @@
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+// 1チャンネルを更新するのにかかるCPUサイクル数
+const CHANNEL_CYCLES: u8 = 15;
+
+// Namco 163拡張音源
+// 128byteの音源RAMに波形とチャンネルのレジスタを置き、最大8チャンネルを時分割で鳴らす
+// RAMは$F800に書いたアドレス（bit7で自動インクリメント）を通して$4800で読み書きする
+pub(super) struct Namco163Audio {
+    ram: [u8; 128],
+    addr: u8,
+    pub(super) disabled: bool,
+    divider: u8,
+    channel: u8,
+    outputs: [i16; 8],
+}
+
+impl Namco163Audio {
+    pub(super) fn new() -> Self {
+        Namco163Audio {
+            ram: [0; 128],
+            addr: 0,
+            disabled: false,
+            divider: 0,
+            channel: 7,
+            outputs: [0; 8],
+        }
+    }
+
+    pub(super) fn set_address(&mut self, data: u8) {
+        self.addr = data;
+    }
+
+    pub(super) fn peek_data(&self) -> u8 {
+        self.ram[(self.addr & 0x7F) as usize]
+    }
+
+    pub(super) fn read_data(&mut self) -> u8 {
+        let value = self.peek_data();
+        self.increment();
+        value
+    }
+
+    pub(super) fn write_data(&mut self, data: u8) {
+        self.ram[(self.addr & 0x7F) as usize] = data;
+        self.increment();
+    }
+
+    fn increment(&mut self) {
+        if self.addr & 0x80 != 0 {
+            self.addr = 0x80 | (self.addr.wrapping_add(1) & 0x7F);
+        }
+    }
+
+    // 鳴らすチャンネル数 $7Fのbit4-6
+    fn channel_count(&self) -> u8 {
+        ((self.ram[0x7F] >> 4) & 0x07) + 1
+    }
+
+    pub(super) fn clock(&mut self) {
+        if self.disabled {
+            return;
+        }
+        self.divider += 1;
+        if self.divider < CHANNEL_CYCLES {
+            return;
+        }
+        self.divider = 0;
+
+        let first = 8 - self.channel_count();
+        if self.channel < first {
+            self.channel = 7;
+        }
+        self.update_channel(self.channel as usize);
+        self.channel = if self.channel == first {
+            7
+        } else {
+            self.channel - 1
+        };
+    }
+
+    // 位相を進めて波形RAMから4bitのサンプルを読む
+    fn update_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let ram = &mut self.ram;
+        let frequency =
+            ram[base] as u32 | (ram[base + 2] as u32) << 8 | ((ram[base + 4] & 0x03) as u32) << 16;
+        let phase =
+            ram[base + 1] as u32 | (ram[base + 3] as u32) << 8 | (ram[base + 5] as u32) << 16;
+        let length = 256 - (ram[base + 4] & 0xFC) as u32;
+        let phase = (phase + frequency) % (length << 16);
+        ram[base + 1] = phase as u8;
+        ram[base + 3] = (phase >> 8) as u8;
+        ram[base + 5] = (phase >> 16) as u8;
+
+        let index = (ram[base + 6] as u32 + (phase >> 16)) & 0xFF;
+        let byte = ram[(index / 2) as usize];
+        let sample = if index & 1 == 0 {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+        let volume = (ram[base + 7] & 0x0F) as i16;
+        self.outputs[channel] = (sample as i16 - 8) * volume;
+    }
+
+    // 鳴らしているチャンネルの平均 無音は0.5
+    pub(super) fn output(&self) -> f32 {
+        let count = self.channel_count() as usize;
+        let sum: i16 = self.outputs[8 - count..].iter().sum();
+        (sum as f32 / count as f32 / 120.0 + 1.0) / 2.0
+    }
+}
+
+// マッパー19 (Namco 163)
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+
+    chr_banks: [u8; 8],
+    // $E0以上はCIRAM（bit0がページ）、それ以外はCHR-ROMの1KBバンク
+    nametable_banks: [u8; 4],
+    prg_banks: [u8; 3],
+    // $F800: PRG-RAMの書き込み保護 音源RAMのアドレスも兼ねる
+    write_protect: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: Namco163Audio,
+}
+
+impl Namco163 {
+    pub fn new(rom: &Rom) -> Self {
+        Namco163 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram: vec![0; prg_ram_size(rom)],
+            chr_banks: [0; 8],
+            nametable_banks: [0xE0, 0xE1, 0xE0, 0xE1],
+            prg_banks: [0; 3],
+            write_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: Namco163Audio::new(),
+        }
+    }
+
+    fn prg_ram_writable(&self, addr: u16) -> bool {
+        let window = (addr - 0x6000) >> 11;
+        !self.prg_ram.is_empty()
+            && self.write_protect & 0xF0 == 0x40
+            && self.write_protect & (1 << window) == 0
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.audio.read_data(),
+            _ => self.cpu_peek(addr),
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        match addr {
+            0x4800..=0x4FFF => self.audio.peek_data(),
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) >> 13) as usize] as usize;
+                self.prg_rom[bank_offset(len, bank, 0x2000, addr)]
+            }
+            0xE000..=0xFFFF => {
+                self.prg_rom[bank_offset(len, (len / 0x2000).saturating_sub(1), 0x2000, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.audio.write_data(data),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | data as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((data & 0x7F) as u16) << 8;
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF if self.prg_ram_writable(addr) => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) >> 11) as usize] = data,
+            0xC000..=0xDFFF => self.nametable_banks[((addr - 0xC000) >> 11) as usize] = data,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = data & 0x3F;
+                self.audio.disabled = data & 0x40 != 0;
+            }
+            0xE800..=0xEFFF => self.prg_banks[1] = data & 0x3F,
+            0xF000..=0xF7FF => self.prg_banks[2] = data & 0x3F,
+            0xF800..=0xFFFF => {
+                self.write_protect = data;
+                self.audio.set_address(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr
+            .read(self.chr_banks[(addr >> 10) as usize] as usize, 0x0400, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+        self.chr.write(bank, 0x0400, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        let page = |i: usize| self.nametable_banks[i] & 1;
+        Mirroring::Custom([page(0), page(1), page(2), page(3)])
+    }
+
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        let bank = self.nametable_banks[((addr >> 10) & 3) as usize];
+        (bank < 0xE0).then(|| self.chr.read(bank as usize, 0x0400, addr))
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8) -> bool {
+        let bank = self.nametable_banks[((addr >> 10) & 3) as usize];
+        if bank >= 0xE0 {
+            return false;
+        }
+        self.chr.write(bank as usize, 0x0400, addr, data);
+        true
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn create_namco163() -> Namco163 {
+        let mut raw = create_rom(0x30, 0x10, 8, 16);
+        for bank in 0..16 {
+            raw[16 + bank * 0x2000] = bank as u8;
+        }
+        let chr = 16 + 8 * 0x4000;
+        for bank in 0..128 {
+            raw[chr + bank * 0x0400] = bank as u8;
+        }
+        Namco163::new(&Rom::new(&raw).unwrap())
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = create_namco163();
+        mapper.cpu_write(0xE000, 3);
+        mapper.cpu_write(0xF000, 9);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+        assert_eq!(mapper.cpu_peek(0xC000), 9);
+        assert_eq!(mapper.cpu_peek(0xE000), 15);
+
+        mapper.cpu_write(0x9800, 0x42);
+        assert_eq!(mapper.ppu_peek(0x0C00), 0x42);
+    }
+
+    #[test]
+    fn test_nametables() {
+        let mut mapper = create_namco163();
+        mapper.cpu_write(0xC000, 0xE1);
+        mapper.cpu_write(0xC800, 0xE0);
+        mapper.cpu_write(0xD000, 0x05);
+        assert_eq!(mapper.nametable_peek(0x2000), None);
+        assert_eq!(mapper.nametable_peek(0x2800), Some(0x05));
+        assert_eq!(mapper.mirroring(), Mirroring::Custom([1, 0, 1, 1]));
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut mapper = create_namco163();
+        mapper.cpu_write(0x6000, 0x11);
+        assert_eq!(mapper.cpu_peek(0x6000), 0);
+        // $6000-$67FFだけ保護する
+        mapper.cpu_write(0xF800, 0x41);
+        mapper.cpu_write(0x6000, 0x11);
+        mapper.cpu_write(0x6800, 0x22);
+        assert_eq!(mapper.cpu_peek(0x6000), 0);
+        assert_eq!(mapper.cpu_peek(0x6800), 0x22);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = create_namco163();
+        mapper.cpu_write(0x5000, 0xFD);
+        mapper.cpu_write(0x5800, 0xFF);
+        mapper.clock();
+        assert!(!mapper.irq());
+        mapper.clock();
+        assert!(mapper.irq());
+        assert_eq!(mapper.cpu_peek(0x5800), 0xFF);
+        mapper.cpu_write(0x5800, 0xFF);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_sound_ram_port() {
+        let mut mapper = create_namco163();
+        mapper.cpu_write(0xF800, 0x80 | 0x10);
+        mapper.cpu_write(0x4800, 0x12);
+        mapper.cpu_write(0x4800, 0x34);
+        mapper.cpu_write(0xF800, 0x80 | 0x10);
+        assert_eq!(mapper.cpu_read(0x4800), 0x12);
+        assert_eq!(mapper.cpu_read(0x4800), 0x34);
+    }
+
+    #[test]
+    fn test_wavetable_channel() {
+        let mut mapper = create_namco163();
+        // 波形: 0番地から4サンプル F,0,F,0
+        mapper.audio.ram[0] = 0x0F;
+        mapper.audio.ram[1] = 0x0F;
+        // チャンネル8 (1チャンネルのみ) 周波数$10000 = 1サンプルずつ進む、長さ4、音量15
+        mapper.audio.ram[0x7C] = 0xFC | 0x01;
+        mapper.audio.ram[0x7F] = 0x0F;
+        let mut outputs = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..CHANNEL_CYCLES {
+                mapper.clock();
+            }
+            outputs.push(mapper.audio.outputs[7]);
+        }
+        assert_eq!(outputs, [-120, 105, -120, 105]);
+    }
+}
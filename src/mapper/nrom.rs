@@ -0,0 +1,104 @@
+use super::{ChrMemory, Mapper, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー0 (NROM)
+// NROM-128は16KBのPRG-ROMを$8000と$C000にミラーし、NROM-256は32KBをそのまま置く
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    // Family BasicなどはPRG-RAMを持つ
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: &Rom) -> Self {
+        Nrom {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram: vec![0; prg_ram_size(rom)],
+            mirroring: rom.screen_mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            // 未接続（オープンバス）
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if (0x6000..=0x7FFF).contains(&addr) && !self.prg_ram.is_empty() {
+            let len = self.prg_ram.len();
+            self.prg_ram[(addr - 0x6000) as usize % len] = data;
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(0, 0x2000, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_nrom_128_mirrors_prg() {
+        let rom = Rom::new(&create_rom(0, 0, 1, 1)).unwrap();
+        let mapper = Nrom::new(&rom);
+        assert_eq!(mapper.cpu_peek(0x8000), mapper.cpu_peek(0xC000));
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_nrom_256() {
+        let rom = Rom::new(&create_rom(0b1, 0, 2, 1)).unwrap();
+        let mapper = Nrom::new(&rom);
+        assert_eq!(mapper.cpu_peek(0x8000), 0x00);
+        assert_eq!(mapper.cpu_peek(0xC000), 0x01);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_chr_rom_and_ram() {
+        let rom = Rom::new(&create_rom(0, 0, 1, 1)).unwrap();
+        let mut mapper = Nrom::new(&rom);
+        mapper.ppu_write(0x0010, 0x55);
+        assert_eq!(mapper.ppu_read(0x0010), 0xCC);
+
+        let rom = Rom::new(&create_rom(0, 0, 1, 0)).unwrap();
+        let mut mapper = Nrom::new(&rom);
+        mapper.ppu_write(0x0010, 0x55);
+        assert_eq!(mapper.ppu_read(0x0010), 0x55);
+    }
+}
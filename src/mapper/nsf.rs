@@ -0,0 +1,343 @@
+use super::fds_audio::FdsAudio;
+use super::fme7::Sunsoft5b;
+use super::mmc5::Mmc5Audio;
+use super::namco163::Namco163Audio;
+use super::opll::Opll;
+use super::vrc6::Vrc6Audio;
+use super::vrc7::OPLL_CYCLES;
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+use crate::nsf::{
+    BANK_REGISTERS, BANK_SIZE, CHIP_FDS, CHIP_MMC5, CHIP_NAMCO163, CHIP_SUNSOFT5B, CHIP_VRC6,
+    CHIP_VRC7,
+};
+
+// NSFを鳴らすための仮想の基板
+// $5FF8-$5FFFで$8000-$FFFFの4KBずつを切り替える
+// ディスクシステムの曲は$6000-$DFFFがRAMで、バンクの切り替えはRAMへのコピーになる
+// 拡張音源はヘッダのフラグで載せる MMC5の曲はExRAMと乗算器も使える
+pub struct Nsf {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    // $5FF6-$5FFF
+    banks: [u8; 10],
+    fds: bool,
+    // 通常は$6000-$7FFF、ディスクシステムは$6000-$FFFF
+    prg_ram: Vec<u8>,
+    fds_audio: Option<FdsAudio>,
+    opll: Option<Opll>,
+    audio_divider: u8,
+    sunsoft: Option<Sunsoft5b>,
+    vrc6: Option<Vrc6Audio>,
+    namco163: Option<Namco163Audio>,
+    mmc5: Option<Mmc5Audio>,
+    // MMC5の$5C00-$5FF5と$5205/$5206
+    exram: Vec<u8>,
+    multiplicand: u8,
+    multiplier: u8,
+}
+
+impl Nsf {
+    pub fn new(rom: &Rom) -> Self {
+        let chips = rom.submapper;
+        let fds = chips & CHIP_FDS != 0;
+        let mut mapper = Nsf {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            banks: [0; 10],
+            fds,
+            prg_ram: vec![0; if fds { 0xA000 } else { prg_ram_size(rom) }],
+            fds_audio: fds.then(FdsAudio::new),
+            opll: (chips & CHIP_VRC7 != 0).then(Opll::new),
+            audio_divider: 0,
+            sunsoft: (chips & CHIP_SUNSOFT5B != 0).then(Sunsoft5b::new),
+            vrc6: (chips & CHIP_VRC6 != 0).then(Vrc6Audio::new),
+            namco163: (chips & CHIP_NAMCO163 != 0).then(Namco163Audio::new),
+            mmc5: (chips & CHIP_MMC5 != 0).then(Mmc5Audio::new),
+            exram: vec![0; if chips & CHIP_MMC5 != 0 { 0x400 } else { 0 }],
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+        };
+        // バンク切り替えを使わない曲の並び プレイヤーが$5FF6-$5FFFを書けば置き換わる
+        for slot in 0..10usize {
+            let bank = if fds { slot } else { slot.saturating_sub(2) };
+            mapper.switch_bank(slot, bank as u8);
+        }
+        mapper
+    }
+
+    // slotは$5FF6からの番号
+    fn switch_bank(&mut self, slot: usize, bank: u8) {
+        self.banks[slot] = bank;
+        if self.fds {
+            let start = slot * BANK_SIZE;
+            for i in 0..BANK_SIZE {
+                self.prg_ram[start + i] = self.prg_rom
+                    [bank_offset(self.prg_rom.len(), bank as usize, BANK_SIZE, i as u16)];
+            }
+        }
+    }
+}
+
+impl Mapper for Nsf {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match (addr, &mut self.namco163) {
+            (0x4800..=0x4FFF, Some(audio)) => audio.read_data(),
+            _ => self.cpu_peek(addr),
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let product = self.multiplicand as u16 * self.multiplier as u16;
+        match addr {
+            0x4040..=0x4092 => self.fds_audio.as_ref().map_or(0, |audio| audio.read(addr)),
+            0x4800..=0x4FFF => self.namco163.as_ref().map_or(0, Namco163Audio::peek_data),
+            0x5015 => self.mmc5.as_ref().map_or(0, Mmc5Audio::status),
+            0x5205 if self.mmc5.is_some() => product as u8,
+            0x5206 if self.mmc5.is_some() => (product >> 8) as u8,
+            0x5C00..=0x5FF5 if !self.exram.is_empty() => self.exram[(addr - 0x5C00) as usize],
+            0x6000..=0xFFFF if self.fds => self.prg_ram[(addr - 0x6000) as usize],
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => {
+                let bank = self.banks[2 + ((addr - 0x8000) as usize >> 12)] as usize;
+                self.prg_rom[bank_offset(self.prg_rom.len(), bank, BANK_SIZE, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x4092 => {
+                if let Some(audio) = &mut self.fds_audio {
+                    audio.write(addr, data);
+                }
+            }
+            // $5FF6/$5FF7はディスクシステムの時だけある
+            0x5FF6..=0x5FF7 if !self.fds => {}
+            0x5FF6..=0x5FFF => self.switch_bank((addr - BANK_REGISTERS) as usize, data),
+            0x5C00..=0x5FF5 if !self.exram.is_empty() => {
+                self.exram[(addr - 0x5C00) as usize] = data;
+            }
+            0x6000..=0xDFFF if self.fds => self.prg_ram[(addr - 0x6000) as usize] = data,
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            _ => {}
+        }
+        // 拡張音源のレジスタ
+        if let Some(mmc5) = &mut self.mmc5 {
+            match addr {
+                0x5000..=0x5015 => mmc5.write(addr, data),
+                0x5205 => self.multiplicand = data,
+                0x5206 => self.multiplier = data,
+                _ => {}
+            }
+        }
+        if let (0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002, Some(vrc6)) =
+            (addr, &mut self.vrc6)
+        {
+            vrc6.write(addr, data);
+        }
+        if let Some(namco163) = &mut self.namco163 {
+            match addr {
+                0x4800..=0x4FFF => namco163.write_data(data),
+                0xF800..=0xFFFF => namco163.set_address(data),
+                _ => {}
+            }
+        }
+        match addr {
+            0x9010 => {
+                if let Some(opll) = &mut self.opll {
+                    opll.write_address(data);
+                }
+            }
+            0x9030 => {
+                if let Some(opll) = &mut self.opll {
+                    opll.write_data(data);
+                }
+            }
+            0xC000 => {
+                if let Some(sunsoft) = &mut self.sunsoft {
+                    sunsoft.register = data & 0x0F;
+                }
+            }
+            0xE000 => {
+                if let Some(sunsoft) = &mut self.sunsoft {
+                    sunsoft.write(data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(0, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn clock(&mut self) {
+        if let Some(audio) = &mut self.fds_audio {
+            audio.clock();
+        }
+        if let Some(opll) = &mut self.opll {
+            self.audio_divider += 1;
+            if self.audio_divider == OPLL_CYCLES {
+                self.audio_divider = 0;
+                opll.clock();
+            }
+        }
+        if let Some(sunsoft) = &mut self.sunsoft {
+            sunsoft.clock();
+        }
+        if let Some(vrc6) = &mut self.vrc6 {
+            vrc6.clock();
+        }
+        if let Some(namco163) = &mut self.namco163 {
+            namco163.clock();
+        }
+        if let Some(mmc5) = &mut self.mmc5 {
+            mmc5.clock();
+        }
+    }
+
+    // 載っている音源の平均 OPLLは無音が0.5
+    fn audio_output(&self) -> f32 {
+        let outputs: Vec<f32> = [
+            self.fds_audio.as_ref().map(FdsAudio::output),
+            self.opll.as_ref().map(|opll| (opll.output() + 1.0) / 2.0),
+            self.sunsoft.as_ref().map(Sunsoft5b::output),
+            self.vrc6.as_ref().map(Vrc6Audio::output),
+            self.namco163.as_ref().map(Namco163Audio::output),
+            self.mmc5.as_ref().map(Mmc5Audio::output),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if outputs.is_empty() {
+            0.0
+        } else {
+            outputs.iter().sum::<f32>() / outputs.len() as f32
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nsf::test::create_nsf;
+
+    fn create_mapper(load_addr: u16, banks: [u8; 8], chips: u8, data: &[u8]) -> Nsf {
+        let nsf = crate::nsf::Nsf::parse(&create_nsf(load_addr, banks, chips, data)).unwrap();
+        let mut mapper = Nsf::new(&nsf.rom());
+        for (addr, bank) in nsf.bank_writes() {
+            mapper.cpu_write(addr, bank);
+        }
+        mapper
+    }
+
+    #[test]
+    fn test_fixed_layout() {
+        let mut mapper = create_mapper(0x8100, [0; 8], 0, &[1, 2]);
+        assert_eq!(mapper.cpu_peek(0x8100), 1);
+        assert_eq!(mapper.cpu_peek(0x8101), 2);
+        // $6000-$7FFFはRAM
+        mapper.cpu_write(0x6000, 0x55);
+        assert_eq!(mapper.cpu_peek(0x6000), 0x55);
+    }
+
+    #[test]
+    fn test_bankswitch() {
+        let mut data = Vec::new();
+        for bank in 0..4 {
+            data.extend(vec![bank; BANK_SIZE]);
+        }
+        let mut mapper = create_mapper(0x8000, [0, 1, 2, 3, 0, 1, 2, 3], 0, &data);
+        assert_eq!(mapper.cpu_peek(0x9000), 1);
+        assert_eq!(mapper.cpu_peek(0xF000), 3);
+        mapper.cpu_write(0x5FF8, 2);
+        assert_eq!(mapper.cpu_peek(0x8FFF), 2);
+        // ディスクシステムでなければ$5FF6は無い
+        mapper.cpu_write(0x5FF6, 3);
+        assert_eq!(mapper.banks[0], 0);
+    }
+
+    #[test]
+    fn test_fds() {
+        let mut data = Vec::new();
+        for bank in 0..2 {
+            data.extend(vec![bank + 1; BANK_SIZE]);
+        }
+        let mut mapper = create_mapper(0x6000, [0; 8], CHIP_FDS, &data);
+        assert_eq!(mapper.cpu_peek(0x6000), 1);
+        assert_eq!(mapper.cpu_peek(0x7000), 2);
+        // 書き換えられる バンクを切り替えるとROMからコピーし直す
+        mapper.cpu_write(0x7000, 0x55);
+        assert_eq!(mapper.cpu_peek(0x7000), 0x55);
+        mapper.cpu_write(0x5FF7, 1);
+        assert_eq!(mapper.cpu_peek(0x7000), 2);
+        mapper.cpu_write(0x5FF8, 0);
+        assert_eq!(mapper.cpu_peek(0x8000), 1);
+
+        // 波形メモリ
+        mapper.cpu_write(0x4089, 0x80);
+        mapper.cpu_write(0x4040, 0x3F);
+        assert_eq!(mapper.cpu_peek(0x4040), 0x7F);
+    }
+
+    #[test]
+    fn test_audio() {
+        let mapper = create_mapper(0x8000, [0; 8], 0, &[0]);
+        assert_eq!(mapper.audio_output(), 0.0);
+        let mut mapper = create_mapper(0x8000, [0; 8], CHIP_VRC7 | CHIP_SUNSOFT5B, &[0]);
+        // OPLLの無音0.5と5Bの0の平均
+        assert_eq!(mapper.audio_output(), 0.25);
+        mapper.cpu_write(0xC000, 0x08);
+        mapper.cpu_write(0xE000, 0x0F);
+        assert_eq!(mapper.sunsoft.as_ref().unwrap().register, 0x08);
+    }
+
+    #[test]
+    fn test_more_chips() {
+        let chips = CHIP_VRC6 | CHIP_MMC5 | CHIP_NAMCO163;
+        let mut mapper = create_mapper(0x8000, [0; 8], chips, &[0]);
+
+        // VRC6の矩形波 一定音量15
+        mapper.cpu_write(0x9000, 0x8F);
+        mapper.cpu_write(0x9002, 0x80);
+        assert!(mapper.vrc6.as_ref().unwrap().output() > 0.0);
+
+        // Namco 163の音源RAM
+        mapper.cpu_write(0xF800, 0x80 | 0x20);
+        mapper.cpu_write(0x4800, 0x12);
+        mapper.cpu_write(0xF800, 0x80 | 0x20);
+        assert_eq!(mapper.cpu_read(0x4800), 0x12);
+
+        // MMC5の乗算器・ExRAM・音源の状態
+        mapper.cpu_write(0x5205, 200);
+        mapper.cpu_write(0x5206, 100);
+        assert_eq!(mapper.cpu_peek(0x5206), (20000 >> 8) as u8);
+        mapper.cpu_write(0x5C00, 0x34);
+        assert_eq!(mapper.cpu_peek(0x5C00), 0x34);
+        mapper.cpu_write(0x5015, 0x01);
+        mapper.cpu_write(0x5003, 0x08);
+        assert_eq!(mapper.cpu_peek(0x5015), 0x01);
+
+        // 載っていない音源のレジスタは無視する
+        let mut mapper = create_mapper(0x8000, [0; 8], 0, &[0]);
+        mapper.cpu_write(0x5C00, 0x34);
+        assert_eq!(mapper.cpu_peek(0x5C00), 0);
+        assert_eq!(mapper.cpu_peek(0x5206), 0);
+    }
+}
@@ -0,0 +1,453 @@
+use std::f32::consts::PI;
+
+// VRC7の音源 (YM2413の派生、6チャンネル、リズム音なし)
+// 3.58MHz/72 = 約49.7kHzで1サンプルずつ計算する
+
+pub const SAMPLE_RATE: f32 = 49716.0;
+
+// 内蔵音色1-15 (0番は$00-$07で定義するユーザー音色)
+// 2019年のダイ解析で得られたVRC7の音色表
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+// MULTに対応する周波数の倍率の2倍
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+// ブロック7でのF-Number上位4bitごとのキースケールの減衰量 (dB)
+const KSL_TABLE: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+    42.0,
+];
+
+// エンベロープの減衰量の最大 (0.375dB単位)
+const MAX_ATTENUATION: u32 = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+#[derive(Clone, Copy)]
+struct Operator {
+    // 19bitの位相
+    phase: u32,
+    state: EnvelopeState,
+    attenuation: u32,
+    counter: u32,
+}
+
+impl Operator {
+    fn new() -> Self {
+        Operator {
+            phase: 0,
+            state: EnvelopeState::Off,
+            attenuation: MAX_ATTENUATION,
+            counter: 0,
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0;
+        self.counter = 0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.state != EnvelopeState::Off {
+            self.state = EnvelopeState::Release;
+        }
+    }
+
+    // レートの周期が来たらtrue
+    fn tick(&mut self, rate: u32) -> bool {
+        let Some(period) = rate_period(rate) else {
+            return false;
+        };
+        self.counter += 1;
+        if self.counter < period {
+            return false;
+        }
+        self.counter = 0;
+        true
+    }
+
+    fn decay(&mut self, rate: u32) {
+        if self.tick(rate) {
+            self.attenuation += 1;
+            if self.attenuation >= MAX_ATTENUATION {
+                self.attenuation = MAX_ATTENUATION;
+                self.state = EnvelopeState::Off;
+            }
+        }
+    }
+
+    // 位相のずれ(周期単位)と追加の減衰(dB)から出力を求める
+    fn output(&self, offset: f32, attenuation: f32, rectified: bool) -> f32 {
+        if self.state == EnvelopeState::Off {
+            return 0.0;
+        }
+        let phase = self.phase as f32 / (1 << 19) as f32 + offset;
+        let mut value = (phase * 2.0 * PI).sin();
+        if rectified && value < 0.0 {
+            value = 0.0;
+        }
+        let db = self.attenuation as f32 * 0.375 + attenuation;
+        value * 10f32.powf(-db / 20.0)
+    }
+}
+
+// 実効レート(0-63)で減衰量を1段動かすまでのサンプル数 0は動かない
+fn rate_period(rate: u32) -> Option<u32> {
+    if rate < 4 {
+        return None;
+    }
+    let shift = 13u32.saturating_sub(rate / 4);
+    Some(((1 << shift) * 4 / (4 + rate % 4)).max(1))
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    fnum: u16,
+    block: u8,
+    key_on: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    // [モジュレータ, キャリア]
+    operators: [Operator; 2],
+    // フィードバック用のモジュレータの直前2回の出力
+    feedback: [f32; 2],
+}
+
+impl Channel {
+    fn new() -> Self {
+        Channel {
+            fnum: 0,
+            block: 0,
+            key_on: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            operators: [Operator::new(); 2],
+            feedback: [0.0; 2],
+        }
+    }
+
+    // F-Numberとブロックから決まるキースケールの減衰量 (dB、6dB/oct)
+    fn key_scale(&self) -> f32 {
+        let db = KSL_TABLE[(self.fnum >> 5) as usize] - 6.0 * (7 - self.block) as f32;
+        db.max(0.0)
+    }
+
+    // 1サンプル進めてキャリアの出力を返す amはトレモロの減衰(dB)、pmはビブラートの周波数比
+    fn sample(&mut self, patch: &[u8; 8], am: f32, pm: f32) -> f32 {
+        let key_code = ((self.block as u32) << 1) | (self.fnum >> 8) as u32;
+        let mut attenuations = [0.0; 2];
+        for (i, attenuation) in attenuations.iter_mut().enumerate() {
+            let flags = patch[i];
+            let key_scale_rate = if flags & 0x10 != 0 {
+                key_code
+            } else {
+                key_code >> 2
+            };
+            let mut increment =
+                (((self.fnum as u32) << self.block) * MULTIPLIERS[(flags & 0x0F) as usize]) >> 1;
+            if flags & 0x40 != 0 {
+                increment = (increment as f32 * (1.0 + pm)) as u32;
+            }
+            let op = &mut self.operators[i];
+            op.phase = (op.phase + increment) & 0x7FFFF;
+            self.update_envelope(i, patch, key_scale_rate);
+
+            let ksl = [0.0, 0.25, 0.5, 1.0][(patch[2 + i] >> 6) as usize];
+            *attenuation = self.key_scale() * ksl;
+            if flags & 0x80 != 0 {
+                *attenuation += am;
+            }
+        }
+        attenuations[0] += (patch[2] & 0x3F) as f32 * 0.75;
+        attenuations[1] += self.volume as f32 * 3.0;
+
+        let feedback = patch[3] & 0x07;
+        let offset = if feedback == 0 {
+            0.0
+        } else {
+            (self.feedback[0] + self.feedback[1]) / 2.0 * 2f32.powi(feedback as i32 - 6)
+        };
+        let modulator = self.operators[0].output(offset, attenuations[0], patch[3] & 0x08 != 0);
+        self.feedback = [self.feedback[1], modulator];
+        self.operators[1].output(modulator * 2.0, attenuations[1], patch[3] & 0x10 != 0)
+    }
+
+    fn update_envelope(&mut self, i: usize, patch: &[u8; 8], key_scale_rate: u32) {
+        let sustained = patch[i] & 0x20 != 0;
+        let attack = (patch[4 + i] >> 4) as u32;
+        let decay = (patch[4 + i] & 0x0F) as u32;
+        let sustain_level = (patch[6 + i] >> 4) as u32 * 8;
+        let release = (patch[6 + i] & 0x0F) as u32;
+        let rate = |value: u32| {
+            if value == 0 {
+                0
+            } else {
+                value * 4 + key_scale_rate
+            }
+        };
+
+        let op = &mut self.operators[i];
+        match op.state {
+            EnvelopeState::Attack => {
+                // 減衰が0のまま鍵盤を押し直した時は、引かずにディケイへ進む
+                let rate = rate(attack);
+                if rate >= 60 {
+                    op.attenuation = 0;
+                } else if op.attenuation > 0 && op.tick(rate) {
+                    op.attenuation -= (op.attenuation >> 2) + 1;
+                }
+                if op.attenuation == 0 {
+                    op.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                op.decay(rate(decay));
+                if op.state == EnvelopeState::Decay && op.attenuation >= sustain_level {
+                    op.state = EnvelopeState::Sustain;
+                }
+            }
+            // 持続音は鍵盤を離すまでそのまま、減衰音はリリースレートで減っていく
+            EnvelopeState::Sustain if !sustained => op.decay(rate(release)),
+            EnvelopeState::Sustain => {}
+            EnvelopeState::Release => {
+                let release = if self.sustain {
+                    5
+                } else if sustained {
+                    release
+                } else {
+                    7
+                };
+                op.decay(rate(release));
+            }
+            EnvelopeState::Off => {}
+        }
+    }
+}
+
+pub struct Opll {
+    address: u8,
+    custom: [u8; 8],
+    channels: [Channel; 6],
+    // トレモロ(3.7Hz)とビブラート(6.4Hz)の位相 (周期単位)
+    am_phase: f32,
+    pm_phase: f32,
+    output: f32,
+}
+
+impl Default for Opll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Opll {
+    pub fn new() -> Self {
+        Opll {
+            address: 0,
+            custom: [0; 8],
+            channels: [Channel::new(); 6],
+            am_phase: 0.0,
+            pm_phase: 0.0,
+            output: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Opll::new();
+    }
+
+    pub fn write_address(&mut self, address: u8) {
+        self.address = address;
+    }
+
+    pub fn write_data(&mut self, data: u8) {
+        let register = self.address;
+        let index = (register & 0x0F) as usize;
+        match register {
+            0x00..=0x07 => self.custom[index] = data,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[index];
+                channel.fnum = (channel.fnum & 0x100) | data as u16;
+            }
+            0x20..=0x25 => {
+                let channel = &mut self.channels[index];
+                channel.fnum = (channel.fnum & 0xFF) | ((data & 0x01) as u16) << 8;
+                channel.block = (data >> 1) & 0x07;
+                channel.sustain = data & 0x20 != 0;
+                let key_on = data & 0x10 != 0;
+                if key_on && !channel.key_on {
+                    channel.operators.iter_mut().for_each(Operator::key_on);
+                } else if !key_on && channel.key_on {
+                    channel.operators.iter_mut().for_each(Operator::key_off);
+                }
+                channel.key_on = key_on;
+            }
+            0x30..=0x35 => {
+                let channel = &mut self.channels[index];
+                channel.instrument = data >> 4;
+                channel.volume = data & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    // 1サンプル分進める
+    pub fn clock(&mut self) {
+        self.am_phase = (self.am_phase + 3.7 / SAMPLE_RATE).fract();
+        self.pm_phase = (self.pm_phase + 6.4 / SAMPLE_RATE).fract();
+        // トレモロは最大4.8dB、ビブラートは約±7セント
+        let am = (1.0 - (self.am_phase * 2.0 * PI).cos()) / 2.0 * 4.8;
+        let pm = (self.pm_phase * 2.0 * PI).sin() * 0.004;
+
+        let mut sum = 0.0;
+        for channel in self.channels.iter_mut() {
+            let patch = match channel.instrument {
+                0 => self.custom,
+                n => PATCHES[n as usize - 1],
+            };
+            sum += channel.sample(&patch, am, pm);
+        }
+        self.output = sum / 6.0;
+    }
+
+    // -1.0から1.0
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(opll: &mut Opll, register: u8, data: u8) {
+        opll.write_address(register);
+        opll.write_data(data);
+    }
+
+    #[test]
+    fn test_silent_until_key_on() {
+        let mut opll = Opll::new();
+        write(&mut opll, 0x10, 0xAC);
+        write(&mut opll, 0x30, 0x30);
+        for _ in 0..100 {
+            opll.clock();
+            assert_eq!(opll.output(), 0.0);
+        }
+    }
+
+    // ほぼ正弦波になるユーザー音色 モジュレータは最小音量、キャリアは即座に立ち上がり持続する
+    fn sine_patch(opll: &mut Opll) {
+        let patch = [0x21, 0x21, 0x3F, 0x00, 0xF0, 0xF0, 0x0F, 0x0F];
+        for (i, data) in patch.iter().enumerate() {
+            write(opll, i as u8, *data);
+        }
+        write(opll, 0x30, 0x00);
+    }
+
+    #[test]
+    fn test_key_on_produces_tone() {
+        let mut opll = Opll::new();
+        sine_patch(&mut opll);
+        // ブロック4でF-Number 288 (約437Hz)
+        write(&mut opll, 0x10, 0x20);
+        write(&mut opll, 0x20, 0x10 | 4 << 1 | 1);
+        let mut peak: f32 = 0.0;
+        let mut crossings = 0;
+        let mut last = 0.0;
+        for _ in 0..SAMPLE_RATE as usize / 10 {
+            opll.clock();
+            let output = opll.output();
+            peak = peak.max(output.abs());
+            if last < 0.0 && output >= 0.0 {
+                crossings += 1;
+            }
+            last = output;
+        }
+        assert!(peak > 0.1);
+        // 0.1秒で約44周期
+        assert!((42..=45).contains(&crossings), "{}", crossings);
+    }
+
+    #[test]
+    fn test_key_off_releases() {
+        let mut opll = Opll::new();
+        sine_patch(&mut opll);
+        write(&mut opll, 0x10, 0x20);
+        write(&mut opll, 0x20, 0x10 | 4 << 1 | 1);
+        for _ in 0..1000 {
+            opll.clock();
+        }
+        write(&mut opll, 0x20, 4 << 1 | 1);
+        for _ in 0..1000 {
+            opll.clock();
+        }
+        assert!(
+            opll.channels[0]
+                .operators
+                .iter()
+                .all(|op| op.state == EnvelopeState::Off)
+        );
+        assert_eq!(opll.output(), 0.0);
+    }
+
+    // 減衰が0のまま押し直しても、アタックで引きすぎない
+    #[test]
+    fn test_key_on_again_at_full_volume() {
+        let mut opll = Opll::new();
+        // アタックレート14 (実効レート56-59)、リリースレート0で鍵盤を離しても減衰しない
+        let patch = [0x21, 0x21, 0x3F, 0x00, 0xE0, 0xE0, 0x00, 0x00];
+        for (i, data) in patch.iter().enumerate() {
+            write(&mut opll, i as u8, *data);
+        }
+        write(&mut opll, 0x30, 0x00);
+        write(&mut opll, 0x10, 0x20);
+        write(&mut opll, 0x20, 0x10 | 4 << 1 | 1);
+        for _ in 0..5000 {
+            opll.clock();
+        }
+        write(&mut opll, 0x20, 4 << 1 | 1);
+        opll.clock();
+        write(&mut opll, 0x20, 0x10 | 4 << 1 | 1);
+        for _ in 0..100 {
+            opll.clock();
+        }
+        let carrier = opll.channels[0].operators[1];
+        assert_ne!(carrier.state, EnvelopeState::Attack);
+        assert_eq!(carrier.attenuation, 0);
+    }
+
+    #[test]
+    fn test_rate_period() {
+        assert_eq!(rate_period(0), None);
+        assert_eq!(rate_period(4), Some(4096));
+        assert_eq!(rate_period(60), Some(1));
+    }
+}
@@ -0,0 +1,152 @@
+use super::flash::Flash;
+use super::{ChrMemory, Mapper, bank_offset, bus_conflict};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー30 (UNROM 512)
+// UxROMと同じく$8000に16KBの切り替えバンク、$C000に最後のバンクを置き、32KBのCHR-RAMを持つ
+// 電池のbitが立っていればPRGはフラッシュで、$8000-$BFFFへの書き込みでゲームが自分を書き換える
+// 立っていなければ$8000-$FFFFがすべてレジスタでバス競合がある
+// ヘッダの4画面のbitは1画面の切り替えとして扱う
+pub struct Unrom512 {
+    flash: Flash,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    one_screen: bool,
+    flashable: bool,
+    // bit0-4 PRGバンク, bit5-6 CHRバンク, bit7 1画面の時のネームテーブル
+    register: u8,
+}
+
+impl Unrom512 {
+    pub fn new(rom: &Rom) -> Self {
+        Unrom512 {
+            flash: Flash::new(rom.prg_rom.clone()),
+            chr: ChrMemory::with_min_ram(rom, 0x8000),
+            mirroring: rom.screen_mirroring,
+            one_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            flashable: rom.battery,
+            register: 0,
+        }
+    }
+
+    fn prg_bank(&self) -> usize {
+        (self.register & 0x1F) as usize
+    }
+
+    fn chr_bank(&self) -> usize {
+        ((self.register >> 5) & 0x03) as usize
+    }
+}
+
+impl Mapper for Unrom512 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let len = self.flash.data().len();
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank(),
+            0xC000..=0xFFFF => (len / 0x4000).saturating_sub(1),
+            _ => return 0,
+        };
+        self.flash.read(bank_offset(len, bank, 0x4000, addr))
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            // フラッシュの番地は切り替えバンクの番号とA0-A13
+            0x8000..=0xBFFF if self.flashable => {
+                let offset = self.prg_bank() << 14 | (addr & 0x3FFF) as usize;
+                self.flash.write(offset, data);
+            }
+            0xC000..=0xFFFF if self.flashable => self.register = data,
+            0x8000..=0xFFFF => self.register = bus_conflict(true, self.cpu_peek(addr), data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank(), 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank(), 0x2000, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        self.flashable.then(|| self.flash.data())
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        if self.flashable {
+            Some(self.flash.data_mut())
+        } else {
+            None
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.one_screen, self.register & 0x80 != 0) {
+            (true, false) => Mirroring::SingleScreenLower,
+            (true, true) => Mirroring::SingleScreenUpper,
+            (false, _) => self.mirroring,
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = Unrom512::new(&Rom::new(&create_rom(0x0A | 0xE0, 0x10, 8, 0)).unwrap());
+        mapper.cpu_write(0xC000, 0x80 | 0x40 | 3);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+
+        // 32KBのCHR-RAMを8KBずつ切り替える
+        mapper.ppu_write(0x0000, 0x55);
+        mapper.cpu_write(0xC000, 0x00);
+        assert_eq!(mapper.ppu_peek(0x0000), 0x00);
+        mapper.cpu_write(0xC000, 0x40);
+        assert_eq!(mapper.ppu_peek(0x0000), 0x55);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn test_self_flash() {
+        // 電池のbitでフラッシュになる
+        let mut mapper = Unrom512::new(&Rom::new(&create_rom(0x02 | 0xE0, 0x10, 8, 0)).unwrap());
+        let command = |mapper: &mut Unrom512, bank: u8, addr: u16, data: u8| {
+            mapper.cpu_write(0xC000, bank);
+            mapper.cpu_write(addr, data);
+        };
+        // 書き込む前に消す ($5555はバンク1の$9555、$2AAAはバンク0の$AAAA)
+        command(&mut mapper, 1, 0x9555, 0xAA);
+        command(&mut mapper, 0, 0xAAAA, 0x55);
+        command(&mut mapper, 1, 0x9555, 0x80);
+        command(&mut mapper, 1, 0x9555, 0xAA);
+        command(&mut mapper, 0, 0xAAAA, 0x55);
+        command(&mut mapper, 2, 0x8000, 0x30);
+        assert_eq!(mapper.cpu_peek(0x8000), 0xFF);
+
+        command(&mut mapper, 1, 0x9555, 0xAA);
+        command(&mut mapper, 0, 0xAAAA, 0x55);
+        command(&mut mapper, 1, 0x9555, 0xA0);
+        command(&mut mapper, 2, 0x8010, 0x42);
+        assert_eq!(mapper.cpu_peek(0x8010), 0x42);
+        assert_eq!(mapper.save_data().unwrap()[2 * 0x4000 + 0x10], 0x42);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        // フラッシュでない基板は書き込みがROMの値とANDされる
+        let mut raw = create_rom(0xE0, 0x10, 8, 0);
+        raw[16 + 0x3FFF] = 0x01;
+        let mut mapper = Unrom512::new(&Rom::new(&raw).unwrap());
+        assert!(mapper.save_data().is_none());
+        mapper.cpu_write(0xBFFF, 0x03);
+        assert_eq!(mapper.cpu_peek(0x8000), 1);
+    }
+}
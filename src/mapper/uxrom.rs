@@ -0,0 +1,84 @@
+use super::{ChrMemory, Mapper, bank_offset, bus_conflict};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー2 (UxROM)
+// $8000に切り替え可能な16KBバンク、$C000に最後のバンクを固定する
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    prg_bank: u8,
+    // NES 2.0のサブマッパー2はバス競合あり
+    bus_conflicts: bool,
+}
+
+impl Uxrom {
+    pub fn new(rom: &Rom) -> Self {
+        Uxrom {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            mirroring: rom.screen_mirroring,
+            prg_bank: 0,
+            bus_conflicts: rom.submapper == 2,
+        }
+    }
+
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank as usize,
+            0xC000..=0xFFFF => (self.prg_rom.len() / 0x4000).saturating_sub(1),
+            _ => return 0,
+        };
+        self.prg_rom[bank_offset(self.prg_rom.len(), bank, 0x4000, addr)]
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.prg_bank = bus_conflict(self.bus_conflicts, self.cpu_peek(addr), data);
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(0, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_switch_and_fixed_bank() {
+        let mut mapper = Uxrom::new(&Rom::new(&create_rom(0x20, 0, 8, 0)).unwrap());
+        mapper.cpu_write(0x8000, 3);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+        assert_eq!(mapper.cpu_peek(0xC000), 7);
+    }
+
+    #[test]
+    fn test_bus_conflict() {
+        let mut mapper = Uxrom::new(&Rom::new(&create_rom(0x20, 0, 8, 0)).unwrap());
+        mapper.set_bus_conflicts(true);
+        // $C000のROMの値は7なので 5 & 7 = 5、$8000(バンク0)なら 5 & 0 = 0
+        mapper.cpu_write(0xC000, 5);
+        assert_eq!(mapper.cpu_peek(0x8000), 5);
+        mapper.cpu_write(0x8000, 6);
+        assert_eq!(mapper.cpu_peek(0x8000), 4);
+    }
+}
@@ -0,0 +1,321 @@
+use super::vrc_irq::VrcIrq;
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    // IRQなし、CHRバンク番号は8bit
+    Vrc2,
+    // IRQあり、CHRバンク番号は9bit、PRGの入れ替えモードあり
+    Vrc4,
+}
+
+// 基板ごとにレジスタ選択のA0/A1につながっているCPUのアドレス線が違う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Vrc2a,
+    Vrc2b,
+    Vrc2c,
+    Vrc4a,
+    Vrc4b,
+    Vrc4c,
+    Vrc4d,
+    Vrc4e,
+    Vrc4f,
+}
+
+impl Variant {
+    // (チップ, A0になるアドレス線, A1になるアドレス線)
+    fn wiring(self) -> (Chip, u16, u16) {
+        match self {
+            Variant::Vrc2a => (Chip::Vrc2, 0x02, 0x01),
+            Variant::Vrc2b => (Chip::Vrc2, 0x01, 0x02),
+            Variant::Vrc2c => (Chip::Vrc2, 0x02, 0x01),
+            Variant::Vrc4a => (Chip::Vrc4, 0x02, 0x04),
+            Variant::Vrc4b => (Chip::Vrc4, 0x02, 0x01),
+            Variant::Vrc4c => (Chip::Vrc4, 0x40, 0x80),
+            Variant::Vrc4d => (Chip::Vrc4, 0x08, 0x04),
+            Variant::Vrc4e => (Chip::Vrc4, 0x04, 0x08),
+            Variant::Vrc4f => (Chip::Vrc4, 0x01, 0x02),
+        }
+    }
+}
+
+// マッパー21/22/23/25 (VRC2/VRC4)
+pub struct Vrc2 {
+    chip: Chip,
+    a0: u16,
+    a1: u16,
+    // VRC2aはCHRのA10が無く、バンク番号を1bit右にずらして使う
+    chr_shift: u8,
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    prg_banks: [u8; 2],
+    // $9002 bit1: $8000と$C000を入れ替える
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    // PRG-RAMの無いVRC2で$6000-$6FFFに見える1bitのラッチ
+    latch: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc2 {
+    pub fn new(rom: &Rom) -> Self {
+        let mut mapper = Vrc2 {
+            chip: Chip::Vrc4,
+            a0: 0x01,
+            a1: 0x02,
+            chr_shift: 0,
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram: vec![0; prg_ram_size(rom)],
+            prg_banks: [0; 2],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            latch: 0,
+            irq: VrcIrq::new(),
+        };
+        let variant = match (rom.mapper, rom.submapper) {
+            (21, 1) => Some(Variant::Vrc4a),
+            (21, 2) => Some(Variant::Vrc4c),
+            (22, _) => Some(Variant::Vrc2a),
+            (23, 1) => Some(Variant::Vrc4f),
+            (23, 2) => Some(Variant::Vrc4e),
+            (23, 3) => Some(Variant::Vrc2b),
+            (25, 1) => Some(Variant::Vrc4b),
+            (25, 2) => Some(Variant::Vrc4d),
+            (25, 3) => Some(Variant::Vrc2c),
+            _ => None,
+        };
+        match variant {
+            Some(variant) => mapper.set_variant(variant),
+            // サブマッパーが無い時は同じマッパー番号の基板の配線を両方受け付ける
+            None => {
+                (mapper.a0, mapper.a1) = match rom.mapper {
+                    21 => (0x42, 0x84),
+                    25 => (0x0A, 0x05),
+                    _ => (0x05, 0x0A),
+                };
+            }
+        }
+        mapper
+    }
+
+    // ROMデータベースなどで基板がわかった時に配線を決める
+    pub fn set_variant(&mut self, variant: Variant) {
+        (self.chip, self.a0, self.a1) = variant.wiring();
+        self.chr_shift = (variant == Variant::Vrc2a) as u8;
+    }
+
+    // 配線を正規化したレジスタ番号 ($x000-$x003)
+    fn register(&self, addr: u16) -> u16 {
+        let a0 = (addr & self.a0 != 0) as u16;
+        let a1 = (addr & self.a1 != 0) as u16;
+        (addr & 0xF000) | a1 << 1 | a0
+    }
+
+    fn prg_bank_for(&self, addr: u16) -> usize {
+        let second_last = (self.prg_rom.len() / 0x2000).saturating_sub(2);
+        match (addr, self.prg_swap) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.prg_banks[0] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.prg_banks[1] as usize,
+            _ => second_last + 1,
+        }
+    }
+
+    fn chr_bank_for(&self, addr: u16) -> usize {
+        (self.chr_banks[(addr >> 10) as usize] >> self.chr_shift) as usize
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match (self.chip, register) {
+            (_, 0x8000..=0x8003) => self.prg_banks[0] = data & 0x1F,
+            (Chip::Vrc2, 0x9000..=0x9003) => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            (Chip::Vrc4, 0x9000) => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            (Chip::Vrc4, 0x9002) => self.prg_swap = data & 0x02 != 0,
+            (_, 0xA000..=0xA003) => self.prg_banks[1] = data & 0x1F,
+            (_, 0xB000..=0xEFFF) => {
+                // $B000/$B001が0番の下位/上位4bit、$B002/$B003が1番…
+                let index = (((register - 0xB000) >> 12) * 2 + ((register >> 1) & 1)) as usize;
+                let bank = self.chr_banks[index];
+                self.chr_banks[index] = if register & 1 == 0 {
+                    (bank & 0x1F0) | (data & 0x0F) as u16
+                } else {
+                    let mask = if self.chip == Chip::Vrc4 { 0x1F } else { 0x0F };
+                    (bank & 0x0F) | ((data & mask) as u16) << 4
+                };
+            }
+            (Chip::Vrc4, 0xF000) => self.irq.set_latch_low(data),
+            (Chip::Vrc4, 0xF001) => self.irq.set_latch_high(data),
+            (Chip::Vrc4, 0xF002) => self.irq.write_control(data),
+            (Chip::Vrc4, 0xF003) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Vrc2 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x6000..=0x6FFF if self.chip == Chip::Vrc2 => self.latch,
+            0x8000..=0xFFFF => {
+                let offset = bank_offset(self.prg_rom.len(), self.prg_bank_for(addr), 0x2000, addr);
+                self.prg_rom[offset]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0x6000..=0x6FFF if self.chip == Chip::Vrc2 => self.latch = data & 0x01,
+            0x8000..=0xFFFF => self.write_register(self.register(addr), data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank_for(addr), 0x0400, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank_for(addr), 0x0400, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    // NES 2.0ヘッダでマッパー番号とサブマッパーを指定する
+    fn create_vrc(mapper: u8, submapper: u8) -> Vrc2 {
+        let mut raw = create_rom(mapper << 4, (mapper & 0xF0) | 0x08, 8, 16);
+        raw[8] = submapper << 4;
+        for bank in 0..16 {
+            raw[16 + bank * 0x2000] = bank as u8;
+        }
+        let chr = 16 + 8 * 0x4000;
+        for bank in 0..128 {
+            raw[chr + bank * 0x0400] = bank as u8;
+        }
+        Vrc2::new(&Rom::new(&raw).unwrap())
+    }
+
+    #[test]
+    fn test_vrc4_prg_swap() {
+        let mut mapper = create_vrc(23, 1);
+        mapper.cpu_write(0x8000, 3);
+        mapper.cpu_write(0xA000, 4);
+        assert_eq!(mapper.cpu_peek(0x8000), 3);
+        assert_eq!(mapper.cpu_peek(0xA000), 4);
+        assert_eq!(mapper.cpu_peek(0xC000), 14);
+        assert_eq!(mapper.cpu_peek(0xE000), 15);
+
+        mapper.cpu_write(0x9002, 0x02);
+        assert_eq!(mapper.cpu_peek(0x8000), 14);
+        assert_eq!(mapper.cpu_peek(0xC000), 3);
+    }
+
+    #[test]
+    fn test_address_lines() {
+        // VRC4c: A6/A7がレジスタ選択 $B040は0番の上位、$B080は1番の下位
+        let mut mapper = create_vrc(21, 2);
+        mapper.cpu_write(0xB000, 0x05);
+        mapper.cpu_write(0xB040, 0x01);
+        mapper.cpu_write(0xB080, 0x07);
+        assert_eq!(mapper.ppu_peek(0x0000), 0x15);
+        assert_eq!(mapper.ppu_peek(0x0400), 0x07);
+
+        // VRC4b: A0とA1が逆 $B002は0番の上位、$B001は1番の下位
+        let mut mapper = create_vrc(25, 1);
+        mapper.cpu_write(0xB002, 0x03);
+        mapper.cpu_write(0xB001, 0x09);
+        assert_eq!(mapper.ppu_peek(0x0000), 0x30);
+        assert_eq!(mapper.ppu_peek(0x0400), 0x09);
+    }
+
+    #[test]
+    fn test_vrc2a_chr_shift() {
+        let mut mapper = create_vrc(22, 0);
+        mapper.cpu_write(0xB000, 0x0A);
+        assert_eq!(mapper.ppu_peek(0x0000), 0x05);
+        // VRC2にはIRQが無い
+        mapper.cpu_write(0xF002, 0x06);
+        mapper.clock();
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_combined_wiring() {
+        // サブマッパー0ではVRC4eとVRC4fのどちらの書き方でも届く
+        let mut mapper = create_vrc(23, 0);
+        mapper.cpu_write(0x9000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.cpu_write(0xB001, 0x01);
+        mapper.cpu_write(0xB008, 0x02);
+        assert_eq!(mapper.ppu_peek(0x0000), 0x10);
+        assert_eq!(mapper.ppu_peek(0x0400), 0x02);
+    }
+
+    #[test]
+    fn test_vrc4_irq() {
+        let mut mapper = create_vrc(21, 1);
+        mapper.cpu_write(0xF000, 0x0F);
+        mapper.cpu_write(0xF002, 0x0F);
+        mapper.cpu_write(0xF004, 0x06);
+        mapper.clock();
+        assert!(mapper.irq());
+        mapper.cpu_write(0xF006, 0);
+        assert!(!mapper.irq());
+    }
+}
@@ -0,0 +1,417 @@
+use super::vrc_irq::VrcIrq;
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+// 矩形波チャンネル ($9000-$9002, $A000-$A002)
+struct Pulse {
+    // 1なら常に音量を出す（デューティ無視）
+    constant: bool,
+    duty: u8,
+    volume: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    // 15から0へ数え、デューティ以下の間だけ音を出す
+    step: u8,
+}
+
+impl Pulse {
+    fn new() -> Self {
+        Pulse {
+            constant: false,
+            duty: 0,
+            volume: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 15,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.constant = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0x07;
+                self.volume = data & 0x0F;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+// のこぎり波チャンネル ($B000-$B002)
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    // 2クロックごとにrateを足し、14クロック目で0に戻る
+    steps: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn new() -> Self {
+        Sawtooth {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            steps: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.steps = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.steps += 1;
+        if self.steps == 14 {
+            self.steps = 0;
+            self.accumulator = 0;
+        } else if self.steps.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+// VRC6拡張音源 矩形波2つとのこぎり波
+// レジスタは配線を正規化した$9000-$9003, $A000-$A002, $B000-$B002
+pub(super) struct Vrc6Audio {
+    pulses: [Pulse; 2],
+    sawtooth: Sawtooth,
+    // $9003: bit0 停止, bit1 周波数16倍, bit2 周波数256倍
+    control: u8,
+}
+
+impl Vrc6Audio {
+    pub(super) fn new() -> Self {
+        Vrc6Audio {
+            pulses: [Pulse::new(), Pulse::new()],
+            sawtooth: Sawtooth::new(),
+            control: 0,
+        }
+    }
+
+    pub(super) fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0x9000..=0x9002 => self.pulses[0].write(register & 3, data),
+            0x9003 => self.control = data & 0x07,
+            0xA000..=0xA002 => self.pulses[1].write(register & 3, data),
+            0xB000..=0xB002 => self.sawtooth.write(register & 3, data),
+            _ => {}
+        }
+    }
+
+    pub(super) fn clock(&mut self) {
+        if self.control & 0x01 != 0 {
+            return;
+        }
+        let shift = match self.control {
+            0x04..=0x07 => 8,
+            0x02..=0x03 => 4,
+            _ => 0,
+        };
+        for pulse in self.pulses.iter_mut() {
+            pulse.clock(shift);
+        }
+        self.sawtooth.clock(shift);
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        let sum = self.pulses[0].output() + self.pulses[1].output() + self.sawtooth.output();
+        sum as f32 / 61.0
+    }
+}
+
+// マッパー24/26 (VRC6) 26はA0とA1が入れ替わっている
+pub struct Vrc6 {
+    swap_lines: bool,
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    // $8000の16KBバンクと$C000の8KBバンク
+    prg_bank16: u8,
+    prg_bank8: u8,
+    chr_banks: [u8; 8],
+    // $B003: bit0-1 CHRモード, bit2-3 ミラーリング, bit7 PRG-RAM有効
+    banking: u8,
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(rom: &Rom) -> Self {
+        Vrc6 {
+            swap_lines: rom.mapper == 26,
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram: vec![0; prg_ram_size(rom)],
+            prg_bank16: 0,
+            prg_bank8: 0,
+            chr_banks: [0; 8],
+            banking: 0,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    // 配線を正規化したレジスタ番号 ($x000-$x003)
+    fn register(&self, addr: u16) -> u16 {
+        let (a0, a1) = if self.swap_lines {
+            ((addr >> 1) & 1, addr & 1)
+        } else {
+            (addr & 1, (addr >> 1) & 1)
+        };
+        (addr & 0xF000) | a1 << 1 | a0
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let len = self.prg_rom.len();
+        match addr {
+            0x8000..=0xBFFF => bank_offset(len, self.prg_bank16 as usize, 0x4000, addr),
+            0xC000..=0xDFFF => bank_offset(len, self.prg_bank8 as usize, 0x2000, addr),
+            _ => bank_offset(len, (len / 0x2000).saturating_sub(1), 0x2000, addr),
+        }
+    }
+
+    // 1KB単位のCHRバンク番号
+    fn chr_bank_for(&self, addr: u16) -> usize {
+        let index = (addr >> 10) as usize;
+        let a10 = index & 1;
+        match (self.banking & 0x03, addr) {
+            (0, _) | (2 | 3, 0x0000..=0x0FFF) => self.chr_banks[index] as usize,
+            // 2KBバンクはPPUのA10をそのまま使う
+            (1, _) => (self.chr_banks[index / 2] & 0xFE) as usize | a10,
+            _ => (self.chr_banks[4 + (index - 4) / 2] & 0xFE) as usize | a10,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.banking & 0x80 != 0
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0x8000..=0x8003 => self.prg_bank16 = data & 0x0F,
+            0xB003 => self.banking = data,
+            0x9000..=0xB002 => self.audio.write(register, data),
+            0xC000..=0xC003 => self.prg_bank8 = data & 0x1F,
+            0xD000..=0xD003 => self.chr_banks[(register & 3) as usize] = data,
+            0xE000..=0xE003 => self.chr_banks[4 + (register & 3) as usize] = data,
+            0xF000 => self.irq.set_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0x8000..=0xFFFF => self.write_register(self.register(addr), data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank_for(addr), 0x0400, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank_for(addr), 0x0400, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn create_vrc6(mapper: u8) -> Vrc6 {
+        let mut raw = create_rom(mapper << 4, mapper & 0xF0, 8, 16);
+        for bank in 0..16 {
+            raw[16 + bank * 0x2000] = bank as u8;
+        }
+        let chr = 16 + 8 * 0x4000;
+        for bank in 0..128 {
+            raw[chr + bank * 0x0400] = bank as u8;
+        }
+        Vrc6::new(&Rom::new(&raw).unwrap())
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = create_vrc6(24);
+        mapper.cpu_write(0x8000, 2);
+        mapper.cpu_write(0xC000, 9);
+        assert_eq!(mapper.cpu_peek(0x8000), 4);
+        assert_eq!(mapper.cpu_peek(0xA000), 5);
+        assert_eq!(mapper.cpu_peek(0xC000), 9);
+        assert_eq!(mapper.cpu_peek(0xE000), 15);
+    }
+
+    #[test]
+    fn test_chr_modes_and_lines() {
+        // マッパー26では$D001と$D002が入れ替わる
+        let mut mapper = create_vrc6(26);
+        mapper.cpu_write(0xD001, 0x21);
+        mapper.cpu_write(0xD002, 0x22);
+        assert_eq!(mapper.ppu_peek(0x0400), 0x22);
+        assert_eq!(mapper.ppu_peek(0x0800), 0x21);
+
+        // モード1: 2KBバンク
+        mapper.cpu_write(0xB003, 0x01);
+        assert_eq!(mapper.ppu_peek(0x0800), 0x22);
+        assert_eq!(mapper.ppu_peek(0x0C00), 0x23);
+    }
+
+    #[test]
+    fn test_mirroring_and_prg_ram() {
+        let mut mapper = create_vrc6(24);
+        mapper.cpu_write(0x6000, 0x55);
+        assert_eq!(mapper.cpu_peek(0x6000), 0);
+        mapper.cpu_write(0xB003, 0x84);
+        mapper.cpu_write(0x6000, 0x55);
+        assert_eq!(mapper.cpu_peek(0x6000), 0x55);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_pulse() {
+        let mut mapper = create_vrc6(24);
+        // デューティ7/16、音量8、周期1
+        mapper.cpu_write(0x9000, 0x78);
+        mapper.cpu_write(0x9001, 0x01);
+        mapper.cpu_write(0x9002, 0x80);
+        let mut levels = Vec::new();
+        for _ in 0..32 {
+            mapper.clock();
+            levels.push(mapper.audio.pulses[0].output());
+        }
+        assert_eq!(levels.iter().filter(|level| **level == 8).count(), 16);
+
+        // 停止中は進まない
+        mapper.cpu_write(0x9003, 0x01);
+        let step = mapper.audio.pulses[0].step;
+        mapper.clock();
+        assert_eq!(mapper.audio.pulses[0].step, step);
+    }
+
+    #[test]
+    fn test_sawtooth() {
+        let mut mapper = create_vrc6(24);
+        mapper.cpu_write(0xB000, 0x10);
+        mapper.cpu_write(0xB002, 0x80);
+        let mut levels = Vec::new();
+        for _ in 0..14 {
+            mapper.clock();
+            levels.push(mapper.audio.sawtooth.output());
+        }
+        assert_eq!(levels, [0, 2, 2, 4, 4, 6, 6, 8, 8, 10, 10, 12, 12, 0]);
+        assert_eq!(mapper.audio_output(), 0.0);
+    }
+}
@@ -0,0 +1,250 @@
+use super::opll::Opll;
+use super::vrc_irq::VrcIrq;
+use super::{ChrMemory, Mapper, bank_offset, prg_ram_size};
+use crate::cartridge::{Mirroring, Rom};
+
+// OPLLは3.58MHz (CPUの2倍) を72分周したレートで1サンプル進む
+pub(super) const OPLL_CYCLES: u8 = 36;
+
+// マッパー85 (VRC7)
+// VRC7aはA4、VRC7bはA3でレジスタの組を選ぶ
+pub struct Vrc7 {
+    a0: u16,
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    // $E000: bit0-1 ミラーリング, bit6 音源リセット, bit7 PRG-RAM有効
+    control: u8,
+    irq: VrcIrq,
+    opll: Opll,
+    audio_divider: u8,
+}
+
+impl Vrc7 {
+    pub fn new(rom: &Rom) -> Self {
+        Vrc7 {
+            // サブマッパーが無い時は両方の配線を受け付ける
+            a0: match rom.submapper {
+                1 => 0x08,
+                2 => 0x10,
+                _ => 0x18,
+            },
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram: vec![0; prg_ram_size(rom)],
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::new(),
+            opll: Opll::new(),
+            audio_divider: 0,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.control & 0x80 != 0
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        // 音源のポートは$9010と$9030
+        match addr & 0xF030 {
+            0x9010 => return self.opll.write_address(data),
+            0x9030 => return self.opll.write_data(data),
+            _ => {}
+        }
+        let register = (addr & 0xF000) | if addr & self.a0 != 0 { 0x10 } else { 0 };
+        match register {
+            0x8000 => self.prg_banks[0] = data & 0x3F,
+            0x8010 => self.prg_banks[1] = data & 0x3F,
+            0x9000 => self.prg_banks[2] = data & 0x3F,
+            0xA000..=0xD010 => {
+                let index = ((register - 0xA000) >> 12) * 2 + ((register >> 4) & 1);
+                self.chr_banks[index as usize] = data;
+            }
+            0xE000 => {
+                self.control = data;
+                if data & 0x40 != 0 {
+                    self.opll.reset();
+                }
+            }
+            0xE010 => self.irq.set_latch(data),
+            0xF000 => self.irq.write_control(data),
+            0xF010 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) >> 13) as usize] as usize;
+                self.prg_rom[bank_offset(len, bank, 0x2000, addr)]
+            }
+            0xE000..=0xFFFF => {
+                self.prg_rom[bank_offset(len, (len / 0x2000).saturating_sub(1), 0x2000, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0x8000..=0xFFFF => self.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr
+            .read(self.chr_banks[(addr >> 10) as usize] as usize, 0x0400, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+        self.chr.write(bank, 0x0400, addr, data);
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn save_data_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        if self.control & 0x40 != 0 {
+            return;
+        }
+        self.audio_divider += 1;
+        if self.audio_divider == OPLL_CYCLES {
+            self.audio_divider = 0;
+            self.opll.clock();
+        }
+    }
+
+    // 無音は0.5
+    fn audio_output(&self) -> f32 {
+        (self.opll.output() + 1.0) / 2.0
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    fn create_vrc7(submapper: u8) -> Vrc7 {
+        let mut raw = create_rom(0x50, 0x58, 8, 16);
+        raw[8] = submapper << 4;
+        raw[10] = 0x07;
+        for bank in 0..16 {
+            raw[16 + bank * 0x2000] = bank as u8;
+        }
+        let chr = 16 + 8 * 0x4000;
+        for bank in 0..128 {
+            raw[chr + bank * 0x0400] = bank as u8;
+        }
+        Vrc7::new(&Rom::new(&raw).unwrap())
+    }
+
+    #[test]
+    fn test_banks() {
+        // VRC7a: A4で組を選ぶ
+        let mut mapper = create_vrc7(2);
+        mapper.cpu_write(0x8000, 1);
+        mapper.cpu_write(0x8010, 2);
+        mapper.cpu_write(0x9000, 3);
+        assert_eq!(mapper.cpu_peek(0x8000), 1);
+        assert_eq!(mapper.cpu_peek(0xA000), 2);
+        assert_eq!(mapper.cpu_peek(0xC000), 3);
+        assert_eq!(mapper.cpu_peek(0xE000), 15);
+
+        mapper.cpu_write(0xA010, 0x21);
+        mapper.cpu_write(0xD010, 0x27);
+        assert_eq!(mapper.ppu_peek(0x0400), 0x21);
+        assert_eq!(mapper.ppu_peek(0x1C00), 0x27);
+
+        // VRC7b: A3
+        let mut mapper = create_vrc7(1);
+        mapper.cpu_write(0x8008, 5);
+        assert_eq!(mapper.cpu_peek(0xA000), 5);
+    }
+
+    #[test]
+    fn test_control() {
+        let mut mapper = create_vrc7(0);
+        mapper.cpu_write(0x6000, 0x55);
+        assert_eq!(mapper.cpu_peek(0x6000), 0);
+        mapper.cpu_write(0xE000, 0x81);
+        mapper.cpu_write(0x6000, 0x55);
+        assert_eq!(mapper.cpu_peek(0x6000), 0x55);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = create_vrc7(0);
+        mapper.cpu_write(0xE010, 0xFF);
+        mapper.cpu_write(0xF000, 0x06);
+        mapper.clock();
+        assert!(mapper.irq());
+        mapper.cpu_write(0xF010, 0);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut mapper = create_vrc7(0);
+        assert_eq!(mapper.audio_output(), 0.5);
+        // 音色1で鍵盤を押す
+        mapper.cpu_write(0x9010, 0x30);
+        mapper.cpu_write(0x9030, 0x10);
+        mapper.cpu_write(0x9010, 0x10);
+        mapper.cpu_write(0x9030, 0x20);
+        mapper.cpu_write(0x9010, 0x20);
+        mapper.cpu_write(0x9030, 0x19);
+        let mut changed = false;
+        for _ in 0..OPLL_CYCLES as usize * 200 {
+            mapper.clock();
+            changed |= mapper.audio_output() != 0.5;
+        }
+        assert!(changed);
+
+        // 音源リセット中は止まる
+        mapper.cpu_write(0xE000, 0x40);
+        mapper.clock();
+        assert_eq!(mapper.audio_output(), 0.5);
+    }
+}
@@ -0,0 +1,133 @@
+// コナミVRC4/VRC6/VRC7で共通のIRQカウンタ
+// 8bitのカウンタが$FFから桁あふれするとIRQを出し、ラッチの値に戻る
+// スキャンラインモードではCPUサイクルをプリスケーラで341/3ごとに分けて数える
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    // $x001/$F001の各ビット A: 確認後に有効へ戻す, E: 有効, M: サイクルモード
+    enable_after_ack: bool,
+    enabled: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl Default for VrcIrq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: 341,
+            enable_after_ack: false,
+            enabled: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub fn set_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    // VRC4はラッチを4bitずつ書き込む
+    pub fn set_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xF0) | (value & 0x0F);
+    }
+
+    pub fn set_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0F) | (value & 0x0F) << 4;
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 0x01 != 0;
+        self.enabled = value & 0x02 != 0;
+        self.cycle_mode = value & 0x04 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+        self.pending = false;
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    // CPUの1サイクルごとに呼ぶ
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+            return;
+        }
+        self.prescaler -= 3;
+        if self.prescaler <= 0 {
+            self.prescaler += 341;
+            self.clock_counter();
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cycle_mode() {
+        let mut irq = VrcIrq::new();
+        irq.set_latch(0xFD);
+        irq.write_control(0x06);
+        irq.clock();
+        irq.clock();
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+
+        // Aが0なので確認すると止まる
+        irq.acknowledge();
+        for _ in 0..0x200 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+    }
+
+    #[test]
+    fn test_scanline_mode() {
+        let mut irq = VrcIrq::new();
+        irq.set_latch_low(0x0E);
+        irq.set_latch_high(0x0F);
+        irq.write_control(0x02);
+        // 1ラインはおよそ113.67サイクル
+        for _ in 0..113 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+        for _ in 0..115 {
+            irq.clock();
+        }
+        assert!(irq.pending());
+    }
+}
@@ -0,0 +1,96 @@
+use super::{ChrMemory, Mapper, bank_offset};
+use crate::cartridge::{Mirroring, Rom};
+
+// マッパー99 (Vs. System)
+// $4016のbit2で8KBのCHRバンクを切り替える
+// 40KBのPRGを持つゲームは同じbitで$8000-$9FFFの8KBも切り替わる
+pub struct VsSystem {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    // 2KBの共有RAMが$6000-$7FFFにミラーされる
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+    bank: u8,
+}
+
+impl VsSystem {
+    pub fn new(rom: &Rom) -> Self {
+        VsSystem {
+            prg_rom: rom.prg_rom.clone(),
+            chr: ChrMemory::new(rom),
+            prg_ram: vec![0; 0x0800],
+            mirroring: rom.screen_mirroring,
+            bank: 0,
+        }
+    }
+}
+
+impl Mapper for VsSystem {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[(addr & 0x07FF) as usize],
+            0x8000..=0x9FFF if self.prg_rom.len() > 0x8000 => {
+                let bank = self.bank as usize * 4;
+                self.prg_rom[bank_offset(self.prg_rom.len(), bank, 0x2000, addr)]
+            }
+            0x8000..=0xFFFF => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram[(addr & 0x07FF) as usize] = data;
+        }
+    }
+
+    fn controller_write(&mut self, data: u8) {
+        self.bank = (data >> 2) & 0x01;
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.bank as usize, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.bank as usize, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_chr_bank() {
+        let mut raw = create_rom(0x30, 0x60, 2, 2);
+        raw[16 + 0x8000 + 0x2000] = 1;
+        let mut mapper = VsSystem::new(&Rom::new(&raw).unwrap());
+        assert_eq!(mapper.ppu_read(0x0000), 0xCC);
+        mapper.controller_write(0x04);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+        // 32KBのPRGは切り替わらない
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+    }
+
+    #[test]
+    fn test_40k_prg() {
+        // 40KB: 後ろに8KBを足す
+        let mut rom = Rom::new(&create_rom(0x30, 0x60, 2, 1)).unwrap();
+        rom.prg_rom.extend(vec![2; 0x2000]);
+        let mut mapper = VsSystem::new(&rom);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xE000), 1);
+        mapper.controller_write(0x04);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+
+        mapper.cpu_write(0x6000, 0x55);
+        assert_eq!(mapper.cpu_read(0x6800), 0x55);
+    }
+}
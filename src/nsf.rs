@@ -0,0 +1,553 @@
+use crate::cartridge::{ConsoleType, Mirroring, Rom, RomError, Timing};
+
+// NSF/NSFe形式の音楽ファイル
+// NSFは128byteのヘッダの後に曲のデータが続く
+// NSFeは「4byteの長さ・4文字のID・中身」のチャンクが続き、曲名や長さも持てる
+pub const NSF_TAG: &[u8; 5] = b"NESM\x1A";
+pub const NSFE_TAG: &[u8; 4] = b"NSFE";
+const HEADER_SIZE: usize = 0x80;
+
+// マッパー番号の代わり NES 2.0の12bitの範囲の外にして実際の基板と重ならないようにする
+pub const MAPPER: u16 = 0x1000;
+// バンクの大きさ
+pub const BANK_SIZE: usize = 0x1000;
+// $5FF6-$5FFF 前の2つはディスクシステムの時だけ$6000-$7FFFを切り替える
+pub const BANK_REGISTERS: u16 = 0x5FF6;
+
+// 拡張音源のフラグ
+pub const CHIP_VRC6: u8 = 0x01;
+pub const CHIP_VRC7: u8 = 0x02;
+pub const CHIP_FDS: u8 = 0x04;
+pub const CHIP_MMC5: u8 = 0x08;
+pub const CHIP_NAMCO163: u8 = 0x10;
+pub const CHIP_SUNSOFT5B: u8 = 0x20;
+const CHIP_NAMES: [(u8, &str); 6] = [
+    (CHIP_VRC6, "VRC6"),
+    (CHIP_VRC7, "VRC7"),
+    (CHIP_FDS, "FDS"),
+    (CHIP_MMC5, "MMC5"),
+    (CHIP_NAMCO163, "Namco 163"),
+    (CHIP_SUNSOFT5B, "Sunsoft 5B"),
+];
+
+// PLAYを呼ぶ間隔の既定値 (マイクロ秒)
+const NTSC_SPEED: u16 = 16639;
+const PAL_SPEED: u16 = 19997;
+const NTSC_CPU_CLOCK: u64 = 1_789_773;
+const PAL_CPU_CLOCK: u64 = 1_662_607;
+
+// NSFeの各曲の情報 分からないものはNone
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Track {
+    pub name: Option<String>,
+    pub length_ms: Option<u32>,
+    pub fade_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    // NSFeだけ
+    pub ripper: String,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub songs: u8,
+    // 0から数える
+    pub starting_song: u8,
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub timing: Timing,
+    pub chips: u8,
+    // バンク切り替えを使う時の$8000-$FFFFの初期バンク
+    pub banks: Option<[u8; 8]>,
+    pub data: Vec<u8>,
+    // 曲ごとの情報 songsと同じ数
+    pub tracks: Vec<Track>,
+    // NSFeの再生順 無ければ番号順
+    pub playlist: Option<Vec<u8>>,
+}
+
+// CPUのクロック (Hz) PAL以外はNTSCとして鳴らす
+pub fn cpu_clock(timing: Timing) -> u64 {
+    match timing {
+        Timing::Pal => PAL_CPU_CLOCK,
+        _ => NTSC_CPU_CLOCK,
+    }
+}
+
+pub fn is_nsf(raw: &[u8]) -> bool {
+    raw.starts_with(NSF_TAG) || raw.starts_with(NSFE_TAG)
+}
+
+// 0で終わる文字列
+fn string(data: &[u8]) -> String {
+    let text = data.split(|&b| b == 0).next().unwrap_or(&[]);
+    String::from_utf8_lossy(text).trim().to_string()
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+// bit0 PAL, bit1 両対応
+fn region(flags: u8) -> Timing {
+    if flags & 0x02 != 0 {
+        Timing::MultiRegion
+    } else if flags & 0x01 != 0 {
+        Timing::Pal
+    } else {
+        Timing::Ntsc
+    }
+}
+
+// 0でなければバンク切り替えを使う
+fn banks(data: &[u8]) -> Option<[u8; 8]> {
+    let mut banks = [0; 8];
+    for (bank, &byte) in banks.iter_mut().zip(data) {
+        *bank = byte;
+    }
+    banks.iter().any(|&b| b != 0).then_some(banks)
+}
+
+// NSFeの曲の長さ 負の値は指定なし
+fn milliseconds(data: &[u8]) -> Vec<Option<u32>> {
+    data.chunks_exact(4)
+        .map(|ms| {
+            let ms = i32::from_le_bytes(ms.try_into().unwrap());
+            u32::try_from(ms).ok()
+        })
+        .collect()
+}
+
+impl Nsf {
+    pub fn parse(raw: &[u8]) -> Result<Nsf, RomError> {
+        let nsf = if raw.starts_with(NSF_TAG) {
+            Self::parse_nsf(raw)?
+        } else if raw.starts_with(NSFE_TAG) {
+            Self::parse_nsfe(raw)?
+        } else {
+            return Err(RomError::BadMagic);
+        };
+        if nsf.data.is_empty() {
+            return Err(RomError::Unsupported("NSF file without music data"));
+        }
+        // バンク切り替えを使わない曲は置き場所の後ろに収まっている必要がある
+        let base = if nsf.uses_fds() { 0x6000 } else { 0x8000 };
+        if nsf.banks.is_none() && nsf.load_addr < base {
+            return Err(RomError::Unsupported("NSF load address below $8000"));
+        }
+        Ok(nsf)
+    }
+
+    fn parse_nsf(raw: &[u8]) -> Result<Nsf, RomError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::Truncated {
+                expected: HEADER_SIZE,
+                actual: raw.len(),
+            });
+        }
+        let header = &raw[..HEADER_SIZE];
+        let songs = header[6];
+        // NSF2では$7D-$7Fにデータの長さがあり、その後ろはメタデータ
+        let data_len = u32::from_le_bytes([header[0x7D], header[0x7E], header[0x7F], 0]) as usize;
+        let data = &raw[HEADER_SIZE..];
+        let data = if header[5] >= 2 && data_len != 0 {
+            &data[..data_len.min(data.len())]
+        } else {
+            data
+        };
+        Ok(Nsf {
+            title: string(&header[0x0E..0x2E]),
+            artist: string(&header[0x2E..0x4E]),
+            copyright: string(&header[0x4E..0x6E]),
+            ripper: String::new(),
+            load_addr: u16_at(header, 0x08).unwrap(),
+            init_addr: u16_at(header, 0x0A).unwrap(),
+            play_addr: u16_at(header, 0x0C).unwrap(),
+            songs,
+            starting_song: header[7].saturating_sub(1),
+            ntsc_speed: u16_at(header, 0x6E).unwrap(),
+            pal_speed: u16_at(header, 0x78).unwrap(),
+            timing: region(header[0x7A]),
+            chips: header[0x7B],
+            banks: banks(&header[0x70..0x78]),
+            data: data.to_vec(),
+            tracks: vec![Track::default(); songs as usize],
+            playlist: None,
+        })
+    }
+
+    fn parse_nsfe(raw: &[u8]) -> Result<Nsf, RomError> {
+        let mut nsf = Nsf {
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: String::new(),
+            load_addr: 0,
+            init_addr: 0,
+            play_addr: 0,
+            songs: 0,
+            starting_song: 0,
+            ntsc_speed: NTSC_SPEED,
+            pal_speed: PAL_SPEED,
+            timing: Timing::Ntsc,
+            chips: 0,
+            banks: None,
+            data: Vec::new(),
+            tracks: Vec::new(),
+            playlist: None,
+        };
+        let mut info = false;
+        let mut names = Vec::new();
+        let mut lengths = Vec::new();
+        let mut fades = Vec::new();
+
+        let mut pos = NSFE_TAG.len();
+        while pos < raw.len() {
+            if raw.len() < pos + 8 {
+                return Err(RomError::Truncated {
+                    expected: pos + 8,
+                    actual: raw.len(),
+                });
+            }
+            let len = u32::from_le_bytes(raw[pos..pos + 4].try_into().unwrap()) as usize;
+            let id = &raw[pos + 4..pos + 8];
+            let start = pos + 8;
+            let end = start.saturating_add(len);
+            if raw.len() < end {
+                return Err(RomError::Truncated {
+                    expected: end,
+                    actual: raw.len(),
+                });
+            }
+            let data = &raw[start..end];
+            match id {
+                b"INFO" => {
+                    // 曲数と最初の曲は省略できる 省略されたら1曲で最初から
+                    if data.len() < 8 {
+                        return Err(RomError::Unsupported(
+                            "NSFe INFO chunk shorter than 8 bytes",
+                        ));
+                    }
+                    info = true;
+                    nsf.load_addr = u16_at(data, 0).unwrap();
+                    nsf.init_addr = u16_at(data, 2).unwrap();
+                    nsf.play_addr = u16_at(data, 4).unwrap();
+                    nsf.timing = region(data[6]);
+                    nsf.chips = data[7];
+                    nsf.songs = data.get(8).copied().unwrap_or(1);
+                    nsf.starting_song = data.get(9).copied().unwrap_or(0);
+                }
+                b"DATA" => nsf.data = data.to_vec(),
+                b"BANK" => nsf.banks = banks(data),
+                b"RATE" => {
+                    if let Some(speed) = u16_at(data, 0) {
+                        nsf.ntsc_speed = speed;
+                    }
+                    if let Some(speed) = u16_at(data, 2) {
+                        nsf.pal_speed = speed;
+                    }
+                }
+                b"auth" => {
+                    let mut fields = data.split(|&b| b == 0).map(string);
+                    nsf.title = fields.next().unwrap_or_default();
+                    nsf.artist = fields.next().unwrap_or_default();
+                    nsf.copyright = fields.next().unwrap_or_default();
+                    nsf.ripper = fields.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    names = data.split(|&b| b == 0).map(string).collect();
+                }
+                b"time" => lengths = milliseconds(data),
+                b"fade" => fades = milliseconds(data),
+                b"plst" => nsf.playlist = Some(data.to_vec()),
+                b"NEND" => break,
+                // 大文字で始まるチャンクは読めないと再生できない
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(RomError::Unsupported(
+                        "NSFe file with an unknown required chunk",
+                    ));
+                }
+                _ => {}
+            }
+            pos = end;
+        }
+        if !info {
+            return Err(RomError::Unsupported("NSFe file without an INFO chunk"));
+        }
+
+        nsf.tracks = (0..nsf.songs as usize)
+            .map(|i| Track {
+                name: names.get(i).filter(|name| !name.is_empty()).cloned(),
+                length_ms: lengths.get(i).copied().flatten(),
+                fade_ms: fades.get(i).copied().flatten(),
+            })
+            .collect();
+        if let Some(playlist) = &mut nsf.playlist {
+            playlist.retain(|&song| song < nsf.songs);
+        }
+        Ok(nsf)
+    }
+
+    pub fn uses_fds(&self) -> bool {
+        self.chips & CHIP_FDS != 0
+    }
+
+    pub fn chip_names(&self) -> Vec<&'static str> {
+        CHIP_NAMES
+            .iter()
+            .filter(|(flag, _)| self.chips & flag != 0)
+            .map(|&(_, name)| name)
+            .collect()
+    }
+
+    // 再生する曲の順番
+    pub fn track_order(&self) -> Vec<u8> {
+        match &self.playlist {
+            Some(playlist) => playlist.clone(),
+            None => (0..self.songs).collect(),
+        }
+    }
+
+    // 4KBごとに区切ったPRGの中身
+    // バンク切り替えを使う曲はload_addrの下位12bitの位置から置く
+    // 使わない曲は$8000(ディスクシステムなら$6000)からの固定の並びにする
+    pub fn image(&self) -> Vec<u8> {
+        let (offset, size) = match self.banks {
+            Some(_) => (self.load_addr as usize & (BANK_SIZE - 1), None),
+            None if self.uses_fds() => (self.load_addr as usize - 0x6000, Some(0xA000)),
+            None => (self.load_addr as usize - 0x8000, Some(0x8000)),
+        };
+        let mut image = vec![0; offset];
+        image.extend(&self.data);
+        let size = size.unwrap_or(image.len().div_ceil(BANK_SIZE) * BANK_SIZE);
+        image.resize(size, 0);
+        image
+    }
+
+    // $5FF6-$5FFFに書くバンク番号
+    pub fn initial_banks(&self) -> [u8; 10] {
+        let mut initial = [0; 10];
+        match self.banks {
+            Some(banks) => {
+                initial[2..].copy_from_slice(&banks);
+                // ディスクシステムの$6000-$7FFFは$5FFE/$5FFFと同じバンクから始める
+                initial[0] = banks[6];
+                initial[1] = banks[7];
+            }
+            None if self.uses_fds() => {
+                for (i, bank) in initial.iter_mut().enumerate() {
+                    *bank = i as u8;
+                }
+            }
+            None => {
+                for (i, bank) in initial[2..].iter_mut().enumerate() {
+                    *bank = i as u8;
+                }
+            }
+        }
+        initial
+    }
+
+    // INITを呼ぶ前にプレイヤーが書くレジスタ
+    pub fn bank_writes(&self) -> Vec<(u16, u8)> {
+        let skip = if self.uses_fds() { 0 } else { 2 };
+        self.initial_banks()
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .map(|(i, &bank)| (BANK_REGISTERS + i as u16, bank))
+            .collect()
+    }
+
+    // INITに渡すAレジスタ(曲番号)とXレジスタ(0はNTSC、1はPAL)
+    pub fn init_registers(&self, song: u8, timing: Timing) -> (u8, u8) {
+        (song, (timing == Timing::Pal) as u8)
+    }
+
+    // PLAYを呼ぶ間隔 (CPUサイクル)
+    pub fn play_cycles(&self, timing: Timing) -> u64 {
+        let speed = match timing {
+            Timing::Pal => self.pal_speed,
+            _ => self.ntsc_speed,
+        };
+        let clock = cpu_clock(timing);
+        let speed = match speed {
+            0 if timing == Timing::Pal => PAL_SPEED,
+            0 => NTSC_SPEED,
+            speed => speed,
+        };
+        speed as u64 * clock / 1_000_000
+    }
+
+    // 専用のマッパーに挿すRom 拡張音源のフラグはサブマッパーに入れる
+    pub fn rom(&self) -> Rom {
+        Rom {
+            prg_rom: self.image(),
+            chr_rom: Vec::new(),
+            trainer: None,
+            mapper: MAPPER,
+            submapper: self.chips,
+            screen_mirroring: Mirroring::Horizontal,
+            battery: false,
+            nes2: false,
+            prg_ram_size: 0x2000,
+            prg_nvram_size: 0,
+            chr_ram_size: 0x2000,
+            chr_nvram_size: 0,
+            timing: self.timing,
+            console_type: ConsoleType::Nes,
+            misc_rom: Vec::new(),
+            expansion_device: 0,
+            disk_sides: Vec::new(),
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // テスト用のNSFファイルを組み立てる
+    pub fn create_nsf(load_addr: u16, banks: [u8; 8], chips: u8, data: &[u8]) -> Vec<u8> {
+        let mut raw = NSF_TAG.to_vec();
+        raw.extend([1, 3, 2]);
+        raw.extend(load_addr.to_le_bytes());
+        raw.extend(0x8000u16.to_le_bytes());
+        raw.extend(0x8003u16.to_le_bytes());
+        raw.extend(b"Title");
+        raw.resize(0x2E, 0);
+        raw.extend(b"Artist");
+        raw.resize(0x6E, 0);
+        raw.extend(16639u16.to_le_bytes());
+        raw.extend(banks);
+        raw.extend(20000u16.to_le_bytes());
+        raw.extend([0, chips]);
+        raw.resize(HEADER_SIZE, 0);
+        raw.extend(data);
+        raw
+    }
+
+    fn chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+        out.extend((data.len() as u32).to_le_bytes());
+        out.extend(id);
+        out.extend(data);
+    }
+
+    #[test]
+    fn test_parse_nsf() {
+        let raw = create_nsf(0x8000, [0; 8], CHIP_VRC6 | CHIP_FDS, &[0xEA; 0x100]);
+        assert!(is_nsf(&raw));
+        let nsf = Nsf::parse(&raw).unwrap();
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(nsf.artist, "Artist");
+        assert_eq!((nsf.songs, nsf.starting_song), (3, 1));
+        assert_eq!((nsf.init_addr, nsf.play_addr), (0x8000, 0x8003));
+        assert_eq!(nsf.chip_names(), vec!["VRC6", "FDS"]);
+        assert_eq!(nsf.banks, None);
+        assert_eq!(nsf.tracks.len(), 3);
+        assert_eq!(nsf.track_order(), vec![0, 1, 2]);
+        // 60Hz・50Hz
+        assert_eq!(nsf.play_cycles(Timing::Ntsc), 29780);
+        assert_eq!(nsf.play_cycles(Timing::Pal), 33252);
+        assert_eq!(nsf.init_registers(2, Timing::Pal), (2, 1));
+
+        assert!(Nsf::parse(&raw[..0x40]).is_err());
+    }
+
+    #[test]
+    fn test_image() {
+        // 固定の並びなら$8000からの32KBに置く
+        let nsf = Nsf::parse(&create_nsf(0x8100, [0; 8], 0, &[1, 2])).unwrap();
+        let image = nsf.image();
+        assert_eq!(image.len(), 0x8000);
+        assert_eq!(&image[0x100..0x102], &[1, 2]);
+        assert_eq!(nsf.initial_banks(), [0, 0, 0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(nsf.bank_writes()[0], (0x5FF8, 0));
+
+        // バンク切り替えはload_addrの下位12bitを詰め物にする
+        let nsf = Nsf::parse(&create_nsf(
+            0x8123,
+            [0, 1, 2, 3, 4, 5, 6, 1],
+            0,
+            &[7; 0x1000],
+        ))
+        .unwrap();
+        let image = nsf.image();
+        assert_eq!(image.len(), 0x2000);
+        assert_eq!((image[0x122], image[0x123]), (0, 7));
+        assert_eq!(nsf.bank_writes().len(), 8);
+
+        // ディスクシステムは$5FF6/$5FF7も書く
+        let nsf = Nsf::parse(&create_nsf(0x6000, [0; 8], CHIP_FDS, &[1])).unwrap();
+        assert_eq!(nsf.image().len(), 0xA000);
+        assert_eq!(nsf.bank_writes()[0], (0x5FF6, 0));
+        assert!(Nsf::parse(&create_nsf(0x6000, [0; 8], 0, &[1])).is_err());
+    }
+
+    #[test]
+    fn test_parse_nsfe() {
+        let mut raw = NSFE_TAG.to_vec();
+        let mut info = Vec::new();
+        info.extend(0x8000u16.to_le_bytes());
+        info.extend(0x8000u16.to_le_bytes());
+        info.extend(0x8003u16.to_le_bytes());
+        info.extend([1, CHIP_SUNSOFT5B, 2, 1]);
+        chunk(&mut raw, b"INFO", &info);
+        chunk(&mut raw, b"DATA", &[0xEA; 16]);
+        chunk(&mut raw, b"auth", b"Song\0Composer\0\0Ripper\0");
+        chunk(&mut raw, b"tlbl", b"First\0Second\0");
+        let mut time = 90_000i32.to_le_bytes().to_vec();
+        time.extend((-1i32).to_le_bytes());
+        chunk(&mut raw, b"time", &time);
+        chunk(&mut raw, b"fade", &5_000i32.to_le_bytes());
+        chunk(&mut raw, b"plst", &[1, 0, 7]);
+        // 小文字で始まる知らないチャンクは飛ばす
+        chunk(&mut raw, b"xtra", &[0; 3]);
+        chunk(&mut raw, b"NEND", &[]);
+
+        let nsf = Nsf::parse(&raw).unwrap();
+        assert_eq!(nsf.title, "Song");
+        assert_eq!(nsf.artist, "Composer");
+        assert_eq!(nsf.ripper, "Ripper");
+        assert_eq!(nsf.timing, Timing::Pal);
+        assert_eq!((nsf.songs, nsf.starting_song), (2, 1));
+        assert_eq!(nsf.ntsc_speed, NTSC_SPEED);
+        assert_eq!(
+            nsf.tracks[0],
+            Track {
+                name: Some("First".to_string()),
+                length_ms: Some(90_000),
+                fade_ms: Some(5_000),
+            }
+        );
+        assert_eq!(nsf.tracks[1].length_ms, None);
+        // 範囲外の曲は再生順から外す
+        assert_eq!(nsf.track_order(), vec![1, 0]);
+        assert_eq!(nsf.rom().submapper, CHIP_SUNSOFT5B);
+
+        // INFOの後ろ2byteは省略できる
+        for len in [8, 9] {
+            let mut raw = NSFE_TAG.to_vec();
+            chunk(&mut raw, b"INFO", &info[..len]);
+            chunk(&mut raw, b"DATA", &[0xEA; 16]);
+            chunk(&mut raw, b"NEND", &[]);
+            let short = Nsf::parse(&raw).unwrap();
+            assert_eq!(short.songs, if len == 9 { 2 } else { 1 });
+            assert_eq!(short.starting_song, 0);
+            assert_eq!(short.tracks.len(), short.songs as usize);
+        }
+        let mut short = NSFE_TAG.to_vec();
+        chunk(&mut short, b"INFO", &info[..7]);
+        assert!(Nsf::parse(&short).is_err());
+
+        // 大文字で始まる知らないチャンクは読めない
+        let mut raw = raw[..raw.len() - 8].to_vec();
+        chunk(&mut raw, b"XTRA", &[0; 3]);
+        assert!(Nsf::parse(&raw).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use crate::apu::Apu;
+use crate::bus::Bus;
+use crate::cartridge::{Cartridge, RomError, Timing};
+use crate::cpu::{CPU, CpuError};
+use crate::nsf::{self, Nsf};
+use std::f32::consts::PI;
+use std::fmt;
+
+// NSFを鳴らすプレイヤー PPUは使わない
+// INITを1回呼んだ後、ヘッダに書かれた間隔でPLAYを呼び続け、その間のAPUと拡張音源の出力を録る
+
+// INIT/PLAYから戻ってくる番地 PCがここに来たら止める
+// $4100-$4FFFには何もつながっていないので、曲のコードが実行することはない
+const RETURN_ADDR: u16 = 0x4100;
+// INIT/PLAYがこの秒数で戻らなければ止まったとみなす
+const TIMEOUT_SECONDS: u64 = 4;
+// NSFeに長さが書かれていない曲の長さとフェードアウト
+pub const DEFAULT_LENGTH_MS: u32 = 180_000;
+pub const DEFAULT_FADE_MS: u32 = 8_000;
+// 拡張音源は0.0-1.0で出てくるので、本体のAPUと同じくらいになるように半分にする
+const EXPANSION_LEVEL: f32 = 0.5;
+// 本体の出力段のフィルタ 直流を切る高域通過と、耳に痛い高音を落とす低域通過
+const HIGH_PASS_HZ: f32 = 90.0;
+const LOW_PASS_HZ: f32 = 14_000.0;
+
+#[derive(Debug)]
+pub enum PlayError {
+    Rom(RomError),
+    Cpu(CpuError),
+    // 0から数えた曲番号がない
+    NoSuchSong(u8),
+    // INITかPLAYが戻ってこなかった
+    NoReturn { routine: &'static str, addr: u16 },
+}
+
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayError::Rom(err) => write!(f, "{}", err),
+            PlayError::Cpu(err) => write!(f, "{}", err),
+            PlayError::NoSuchSong(song) => write!(f, "no song {}", *song as u16 + 1),
+            PlayError::NoReturn { routine, addr } => {
+                write!(f, "{} did not return (stopped at ${:04X})", routine, addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PlayError {}
+
+impl From<CpuError> for PlayError {
+    fn from(err: CpuError) -> Self {
+        PlayError::Cpu(err)
+    }
+}
+
+// CPUサイクルからサンプリング周波数への変換とフィルタ
+struct Output {
+    // 次にサンプルを取るCPUサイクル
+    next_sample: f64,
+    cycles_per_sample: f64,
+    high_pass: f32,
+    high_pass_input: f32,
+    high_pass_output: f32,
+    low_pass: f32,
+    low_pass_output: f32,
+    samples: Vec<f32>,
+}
+
+impl Output {
+    fn new(cpu_clock: u64, sample_rate: u32) -> Self {
+        let dt = 1.0 / sample_rate as f32;
+        let rc = |hz: f32| 1.0 / (2.0 * PI * hz);
+        Output {
+            next_sample: 0.0,
+            cycles_per_sample: cpu_clock as f64 / sample_rate as f64,
+            high_pass: rc(HIGH_PASS_HZ) / (rc(HIGH_PASS_HZ) + dt),
+            high_pass_input: 0.0,
+            high_pass_output: 0.0,
+            low_pass: dt / (rc(LOW_PASS_HZ) + dt),
+            low_pass_output: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    // サンプルを取る時刻を過ぎていれば、その時点の出力を録る
+    fn sample(&mut self, bus: &Bus) {
+        while bus.cycles as f64 >= self.next_sample {
+            let expansion = bus.cartridge().map_or(0.0, Cartridge::audio_output);
+            let input = bus.audio_output() + expansion * EXPANSION_LEVEL;
+
+            self.high_pass_output =
+                self.high_pass * (self.high_pass_output + input - self.high_pass_input);
+            self.high_pass_input = input;
+            self.low_pass_output += (self.high_pass_output - self.low_pass_output) * self.low_pass;
+
+            self.samples.push(self.low_pass_output);
+            self.next_sample += self.cycles_per_sample;
+        }
+    }
+}
+
+pub struct NsfPlayer {
+    pub nsf: Nsf,
+    timing: Timing,
+    sample_rate: u32,
+    cpu: CPU,
+    output: Output,
+    // 次にPLAYを呼ぶCPUサイクル
+    next_play: u64,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf, sample_rate: u32) -> Self {
+        // 両対応の曲はNTSCで鳴らす
+        let timing = match nsf.timing {
+            Timing::Pal => Timing::Pal,
+            _ => Timing::Ntsc,
+        };
+        NsfPlayer {
+            nsf,
+            timing,
+            sample_rate,
+            cpu: CPU::new(),
+            output: Output::new(nsf::cpu_clock(timing), sample_rate),
+            next_play: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // 0から数えたsong番目の曲のINITを呼ぶ 本体とカートリッジは電源を入れ直した状態から始める
+    pub fn start(&mut self, song: u8) -> Result<(), PlayError> {
+        if song >= self.nsf.songs {
+            return Err(PlayError::NoSuchSong(song));
+        }
+        let cartridge = Cartridge::new(self.nsf.rom()).map_err(PlayError::Rom)?;
+        self.cpu = CPU::new();
+        self.cpu
+            .bus
+            .map(0x4000..=0x4017, Box::new(Apu::new(self.timing)))
+            .expect("$4000-$4017 is free");
+        self.cpu.bus.insert_cartridge(cartridge);
+        self.output = Output::new(nsf::cpu_clock(self.timing), self.sample_rate);
+
+        // APUを初期化してから、初期バンクを書く
+        let bus = &mut self.cpu.bus;
+        for addr in 0x4000..=0x4013 {
+            bus.mem_write(addr, 0x00);
+        }
+        bus.mem_write(0x4015, 0x00);
+        bus.mem_write(0x4015, 0x0F);
+        bus.mem_write(0x4017, 0x40);
+        for (addr, bank) in self.nsf.bank_writes() {
+            bus.mem_write(addr, bank);
+        }
+
+        let (a, x) = self.nsf.init_registers(song, self.timing);
+        self.cpu.register_a = a;
+        self.cpu.register_x = x;
+        self.call(self.nsf.init_addr, "INIT")?;
+        self.next_play = self.cpu.bus.cycles + self.nsf.play_cycles(self.timing);
+        Ok(())
+    }
+
+    // addrのサブルーチンをJSRで呼んだのと同じように実行し、RTSで戻るまで待つ
+    fn call(&mut self, addr: u16, routine: &'static str) -> Result<(), PlayError> {
+        let cpu = &mut self.cpu;
+        // RTSは積まれた番地+1に戻る
+        let [lo, hi] = (RETURN_ADDR - 1).to_le_bytes();
+        cpu.bus.mem_write(0x01FD, hi);
+        cpu.bus.mem_write(0x01FC, lo);
+        cpu.stack_pointer = 0xFB;
+        cpu.program_counter = addr;
+
+        let deadline = cpu.bus.cycles + nsf::cpu_clock(self.timing) * TIMEOUT_SECONDS;
+        let output = &mut self.output;
+        cpu.run_with_callback(|cpu| {
+            output.sample(&cpu.bus);
+            cpu.program_counter != RETURN_ADDR && cpu.bus.cycles < deadline
+        })?;
+        if cpu.program_counter != RETURN_ADDR {
+            return Err(PlayError::NoReturn {
+                routine,
+                addr: cpu.program_counter,
+            });
+        }
+        Ok(())
+    }
+
+    // count個のサンプルができるまでPLAYを呼び続ける 値はおおよそ-1.0から1.0
+    pub fn render(&mut self, count: usize) -> Result<Vec<f32>, PlayError> {
+        while self.output.samples.len() < count {
+            let cycles = self.cpu.bus.cycles;
+            if cycles >= self.next_play {
+                // PLAYが間隔より長くかかった時は、戻ったらすぐに次を呼ぶ
+                self.next_play += self.nsf.play_cycles(self.timing);
+                self.call(self.nsf.play_addr, "PLAY")?;
+            } else {
+                // 次のPLAYまではCPUを止めたまま時間だけ進める
+                let wait = (self.next_play - cycles).min(8) as u8;
+                self.cpu.bus.tick(wait);
+                self.output.sample(&self.cpu.bus);
+            }
+        }
+        Ok(self.output.samples.drain(..count).collect())
+    }
+
+    // 1曲を最初から終わりまで16bitのPCMにする
+    // NSFeの長さとフェードアウトを使い、無ければ既定の長さにする
+    pub fn render_track(&mut self, song: u8) -> Result<Vec<i16>, PlayError> {
+        let track = self
+            .nsf
+            .tracks
+            .get(song as usize)
+            .ok_or(PlayError::NoSuchSong(song))?;
+        let to_samples = |ms: u32| (ms as u64 * self.sample_rate as u64 / 1000) as usize;
+        let length = to_samples(track.length_ms.unwrap_or(DEFAULT_LENGTH_MS));
+        let fade = to_samples(track.fade_ms.unwrap_or(DEFAULT_FADE_MS));
+
+        self.start(song)?;
+        let samples = self.render(length + fade)?;
+        Ok(samples
+            .iter()
+            .enumerate()
+            .map(|(i, &sample)| {
+                let gain = if i < length {
+                    1.0
+                } else {
+                    1.0 - (i - length) as f32 / fade as f32
+                };
+                ((sample * gain).clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+            })
+            .collect())
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nsf::test::create_nsf;
+
+    // INITは$8000、PLAYは$8003
+    // INIT: 曲番号を$00に置き、矩形波を鳴らす PLAY: $01を数える
+    fn create_player() -> NsfPlayer {
+        let code = [
+            0x4C, 0x06, 0x80, // JMP $8006
+            0xE6, 0x01, // INC $01
+            0x60, // RTS
+            0x85, 0x00, // STA $00
+            0xA9, 0x01, 0x8D, 0x15, 0x40, // LDA #$01, STA $4015
+            0xA9, 0xBF, 0x8D, 0x00, 0x40, // LDA #$BF, STA $4000
+            0xA9, 0xFD, 0x8D, 0x02, 0x40, // LDA #$FD, STA $4002
+            0xA9, 0x08, 0x8D, 0x03, 0x40, // LDA #$08, STA $4003
+            0x60, // RTS
+        ];
+        let nsf = Nsf::parse(&create_nsf(0x8000, [0; 8], 0, &code)).unwrap();
+        NsfPlayer::new(nsf, 44100)
+    }
+
+    #[test]
+    fn test_init_and_play() {
+        let mut player = create_player();
+        player.start(2).unwrap();
+        assert_eq!(player.cpu.bus.peek(0x0000), 2);
+        assert_eq!(player.cpu.bus.peek(0x0001), 0);
+
+        // NTSCでは1秒に約60回PLAYを呼ぶ
+        let samples = player.render(44100).unwrap();
+        assert_eq!(samples.len(), 44100);
+        let calls = player.cpu.bus.peek(0x0001);
+        assert!((59..=61).contains(&calls), "{}", calls);
+
+        // 矩形波が鳴っている
+        let max = samples.iter().cloned().fold(f32::MIN, f32::max);
+        let min = samples.iter().cloned().fold(f32::MAX, f32::min);
+        assert!(max - min > 0.1);
+
+        assert!(matches!(player.start(3), Err(PlayError::NoSuchSong(3))));
+    }
+
+    #[test]
+    fn test_render_track_fades_out() {
+        let mut player = create_player();
+        player.nsf.tracks[0].length_ms = Some(100);
+        player.nsf.tracks[0].fade_ms = Some(100);
+        let samples = player.render_track(0).unwrap();
+        assert_eq!(samples.len(), 8820);
+        let peak = |range: &[i16]| range.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!(peak(&samples[4000..4410]) > 1000);
+        assert!(peak(&samples[8700..]) < peak(&samples[4000..4410]) / 10);
+    }
+
+    #[test]
+    fn test_init_without_return() {
+        // INITが$8000で止まり続ける
+        let nsf = Nsf::parse(&create_nsf(0x8000, [0; 8], 0, &[0x4C, 0x00, 0x80])).unwrap();
+        let mut player = NsfPlayer::new(nsf, 44100);
+        assert!(matches!(
+            player.start(0),
+            Err(PlayError::NoReturn {
+                routine: "INIT",
+                addr: 0x8000
+            })
+        ));
+    }
+}
@@ -0,0 +1,502 @@
+use crate::hash::crc32;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// 読み込み時にROMへ当てるパッチ (IPS/UPS/BPS)
+// 元のファイルは書き換えず、メモリ上のコピーに当てる
+
+// UPS/BPSに書かれた出力の大きさの上限 大きなマルチカートでも収まる
+// 壊れたパッチで巨大なメモリを確保しないようにする
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ips,
+    Ups,
+    Bps,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchError {
+    // 先頭の識別子がどの形式でもない
+    UnknownFormat,
+    // 途中でパッチが終わっている
+    Truncated,
+    // パッチ自身が壊れている
+    Corrupt(&'static str),
+    // 当てる先のROMが違う
+    SourceMismatch { expected: u32, actual: u32 },
+    // 当てた結果がパッチに書かれたものと違う
+    TargetMismatch { expected: u32, actual: u32 },
+    // パッチファイルのCRC32が合わない
+    PatchMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an IPS, UPS or BPS patch"),
+            PatchError::Truncated => write!(f, "patch is truncated"),
+            PatchError::Corrupt(reason) => write!(f, "patch is corrupt: {}", reason),
+            PatchError::SourceMismatch { expected, actual } => write!(
+                f,
+                "patch is for a different ROM: expected CRC32 {:08x}, found {:08x}",
+                expected, actual
+            ),
+            PatchError::TargetMismatch { expected, actual } => write!(
+                f,
+                "patched ROM has CRC32 {:08x}, expected {:08x}",
+                actual, expected
+            ),
+            PatchError::PatchMismatch { expected, actual } => write!(
+                f,
+                "patch checksum mismatch: expected {:08x}, found {:08x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl Format {
+    pub fn detect(patch: &[u8]) -> Option<Format> {
+        if patch.starts_with(b"PATCH") {
+            Some(Format::Ips)
+        } else if patch.starts_with(b"UPS1") {
+            Some(Format::Ups)
+        } else if patch.starts_with(b"BPS1") {
+            Some(Format::Bps)
+        } else {
+            None
+        }
+    }
+}
+
+// ROMの隣にある同じ名前のパッチ ips/ups/bpsの順に探す
+pub fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    ["ips", "ups", "bps"]
+        .iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+// パッチの形式を判別して当てる
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match Format::detect(patch) {
+        Some(Format::Ips) => apply_ips(source, patch),
+        Some(Format::Ups) => apply_ups(source, patch),
+        Some(Format::Bps) => apply_bps(source, patch),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+// パッチを先頭から読む
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Reader { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.pos.checked_add(len).ok_or(PatchError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(PatchError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    // ビッグエンディアンの整数
+    fn uint(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    // UPS/BPSの可変長整数 7bitずつ下位から 最上位bitが1なら最後のbyte
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            value = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|n| n.checked_add(value))
+                .ok_or(PatchError::Corrupt("number too large"))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift
+                .checked_mul(0x80)
+                .ok_or(PatchError::Corrupt("number too large"))?;
+            value = value
+                .checked_add(shift)
+                .ok_or(PatchError::Corrupt("number too large"))?;
+        }
+    }
+}
+
+// IPS: 3byteの位置と2byteの長さの組が"EOF"まで続く 長さ0は同じ値の繰り返し
+// "EOF"の後に3byteあれば、その大きさに切り詰める
+fn apply_ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = source.to_vec();
+    let mut reader = Reader::new(patch, 5);
+    loop {
+        if reader.bytes(3)? == b"EOF" {
+            break;
+        }
+        reader.pos -= 3;
+        let offset = reader.uint(3)?;
+        let (len, fill) = match reader.uint(2)? {
+            0 => (reader.uint(2)?, Some(reader.byte()?)),
+            len => (len, None),
+        };
+        if target.len() < offset + len {
+            target.resize(offset + len, 0);
+        }
+        match fill {
+            Some(value) => target[offset..offset + len].fill(value),
+            None => target[offset..offset + len].copy_from_slice(reader.bytes(len)?),
+        }
+    }
+    if let Ok(len) = reader.uint(3) {
+        target.truncate(len);
+    }
+    Ok(target)
+}
+
+// UPS/BPSの末尾12byteのCRC32を確かめる
+fn check_footer(source: &[u8], patch: &[u8]) -> Result<(u32, usize), PatchError> {
+    if patch.len() < 4 + 12 {
+        return Err(PatchError::Truncated);
+    }
+    let footer = patch.len() - 12;
+    let word = |at: usize| u32::from_le_bytes(patch[at..at + 4].try_into().unwrap());
+    let actual = crc32(&patch[..patch.len() - 4]);
+    if word(footer + 8) != actual {
+        return Err(PatchError::PatchMismatch {
+            expected: word(footer + 8),
+            actual,
+        });
+    }
+    let actual = crc32(source);
+    if word(footer) != actual {
+        return Err(PatchError::SourceMismatch {
+            expected: word(footer),
+            actual,
+        });
+    }
+    Ok((word(footer + 4), footer))
+}
+
+// パッチに書かれた出力の大きさ
+fn target_size(reader: &mut Reader) -> Result<usize, PatchError> {
+    let size = reader.number()?;
+    if size > MAX_TARGET_SIZE {
+        return Err(PatchError::Corrupt("target size too large"));
+    }
+    Ok(size)
+}
+
+fn check_target(target: Vec<u8>, expected: u32) -> Result<Vec<u8>, PatchError> {
+    let actual = crc32(&target);
+    if actual != expected {
+        return Err(PatchError::TargetMismatch { expected, actual });
+    }
+    Ok(target)
+}
+
+// UPS: 元との差分をXORで持つ 位置は前の差分の終わりからの相対
+fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (target_crc, footer) = check_footer(source, patch)?;
+    let mut reader = Reader::new(&patch[..footer], 4);
+    let source_size = reader.number()?;
+    let target_size = target_size(&mut reader)?;
+    if source_size != source.len() {
+        return Err(PatchError::Corrupt("source size does not match"));
+    }
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+    let mut pos = 0usize;
+    while reader.pos < footer {
+        pos = pos
+            .checked_add(reader.number()?)
+            .ok_or(PatchError::Corrupt("offset too large"))?;
+        loop {
+            let byte = reader.byte()?;
+            if let Some(value) = target.get_mut(pos) {
+                *value ^= byte;
+            }
+            pos = pos
+                .checked_add(1)
+                .ok_or(PatchError::Corrupt("offset too large"))?;
+            if byte == 0 {
+                break;
+            }
+        }
+    }
+    check_target(target, target_crc)
+}
+
+// BPS: 元やパッチ、出力済みの部分からコピーする命令の列
+fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (target_crc, footer) = check_footer(source, patch)?;
+    let mut reader = Reader::new(&patch[..footer], 4);
+    let source_size = reader.number()?;
+    let target_size = target_size(&mut reader)?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    if source_size != source.len() {
+        return Err(PatchError::Corrupt("source size does not match"));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+    // 相対位置は符号を最下位bitに持つ
+    let relative = |base: usize, data: usize| {
+        let distance = data >> 1;
+        if data & 1 != 0 {
+            base.checked_sub(distance)
+        } else {
+            base.checked_add(distance)
+        }
+        .ok_or(PatchError::Corrupt("offset out of range"))
+    };
+    while reader.pos < footer {
+        let data = reader.number()?;
+        let len = (data >> 2) + 1;
+        if len > target_size - target.len() {
+            return Err(PatchError::Corrupt("output larger than target size"));
+        }
+        match data & 3 {
+            // 元の同じ位置から
+            0 => {
+                let start = target.len();
+                let bytes = source
+                    .get(start..start + len)
+                    .ok_or(PatchError::Corrupt("source read out of range"))?;
+                target.extend_from_slice(bytes);
+            }
+            // パッチに書かれたバイト列
+            1 => target.extend_from_slice(reader.bytes(len)?),
+            // 元の任意の位置から
+            2 => {
+                source_offset = relative(source_offset, reader.number()?)?;
+                let end = source_offset
+                    .checked_add(len)
+                    .ok_or(PatchError::Corrupt("source copy out of range"))?;
+                let bytes = source
+                    .get(source_offset..end)
+                    .ok_or(PatchError::Corrupt("source copy out of range"))?;
+                target.extend_from_slice(bytes);
+                source_offset = end;
+            }
+            // 出力済みの部分から 重なってもよいので1byteずつ
+            _ => {
+                target_offset = relative(target_offset, reader.number()?)?;
+                for _ in 0..len {
+                    let byte = *target
+                        .get(target_offset)
+                        .ok_or(PatchError::Corrupt("target copy out of range"))?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if target.len() != target_size {
+        return Err(PatchError::Corrupt("output smaller than target size"));
+    }
+    check_target(target, target_crc)
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn number(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(0x80 | byte);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn footer(source: &[u8], target: &[u8], mut patch: Vec<u8>) -> Vec<u8> {
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_number() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1 << 20] {
+            let mut encoded = Vec::new();
+            number(value, &mut encoded);
+            assert_eq!(Reader::new(&encoded, 0).number(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_ips() {
+        let source = vec![0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // 2番地に2byte
+        patch.extend([0, 0, 2, 0, 2, 0xAA, 0xBB]);
+        // 5番地から0x11を3回
+        patch.extend([0, 0, 5, 0, 0, 0, 3, 0x11]);
+        // 末尾より先も書ける
+        patch.extend([0, 0, 9, 0, 1, 0x22]);
+        patch.extend(b"EOF");
+        let target = apply(&source, &patch).unwrap();
+        assert_eq!(target, vec![0, 0, 0xAA, 0xBB, 0, 0x11, 0x11, 0x11, 0, 0x22]);
+
+        // 切り詰め
+        patch.extend([0, 0, 4]);
+        assert_eq!(apply(&source, &patch).unwrap().len(), 4);
+
+        assert_eq!(apply(&source, b"PATCH\0\0"), Err(PatchError::Truncated));
+        assert_eq!(apply(&source, b"XXXXX"), Err(PatchError::UnknownFormat));
+    }
+
+    #[test]
+    fn test_ups() {
+        let source = b"Hello, World".to_vec();
+        let target = b"Hello, NES!!!".to_vec();
+        let mut patch = b"UPS1".to_vec();
+        number(source.len(), &mut patch);
+        number(target.len(), &mut patch);
+        // 7番地から差分
+        number(7, &mut patch);
+        for (i, byte) in target.iter().enumerate().skip(7) {
+            patch.push(byte ^ source.get(i).copied().unwrap_or(0));
+        }
+        patch.push(0);
+        let patch = footer(&source, &target, patch);
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+
+        // 違うROMには当てない
+        assert!(matches!(
+            apply(b"Hello, Earth", &patch),
+            Err(PatchError::SourceMismatch { .. })
+        ));
+        // パッチが壊れている
+        let mut broken = patch.clone();
+        broken[8] ^= 1;
+        assert!(matches!(
+            apply(&source, &broken),
+            Err(PatchError::PatchMismatch { .. })
+        ));
+    }
+
+    // 大きすぎる数は確保や計算の前にエラーにする
+    #[test]
+    fn test_oversized_numbers() {
+        let source = b"ABCDEFGH".to_vec();
+        let huge = usize::MAX >> 1;
+
+        let mut patch = b"UPS1".to_vec();
+        number(source.len(), &mut patch);
+        number(huge, &mut patch);
+        let patch = footer(&source, &source, patch);
+        assert_eq!(
+            apply(&source, &patch),
+            Err(PatchError::Corrupt("target size too large"))
+        );
+
+        // UPSの位置が桁あふれする
+        let mut patch = b"UPS1".to_vec();
+        number(source.len(), &mut patch);
+        number(source.len(), &mut patch);
+        number(usize::MAX, &mut patch);
+        patch.extend([1, 0]);
+        let patch = footer(&source, &source, patch);
+        assert_eq!(
+            apply(&source, &patch),
+            Err(PatchError::Corrupt("offset too large"))
+        );
+
+        let mut patch = b"BPS1".to_vec();
+        number(source.len(), &mut patch);
+        number(MAX_TARGET_SIZE + 1, &mut patch);
+        number(0, &mut patch);
+        let patch = footer(&source, &source, patch);
+        assert_eq!(
+            apply(&source, &patch),
+            Err(PatchError::Corrupt("target size too large"))
+        );
+
+        // メタデータの大きさ
+        let mut patch = b"BPS1".to_vec();
+        number(source.len(), &mut patch);
+        number(source.len(), &mut patch);
+        number(usize::MAX, &mut patch);
+        let patch = footer(&source, &source, patch);
+        assert_eq!(apply(&source, &patch), Err(PatchError::Truncated));
+
+        // SourceCopyの位置と長さの和が桁あふれする
+        let mut patch = b"BPS1".to_vec();
+        number(source.len(), &mut patch);
+        number(source.len(), &mut patch);
+        number(0, &mut patch);
+        number((1 << 2) | 2, &mut patch);
+        number((usize::MAX >> 1) << 1, &mut patch);
+        let patch = footer(&source, &source, patch);
+        assert_eq!(
+            apply(&source, &patch),
+            Err(PatchError::Corrupt("source copy out of range"))
+        );
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = b"ABCDEFGH".to_vec();
+        let target = b"ABCDxyxyxyGHAB".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        number(source.len(), &mut patch);
+        number(target.len(), &mut patch);
+        number(0, &mut patch);
+        // SourceRead 4byte
+        number(3 << 2, &mut patch);
+        // TargetRead "xy"
+        number((1 << 2) | 1, &mut patch);
+        patch.extend(b"xy");
+        // TargetCopy 4byte 位置4から
+        number((3 << 2) | 3, &mut patch);
+        number(4 << 1, &mut patch);
+        // SourceCopy 2byte 位置6から
+        number((1 << 2) | 2, &mut patch);
+        number(6 << 1, &mut patch);
+        // SourceCopy 2byte 位置0から (8から-8)
+        number((1 << 2) | 2, &mut patch);
+        number((8 << 1) | 1, &mut patch);
+        let patch = footer(&source, &target, patch);
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+
+        // 結果のCRC32が違う
+        let mut wrong = patch[..patch.len() - 12].to_vec();
+        wrong.extend(crc32(&source).to_le_bytes());
+        wrong.extend(0u32.to_le_bytes());
+        wrong.extend(crc32(&wrong).to_le_bytes());
+        assert!(matches!(
+            apply(&source, &wrong),
+            Err(PatchError::TargetMismatch { .. })
+        ));
+    }
+}
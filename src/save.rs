@@ -0,0 +1,138 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// 電池バックアップされたメモリを書き出す間隔 (NTSCのCPUで約5秒)
+pub const FLUSH_INTERVAL_CYCLES: u64 = 1_789_773 * 5;
+
+// ROMと同じ場所・同じ名前の.savファイル
+pub fn save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+// 一時ファイルに書いてから置き換える
+// 書き込み中に落ちても前の.savか新しい.savのどちらかが残る
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let result = fs::File::create(&temp).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(err) = result.and_then(|_| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+    Ok(())
+}
+
+// カートリッジに結びつけた.savファイル
+// 最後に書いた内容を覚えておき、変化があった時だけ書き出す
+pub struct SaveFile {
+    path: PathBuf,
+    written: Vec<u8>,
+    cycles: u64,
+}
+
+impl SaveFile {
+    pub fn new(path: PathBuf) -> Self {
+        SaveFile {
+            path,
+            written: Vec::new(),
+            cycles: 0,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // ファイルがあればdataに読み込む 大きさが違う時は先頭から重なる分だけ使う
+    pub fn load(&mut self, data: &mut [u8]) -> io::Result<bool> {
+        let saved = match fs::read(&self.path) {
+            Ok(saved) => saved,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.written = data.to_vec();
+                return Ok(false);
+            }
+            Err(err) => return Err(err),
+        };
+        let len = saved.len().min(data.len());
+        data[..len].copy_from_slice(&saved[..len]);
+        self.written = data.to_vec();
+        Ok(true)
+    }
+
+    // 前回から変わっていれば書き出す 書いたらtrue
+    pub fn flush(&mut self, data: &[u8]) -> io::Result<bool> {
+        self.cycles = 0;
+        if self.written == data {
+            return Ok(false);
+        }
+        write_atomic(&self.path, data)?;
+        self.written = data.to_vec();
+        Ok(true)
+    }
+
+    // CPUの1サイクルごとに呼ぶ 書き出す時期になったらtrue
+    pub fn tick(&mut self) -> bool {
+        self.cycles += 1;
+        self.cycles >= FLUSH_INTERVAL_CYCLES
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("nes-save-{}-{}.sav", process::id(), name))
+    }
+
+    #[test]
+    fn test_save_path() {
+        assert_eq!(
+            save_path(Path::new("roms/zelda.nes")),
+            PathBuf::from("roms/zelda.sav")
+        );
+    }
+
+    #[test]
+    fn test_load_and_flush() {
+        let path = temp_path("flush");
+        let _ = fs::remove_file(&path);
+        let mut save = SaveFile::new(path.clone());
+        let mut data = vec![0; 4];
+        assert!(!save.load(&mut data).unwrap());
+
+        // 変化が無ければ書かない
+        assert!(!save.flush(&data).unwrap());
+        assert!(!path.exists());
+
+        data[1] = 0x42;
+        assert!(save.flush(&data).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), vec![0, 0x42, 0, 0]);
+
+        // 別の起動で読み戻す 大きさが違えば重なる分だけ
+        let mut loaded = vec![0xFF; 2];
+        assert!(SaveFile::new(path.clone()).load(&mut loaded).unwrap());
+        assert_eq!(loaded, vec![0, 0x42]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_atomic_leaves_no_temp_file() {
+        let path = temp_path("atomic");
+        fs::write(&path, [1, 2, 3]).unwrap();
+        write_atomic(&path, &[4, 5]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![4, 5]);
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        assert!(!PathBuf::from(temp).exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+use crate::cartridge::{ConsoleType, Mirroring, Rom, RomError, Timing};
+
+// UNIF形式 (.unf)
+// 32byteのヘッダの後に「4文字のID・4byteの長さ・中身」のチャンクが続く
+// マッパー番号の代わりに基板名(MAPR)を持つので、iNESのマッパー番号に読み替える
+pub const UNIF_TAG: &[u8; 4] = b"UNIF";
+const HEADER_SIZE: usize = 32;
+
+// 基板名とiNESのマッパー番号・サブマッパー・PRG-RAMの大きさ
+const BOARDS: &[(&str, u16, u8, usize)] = &[
+    ("NROM", 0, 0, 0),
+    ("NROM-128", 0, 0, 0),
+    ("NROM-256", 0, 0, 0),
+    ("SAROM", 1, 0, 0x2000),
+    ("SBROM", 1, 0, 0),
+    ("SCROM", 1, 0, 0),
+    ("SEROM", 1, 5, 0),
+    ("SGROM", 1, 0, 0),
+    ("SKROM", 1, 0, 0x2000),
+    ("SLROM", 1, 0, 0),
+    ("SL1ROM", 1, 0, 0),
+    ("SNROM", 1, 0, 0x2000),
+    ("SOROM", 1, 2, 0x4000),
+    ("SUROM", 1, 1, 0x2000),
+    ("SXROM", 1, 3, 0x8000),
+    ("UNROM", 2, 2, 0),
+    ("UOROM", 2, 2, 0),
+    ("CNROM", 3, 2, 0),
+    ("TBROM", 4, 0, 0),
+    ("TEROM", 4, 0, 0),
+    ("TFROM", 4, 0, 0),
+    ("TGROM", 4, 0, 0),
+    ("TKROM", 4, 0, 0x2000),
+    ("TLROM", 4, 0, 0),
+    ("TNROM", 4, 0, 0x2000),
+    ("TR1ROM", 4, 0, 0),
+    ("TSROM", 4, 0, 0x2000),
+    ("TVROM", 4, 0, 0),
+    ("EKROM", 5, 0, 0x2000),
+    ("ELROM", 5, 0, 0),
+    ("ETROM", 5, 0, 0x4000),
+    ("EWROM", 5, 0, 0x8000),
+    ("AMROM", 7, 2, 0),
+    ("ANROM", 7, 1, 0),
+    ("AOROM", 7, 0, 0),
+    ("PNROM", 9, 0, 0),
+    ("PEEOROM", 9, 0, 0),
+    ("FJROM", 10, 0, 0x2000),
+    ("FKROM", 10, 0, 0x2000),
+    ("BNROM", 34, 2, 0),
+    ("GNROM", 66, 0, 0),
+    ("MHROM", 66, 0, 0),
+    ("BTR", 69, 0, 0x2000),
+    ("JLROM", 69, 0, 0),
+    ("JSROM", 69, 0, 0x2000),
+    // MMC6 内蔵の1KBのRAMを持つ MMC3として動かす
+    ("HKROM", 4, 1, 0x400),
+    // 任天堂以外の基板
+    ("COLORDREAMS-74*377", 11, 0, 0),
+    ("ACTION53", 28, 0, 0),
+    ("UNROM-512-8", 30, 0, 0),
+    ("UNROM-512-16", 30, 0, 0),
+    ("UNROM-512-32", 30, 0, 0),
+    ("BF9093", 71, 0, 0),
+    // Fire Hawkの基板 ネームテーブルを1画面で切り替える
+    ("BF9097", 71, 1, 0),
+    ("GTROM", 111, 0, 0),
+    ("CHEAPOCABRA", 111, 0, 0),
+];
+
+// 製造元などを表す接頭辞 基板名を引く時は外す
+const PREFIXES: &[&str] = &["NES-", "HVC-", "UNL-", "BTL-", "BMC-", "CAMERICA-"];
+
+// 基板名からマッパー番号などを引く
+pub fn board(name: &str) -> Option<(u16, u8, usize)> {
+    let name = PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name);
+    BOARDS
+        .iter()
+        .find(|(board, ..)| board.eq_ignore_ascii_case(name))
+        .map(|&(_, mapper, submapper, prg_ram)| (mapper, submapper, prg_ram))
+}
+
+pub fn parse(raw: &[u8]) -> Result<Rom, RomError> {
+    if !raw.starts_with(UNIF_TAG) {
+        return Err(RomError::BadMagic);
+    }
+    if raw.len() < HEADER_SIZE {
+        return Err(RomError::Truncated {
+            expected: HEADER_SIZE,
+            actual: raw.len(),
+        });
+    }
+
+    let mut board_name = None;
+    let mut prg_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut mirroring = Mirroring::Horizontal;
+    let mut battery = false;
+    let mut timing = Timing::Ntsc;
+
+    let mut pos = HEADER_SIZE;
+    while pos < raw.len() {
+        if raw.len() < pos + 8 {
+            return Err(RomError::Truncated {
+                expected: pos + 8,
+                actual: raw.len(),
+            });
+        }
+        let id = &raw[pos..pos + 4];
+        let len = u32::from_le_bytes(raw[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let start = pos + 8;
+        let end = start.saturating_add(len);
+        if raw.len() < end {
+            return Err(RomError::Truncated {
+                expected: end,
+                actual: raw.len(),
+            });
+        }
+        let data = &raw[start..end];
+        // PRG0-PRGF/CHR0-CHRFは番号順につなげる
+        let index = (id[3] as char).to_digit(16).map(|n| n as usize);
+        match (&id[..3], index) {
+            (b"PRG", Some(n)) => prg_chunks[n] = Some(data),
+            (b"CHR", Some(n)) => chr_chunks[n] = Some(data),
+            _ => match id {
+                b"MAPR" => {
+                    let name = data.split(|&b| b == 0).next().unwrap_or(&[]);
+                    board_name = Some(String::from_utf8_lossy(name).trim().to_string());
+                }
+                // 0/1は水平/垂直、2/3は1画面、4は4画面、5はマッパーが決める
+                b"MIRR" => {
+                    mirroring = match data.first() {
+                        Some(1) => Mirroring::Vertical,
+                        Some(2) => Mirroring::SingleScreenLower,
+                        Some(3) => Mirroring::SingleScreenUpper,
+                        Some(4) => Mirroring::FourScreen,
+                        _ => Mirroring::Horizontal,
+                    }
+                }
+                b"BATR" => battery = data.first().is_some_and(|&b| b != 0),
+                b"TVCI" => {
+                    timing = match data.first() {
+                        Some(1) => Timing::Pal,
+                        Some(2) => Timing::MultiRegion,
+                        _ => Timing::Ntsc,
+                    }
+                }
+                _ => {}
+            },
+        }
+        pos = end;
+    }
+
+    let board_name = board_name.ok_or(RomError::Unsupported("UNIF file without a board name"))?;
+    let (mapper, submapper, prg_ram) =
+        board(&board_name).ok_or_else(|| RomError::UnsupportedBoard(board_name.clone()))?;
+    let prg_rom: Vec<u8> = prg_chunks
+        .iter()
+        .flatten()
+        .flat_map(|c| c.iter())
+        .copied()
+        .collect();
+    let chr_rom: Vec<u8> = chr_chunks
+        .iter()
+        .flatten()
+        .flat_map(|c| c.iter())
+        .copied()
+        .collect();
+    if prg_rom.is_empty() {
+        return Err(RomError::Unsupported("empty PRG ROM"));
+    }
+    // 電池があればPRG-RAMは最低8KB
+    let prg_ram = if battery {
+        prg_ram.max(0x2000)
+    } else {
+        prg_ram
+    };
+
+    Ok(Rom {
+        chr_ram_size: if chr_rom.is_empty() { 0x2000 } else { 0 },
+        prg_rom,
+        chr_rom,
+        trainer: None,
+        mapper,
+        submapper,
+        screen_mirroring: mirroring,
+        battery,
+        nes2: false,
+        prg_ram_size: if battery { 0 } else { prg_ram },
+        prg_nvram_size: if battery { prg_ram } else { 0 },
+        chr_nvram_size: 0,
+        timing,
+        console_type: ConsoleType::Nes,
+        misc_rom: Vec::new(),
+        expansion_device: 0,
+        disk_sides: Vec::new(),
+    })
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+        out.extend(id);
+        out.extend((data.len() as u32).to_le_bytes());
+        out.extend(data);
+    }
+
+    // テスト用のUNIFファイルを組み立てる
+    fn create_unif(board: &str, prg: &[&[u8]], chr: &[&[u8]]) -> Vec<u8> {
+        let mut raw = UNIF_TAG.to_vec();
+        raw.extend(7u32.to_le_bytes());
+        raw.resize(HEADER_SIZE, 0);
+        let mut name = board.as_bytes().to_vec();
+        name.push(0);
+        chunk(&mut raw, b"MAPR", &name);
+        // 番号順でなくてもよい
+        for (i, data) in prg.iter().enumerate().rev() {
+            chunk(&mut raw, &[b'P', b'R', b'G', b"0123456789ABCDEF"[i]], data);
+        }
+        for (i, data) in chr.iter().enumerate() {
+            chunk(&mut raw, &[b'C', b'H', b'R', b"0123456789ABCDEF"[i]], data);
+        }
+        raw
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut raw = create_unif("NES-SKROM", &[&[1; 0x4000], &[2; 0x4000]], &[&[3; 0x2000]]);
+        chunk(&mut raw, b"MIRR", &[1]);
+        chunk(&mut raw, b"BATR", &[1]);
+        chunk(&mut raw, b"TVCI", &[1]);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!((rom.prg_rom[0], rom.prg_rom[0x4000]), (1, 2));
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+        assert_eq!(rom.timing, Timing::Pal);
+    }
+
+    #[test]
+    fn test_board_names() {
+        assert_eq!(board("NES-TLROM"), Some((4, 0, 0)));
+        assert_eq!(board("HVC-SXROM"), Some((1, 3, 0x8000)));
+        assert_eq!(board("unrom"), Some((2, 2, 0)));
+        assert_eq!(board("BMC-Unknown"), None);
+
+        // CHR-ROMが無ければCHR-RAM
+        let rom = parse(&create_unif("UNL-UNROM", &[&[0; 0x8000]], &[])).unwrap();
+        assert_eq!(rom.chr_ram_size, 0x2000);
+
+        let raw = create_unif("BMC-Unknown", &[&[0; 0x4000]], &[]);
+        assert_eq!(
+            Rom::new(&raw).unwrap_err(),
+            RomError::UnsupportedBoard("BMC-Unknown".to_string())
+        );
+    }
+
+    // 任天堂以外の基板も既存のマッパーで読める
+    #[test]
+    fn test_unlicensed_boards() {
+        assert_eq!(board("NES-HKROM"), Some((4, 1, 0x400)));
+        assert_eq!(board("CAMERICA-BF9097"), Some((71, 1, 0)));
+        assert_eq!(board("COLORDREAMS-74*377"), Some((11, 0, 0)));
+        assert_eq!(board("UNL-UNROM-512-32"), Some((30, 0, 0)));
+
+        let raw = create_unif("CAMERICA-BF9093", &[&[1; 0x4000], &[2; 0x4000]], &[]);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 71);
+        // 最後のバンクが$C000に固定される
+        let mut cartridge = crate::cartridge::Cartridge::new(rom).unwrap();
+        assert_eq!(cartridge.read(0xC000), 2);
+        assert_eq!(cartridge.read(0x8000), 1);
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let mut raw = create_unif("NROM", &[&[0; 0x4000]], &[]);
+        raw.truncate(raw.len() - 1);
+        assert!(matches!(parse(&raw), Err(RomError::Truncated { .. })));
+    }
+}
@@ -0,0 +1,457 @@
+use crate::bus::Device;
+use crate::cartridge::{ConsoleType, Rom};
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+// Vs. System (業務用)
+// $4016/$4017の上位bitにDIPスイッチ・コイン・サービスボタンがつながっている
+// iNES 1.0のヘッダはPPUの型などを持たないので、正しく読むにはNES 2.0のヘッダが要る
+
+// NES 2.0のbyte 13下位4bit 基板に載っているPPUの型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuType {
+    // RP2C03B/RP2C03G/RC2C03B/RC2C03C 通常のNESと同じパレットの並び
+    Rp2c03,
+    // RP2C04-0001から0004 パレットの並びが型ごとに入れ替わっている
+    Rp2c04(u8),
+    // RC2C05-01から05 $2000と$2001が入れ替わり、$2002の下位5bitが型の番号になる
+    Rc2c05(u8),
+    Unknown(u8),
+}
+
+impl PpuType {
+    pub fn from_header(value: u8) -> PpuType {
+        match value {
+            0 | 1 | 6 | 7 => PpuType::Rp2c03,
+            2..=5 => PpuType::Rp2c04(value - 1),
+            8..=0x0C => PpuType::Rc2c05(value - 7),
+            _ => PpuType::Unknown(value),
+        }
+    }
+
+    // パレットが入れ替わっているRP2C04の型番 変換表はPaletteMapで読み込む
+    pub fn scrambled_palette(&self) -> Option<u8> {
+        match self {
+            PpuType::Rp2c04(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // $2000と$2001が入れ替わっている
+    pub fn swaps_control_registers(&self) -> bool {
+        matches!(self, PpuType::Rc2c05(_))
+    }
+
+    // $2002の下位5bitに読める値 RC2C05-05の値は分かっていない
+    pub fn status_id(&self) -> Option<u8> {
+        match self {
+            PpuType::Rc2c05(1) | PpuType::Rc2c05(4) => Some(0x1B),
+            PpuType::Rc2c05(2) => Some(0x3D),
+            PpuType::Rc2c05(3) => Some(0x1C),
+            _ => None,
+        }
+    }
+}
+
+// RP2C04のパレット番号を通常のRP2C03の番号に直す表
+// 4種類の表はROMに入っていないので、64byteのファイルから読む
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteMap([u8; 64]);
+
+impl PaletteMap {
+    // 各byteがRP2C04の番号に対応するRP2C03の番号 0x40以上は不正
+    pub fn new(raw: &[u8]) -> Option<PaletteMap> {
+        let table: [u8; 64] = raw.try_into().ok()?;
+        if table.iter().any(|&index| index >= 0x40) {
+            return None;
+        }
+        Some(PaletteMap(table))
+    }
+
+    // パレットRAMの値は下位6bitだけ使う
+    pub fn map(&self, index: u8) -> u8 {
+        self.0[(index & 0x3F) as usize]
+    }
+}
+
+// NES 2.0のbyte 13上位4bit 基板の種類とコピープロテクト
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hardware {
+    Unisystem,
+    // プロテクトのチップ付き
+    RbiBaseball,
+    TkoBoxing,
+    SuperXevious,
+    // 日本版Vs. Ice Climberは入力の並びが違う
+    IceClimberJp,
+    DualSystem,
+    // デュアル基板でプロテクト付き
+    RaidOnBungelingBay,
+    Unknown(u8),
+}
+
+impl Hardware {
+    pub fn from_header(value: u8) -> Hardware {
+        match value {
+            0 => Hardware::Unisystem,
+            1 => Hardware::RbiBaseball,
+            2 => Hardware::TkoBoxing,
+            3 => Hardware::SuperXevious,
+            4 => Hardware::IceClimberJp,
+            5 => Hardware::DualSystem,
+            6 => Hardware::RaidOnBungelingBay,
+            _ => Hardware::Unknown(value),
+        }
+    }
+
+    pub fn has_protection(&self) -> bool {
+        matches!(
+            self,
+            Hardware::RbiBaseball
+                | Hardware::TkoBoxing
+                | Hardware::SuperXevious
+                | Hardware::RaidOnBungelingBay
+        )
+    }
+
+    pub fn is_dual(&self) -> bool {
+        matches!(self, Hardware::DualSystem | Hardware::RaidOnBungelingBay)
+    }
+}
+
+// Vs. SystemのROMならPPUの型と基板の種類を返す
+pub fn info(rom: &Rom) -> Option<(PpuType, Hardware)> {
+    match rom.console_type {
+        ConsoleType::VsSystem { ppu, hardware } => {
+            Some((PpuType::from_header(ppu), Hardware::from_header(hardware)))
+        }
+        _ => None,
+    }
+}
+
+// コインを入れたことにしておくサイクル数 短いと取りこぼすゲームがあるので4フレームほど押したままにする
+pub const COIN_CYCLES: u32 = 29780 * 4;
+
+// 筐体の操作パネル ホストが書き、$4016/$4017のデバイスが読む
+#[derive(Default)]
+struct Panel {
+    dip_switches: u8,
+    service: bool,
+    coins: [u32; 2],
+    // ポートごとのコントローラーのボタン bit0から順にA・B・Select・Start・上・下・左・右
+    buttons: [u8; 2],
+}
+
+// ホストからパネルを操作するための取っ手 VsSystemをバスに登録した後も使える
+#[derive(Clone)]
+pub struct VsPanel(Rc<RefCell<Panel>>);
+
+impl VsPanel {
+    // slotは0か1
+    pub fn insert_coin(&self, slot: usize) {
+        self.0.borrow_mut().coins[slot] = COIN_CYCLES;
+    }
+
+    pub fn set_service(&self, pressed: bool) {
+        self.0.borrow_mut().service = pressed;
+    }
+
+    // bit0がスイッチ1
+    pub fn set_dip_switches(&self, dip_switches: u8) {
+        self.0.borrow_mut().dip_switches = dip_switches;
+    }
+
+    pub fn dip_switches(&self) -> u8 {
+        self.0.borrow().dip_switches
+    }
+
+    // portは$4016なら0、$4017なら1 どちらが1P側かはゲームによる
+    pub fn set_buttons(&self, port: usize, buttons: u8) {
+        self.0.borrow_mut().buttons[port] = buttons;
+    }
+}
+
+// $4016-$4017に登録するデバイス
+// $4016 読み: bit0 コントローラー, bit2 サービス, bit3-4 DIP 1-2, bit5-6 コイン1-2
+// $4017 読み: bit0 コントローラー, bit2-7 DIP 3-8
+pub struct VsSystem {
+    panel: Rc<RefCell<Panel>>,
+    strobe: bool,
+    shift: [u8; 2],
+}
+
+impl Default for VsSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VsSystem {
+    pub fn new() -> Self {
+        VsSystem {
+            panel: Rc::new(RefCell::new(Panel::default())),
+            strobe: false,
+            shift: [0; 2],
+        }
+    }
+
+    pub fn panel(&self) -> VsPanel {
+        VsPanel(self.panel.clone())
+    }
+
+    fn latch(&mut self) {
+        self.shift = self.panel.borrow().buttons;
+    }
+}
+
+impl Device for VsSystem {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.peek(addr);
+        if self.strobe {
+            self.latch();
+        } else {
+            // 8bit読み終えた後は1が続く
+            let port = (addr & 1) as usize;
+            self.shift[port] = self.shift[port] >> 1 | 0x80;
+        }
+        value
+    }
+
+    // bit0 ストローブ 他のbitはカートリッジ側が見る
+    fn write(&mut self, addr: u16, data: u8) {
+        if addr == 0x4016 {
+            self.strobe = data & 0x01 != 0;
+            if self.strobe {
+                self.latch();
+            }
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        let panel = self.panel.borrow();
+        let port = (addr & 1) as usize;
+        let button = if self.strobe {
+            panel.buttons[port] & 0x01
+        } else {
+            self.shift[port] & 0x01
+        };
+        let dip = panel.dip_switches;
+        if port == 0 {
+            button
+                | (panel.service as u8) << 2
+                | (dip & 0x03) << 3
+                | ((panel.coins[0] > 0) as u8) << 5
+                | ((panel.coins[1] > 0) as u8) << 6
+        } else {
+            button | (dip & 0xFC)
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        let mut panel = self.panel.borrow_mut();
+        for coin in panel.coins.iter_mut() {
+            *coin = coin.saturating_sub(cycles as u32);
+        }
+    }
+}
+
+// RBI Baseball・TKO Boxingのプロテクト $5E00を読むと先頭に戻り、$5E01を読むたびに次の値が出る
+const TKO_BOXING_DATA: [u8; 32] = [
+    0xFF, 0xBF, 0xB7, 0x97, 0x97, 0x17, 0x57, 0x4F, 0x6F, 0x6B, 0xEB, 0xA9, 0xB1, 0x90, 0x94, 0x14,
+    0x56, 0x4E, 0x6F, 0x6B, 0xEB, 0xA9, 0xB1, 0x90, 0xD4, 0x5C, 0x3E, 0x26, 0x87, 0x83, 0x13, 0x00,
+];
+const RBI_BASEBALL_DATA: [u8; 32] = [
+    0x00, 0x00, 0x00, 0x00, 0xB4, 0x00, 0x00, 0x00, 0x00, 0x6F, 0x00, 0x00, 0x00, 0x00, 0x94, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// 基板に載っているプロテクトのチップ 決まった番地を読むと決まった値が返る
+pub struct Protection {
+    hardware: Hardware,
+    index: usize,
+    // Super Xeviousは$5567を読むたびに返す値の組が入れ替わる
+    xevious_select: bool,
+}
+
+impl Protection {
+    // 対応しているチップなら、バスに登録する範囲と一緒に返す
+    // Raid on Bungeling Bayはデュアル基板なので扱わない
+    pub fn new(hardware: Hardware) -> Option<(RangeInclusive<u16>, Protection)> {
+        let range = match hardware {
+            Hardware::RbiBaseball | Hardware::TkoBoxing => 0x5E00..=0x5E01,
+            Hardware::SuperXevious => 0x5400..=0x57FF,
+            _ => return None,
+        };
+        let protection = Protection {
+            hardware,
+            index: 0,
+            xevious_select: false,
+        };
+        Some((range, protection))
+    }
+
+    fn sequence(&self) -> &'static [u8; 32] {
+        match self.hardware {
+            Hardware::TkoBoxing => &TKO_BOXING_DATA,
+            _ => &RBI_BASEBALL_DATA,
+        }
+    }
+}
+
+impl Device for Protection {
+    fn read(&mut self, addr: u16) -> u8 {
+        match (self.hardware, addr) {
+            (Hardware::SuperXevious, 0x5567) => {
+                self.xevious_select = !self.xevious_select;
+                if self.xevious_select { 0x37 } else { 0x3E }
+            }
+            (Hardware::SuperXevious, _) => self.peek(addr),
+            (_, 0x5E00) => {
+                self.index = 0;
+                0
+            }
+            _ => {
+                let data = self.sequence()[self.index];
+                self.index = (self.index + 1) % 32;
+                data
+            }
+        }
+    }
+
+    // チップは書き込みを受けない
+    fn write(&mut self, _addr: u16, _data: u8) {}
+
+    fn peek(&self, addr: u16) -> u8 {
+        match (self.hardware, addr) {
+            (Hardware::SuperXevious, 0x54FF) => 0x05,
+            (Hardware::SuperXevious, 0x5678) => {
+                if self.xevious_select {
+                    0x00
+                } else {
+                    0x01
+                }
+            }
+            (Hardware::SuperXevious, 0x578F) => {
+                if self.xevious_select {
+                    0xD1
+                } else {
+                    0x89
+                }
+            }
+            (Hardware::SuperXevious, 0x5567) => {
+                if self.xevious_select {
+                    0x3E
+                } else {
+                    0x37
+                }
+            }
+            (Hardware::SuperXevious, _) => 0,
+            (_, 0x5E00) => 0,
+            _ => self.sequence()[self.index],
+        }
+    }
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::create_rom;
+
+    #[test]
+    fn test_info() {
+        let mut raw = create_rom(0, 0x09, 2, 1);
+        // PPUはRC2C05-02、基板はRBI Baseball
+        raw[13] = 0x19;
+        let rom = Rom::new(&raw).unwrap();
+        let (ppu, hardware) = info(&rom).unwrap();
+        assert_eq!(ppu, PpuType::Rc2c05(2));
+        assert!(ppu.swaps_control_registers());
+        assert_eq!(ppu.status_id(), Some(0x3D));
+        assert_eq!(hardware, Hardware::RbiBaseball);
+        assert!(hardware.has_protection());
+
+        assert_eq!(PpuType::from_header(3).scrambled_palette(), Some(2));
+        assert_eq!(PpuType::from_header(7).scrambled_palette(), None);
+        assert!(info(&Rom::new(&create_rom(0, 0, 2, 1)).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_palette_map() {
+        // 逆順に並べた表
+        let raw: Vec<u8> = (0..0x40).rev().collect();
+        let map = PaletteMap::new(&raw).unwrap();
+        assert_eq!(map.map(0x00), 0x3F);
+        assert_eq!(map.map(0x3F), 0x00);
+        assert_eq!(map.map(0x41), 0x3E);
+        assert!(PaletteMap::new(&raw[1..]).is_none());
+        let mut raw = raw;
+        raw[5] = 0x40;
+        assert!(PaletteMap::new(&raw).is_none());
+    }
+
+    #[test]
+    fn test_panel() {
+        let mut vs = VsSystem::new();
+        let panel = vs.panel();
+        panel.set_dip_switches(0b1010_0110);
+        assert_eq!(vs.read(0x4016) & 0x18, 0b10 << 3);
+        assert_eq!(vs.read(0x4017) & 0xFC, 0b1010_0100);
+
+        panel.set_service(true);
+        assert_ne!(vs.read(0x4016) & 0x04, 0);
+
+        // コインはしばらく入ったままになる
+        panel.insert_coin(1);
+        assert_eq!(vs.read(0x4016) & 0x60, 0x40);
+        for _ in 0..COIN_CYCLES / 200 {
+            vs.tick(200);
+        }
+        assert_eq!(vs.read(0x4016) & 0x60, 0x40);
+        vs.tick(200);
+        assert_eq!(vs.read(0x4016) & 0x60, 0);
+    }
+
+    #[test]
+    fn test_controller() {
+        let mut vs = VsSystem::new();
+        vs.panel().set_buttons(1, 0b0000_1001);
+        vs.write(0x4016, 1);
+        vs.write(0x4016, 0);
+        let bits: Vec<u8> = (0..9).map(|_| vs.read(0x4017) & 1).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(vs.read(0x4016) & 1, 0);
+    }
+
+    #[test]
+    fn test_protection() {
+        assert!(Protection::new(Hardware::Unisystem).is_none());
+        assert!(Protection::new(Hardware::RaidOnBungelingBay).is_none());
+
+        let (range, mut tko) = Protection::new(Hardware::TkoBoxing).unwrap();
+        assert_eq!(range, 0x5E00..=0x5E01);
+        assert_eq!(tko.read(0x5E01), 0xFF);
+        assert_eq!(tko.read(0x5E01), 0xBF);
+        assert_eq!(tko.peek(0x5E01), 0xB7);
+        tko.read(0x5E00);
+        assert_eq!(tko.read(0x5E01), 0xFF);
+
+        let (_, mut rbi) = Protection::new(Hardware::RbiBaseball).unwrap();
+        let values: Vec<u8> = (0..15).map(|_| rbi.read(0x5E01)).collect();
+        assert_eq!(values[4], 0xB4);
+        assert_eq!(values[9], 0x6F);
+        assert_eq!(values[14], 0x94);
+
+        let (range, mut xevious) = Protection::new(Hardware::SuperXevious).unwrap();
+        assert!(range.contains(&0x54FF) && range.contains(&0x578F));
+        assert_eq!(xevious.read(0x54FF), 0x05);
+        assert_eq!(xevious.read(0x5678), 0x01);
+        assert_eq!(xevious.read(0x578F), 0x89);
+        assert_eq!(xevious.read(0x5567), 0x37);
+        assert_eq!(xevious.read(0x5678), 0x00);
+        assert_eq!(xevious.read(0x578F), 0xD1);
+        assert_eq!(xevious.read(0x5567), 0x3E);
+        assert_eq!(xevious.read(0x5678), 0x01);
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::{self, Write};
+
+// 16bitモノラルのPCMをWAVファイルとして書き出す
+pub fn write<W: Write>(out: &mut W, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // 1はリニアPCM、チャンネル数1
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * 2).to_le_bytes())?;
+    // 1サンプルのbyte数とbit数
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// テスト
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut out = Vec::new();
+        write(&mut out, 44100, &[1, -1]).unwrap();
+        assert_eq!(out.len(), 44 + 4);
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()), 40);
+        assert_eq!(u32::from_le_bytes(out[24..28].try_into().unwrap()), 44100);
+        assert_eq!(&out[36..40], b"data");
+        assert_eq!(&out[44..], &[0x01, 0x00, 0xFF, 0xFF]);
+    }
+}